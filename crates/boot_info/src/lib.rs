@@ -1,10 +1,9 @@
 #![no_std]
 
-use arrayvec::ArrayVec;
 use boot_logger_info::BootLoggerInfo;
 use kernel_graphics::FrameBufferInfo;
 use kernel_image::KernelImageInfo;
-use memory::{virt::VirtAddr, MemoryMap};
+use memory::{virt::VirtAddr, MemoryMap, MemoryMapEntry};
 use platform_info::PlatformInfo;
 
 pub mod boot_logger_info;
@@ -30,6 +29,7 @@ pub const BOOT_INFO_STRUCT_V1: usize = 1;
 /// ----------------
 /// |     Data     |
 /// |   (initrd)   |
+/// | (memory map) |
 /// ----------------
 /// ```
 ///
@@ -47,14 +47,18 @@ pub struct BootInfoHeader {
     pub frame_buffer_info: FrameBufferInfo,
     /// Information about the current platform
     pub platform_info: PlatformInfo,
-    /// Physical Memory map
-    pub memory_map: MemoryMap,
+    /// The address of the entries of the physical memory map.
+    pub memory_map_addr: VirtAddr,
+    /// The number of entries of the physical memory map.
+    pub memory_map_len: usize,
     /// A fixed size string containing to logging output during the boot loader
     pub boot_logger: BootLoggerInfo,
     /// The address of the initial ramdisk (initrd).
     pub initrd_addr: VirtAddr,
     /// The size in bytes of the initial ramdisk (initrd).
     pub initrd_size: usize,
+    /// The number of cores that enter the kernel.
+    pub num_cores: usize,
 }
 
 impl BootInfoHeader {
@@ -66,10 +70,32 @@ impl BootInfoHeader {
             kernel_image_info: KernelImageInfo::empty(),
             frame_buffer_info: FrameBufferInfo::empty(),
             platform_info: PlatformInfo::None,
-            memory_map: MemoryMap::new(ArrayVec::new_const()),
+            memory_map_addr: VirtAddr::zero(),
+            memory_map_len: 0,
             boot_logger: BootLoggerInfo::new_const(),
             initrd_addr: VirtAddr::zero(),
             initrd_size: 0,
+            num_cores: 0,
         }
     }
+
+    /// The physical memory map stored in the data body.
+    ///
+    /// Note: the memory map is only accessible from the higher half address space
+    /// the kernel is entered with.
+    pub fn memory_map(&self) -> MemoryMap<'_> {
+        if self.memory_map_len == 0 {
+            return MemoryMap::empty();
+        }
+
+        // Safety: the loader guarantees that the memory map is inside the boot info
+        let entries = unsafe {
+            core::slice::from_raw_parts(
+                self.memory_map_addr.as_ptr::<MemoryMapEntry>(),
+                self.memory_map_len,
+            )
+        };
+
+        MemoryMap::new(entries)
+    }
 }
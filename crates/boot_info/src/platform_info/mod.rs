@@ -3,6 +3,7 @@ pub mod uefi;
 
 use pc_x86::PCx86Info;
 use uefi::UefiInfo;
+#[derive(Clone)]
 pub enum PlatformInfo {
     None,
     PCX86(PCx86Info),
@@ -1,9 +1,11 @@
+#[derive(Clone)]
 pub enum Rsdp {
     V1(RsdpV1),
     V2(RsdpV2),
 }
 
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 pub struct RsdpV1 {
     pub signature: [u8; 8],
     pub checksum: u8,
@@ -13,6 +15,7 @@ pub struct RsdpV1 {
 }
 
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 pub struct RsdpV2 {
     pub signature: [u8; 8],
     pub checksum: u8,
@@ -25,6 +28,7 @@ pub struct RsdpV2 {
     pub reserved: [u8; 3],
 }
 
+#[derive(Clone)]
 pub struct PCx86Info {
     pub rsdp: Rsdp,
 }
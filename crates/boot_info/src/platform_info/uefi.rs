@@ -1,5 +1,9 @@
+use memory::phys::PhysAddr;
 use memory::virt::VirtAddr;
 
+#[derive(Clone)]
 pub struct UefiInfo {
     pub system_table_address: VirtAddr,
+    /// The physical address of the RSDP, taken from the configuration table.
+    pub rsdp_address: PhysAddr,
 }
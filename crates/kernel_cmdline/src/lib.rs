@@ -10,6 +10,8 @@ pub struct KernelCommandLine {
     pub use_reloc: Option<bool>,
     pub stack_size: Option<usize>,
     pub initial_heap_size: Option<usize>,
+    pub meminfo: Option<()>,
+    pub dump_mappings: Option<()>,
 }
 
 impl KernelCommandLine {
@@ -30,6 +32,16 @@ impl KernelCommandLine {
         self.welcome.is_some()
     }
 
+    /// Whether the kernel logs a memory usage report at the end of boot.
+    pub fn meminfo(&self) -> bool {
+        self.meminfo.is_some()
+    }
+
+    /// Whether the kernel logs the mappings of its address space at the end of boot.
+    pub fn dump_mappings(&self) -> bool {
+        self.dump_mappings.is_some()
+    }
+
     pub fn use_reloc(&self) -> bool {
         self.use_reloc.unwrap_or(true)
     }
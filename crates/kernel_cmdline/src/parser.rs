@@ -59,6 +59,8 @@ impl<'a> KernelCommandLineParser<'a> {
         let mut use_reloc = None;
         let mut stack_size = None;
         let mut initial_heap_size = None;
+        let mut meminfo = None;
+        let mut dump_mappings = None;
 
         for keyvalue in self.keyvalue_pairs() {
             if keyvalue.key == "welcome" {
@@ -76,6 +78,14 @@ impl<'a> KernelCommandLineParser<'a> {
             if keyvalue.key == "initial_heap_size" {
                 initial_heap_size = keyvalue.get();
             }
+
+            if keyvalue.key == "meminfo" {
+                meminfo = Some(());
+            }
+
+            if keyvalue.key == "dump_mappings" {
+                dump_mappings = Some(());
+            }
         }
 
         let cmd = KernelCommandLine {
@@ -83,6 +93,8 @@ impl<'a> KernelCommandLineParser<'a> {
             use_reloc,
             stack_size,
             initial_heap_size,
+            meminfo,
+            dump_mappings,
         };
 
         cmd.verfy();
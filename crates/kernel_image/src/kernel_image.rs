@@ -190,8 +190,9 @@ impl<'a> ParsedKernelImage<'a> {
     }
 
     /// The total size of the stack area which contains one stack per core.
+    /// Every stack is preceded by a guard page which is left unmapped by the kernel.
     pub fn total_stack_size(&self) -> usize {
-        self.stack_size * self.num_cores
+        (self.stack_size + PAGE_SIZE) * self.num_cores
     }
 
     /// The size of the initially allocated heap for the kernel.
@@ -334,6 +335,7 @@ impl<'a> ParsedKernelImage<'a> {
 
         KernelImageInfo {
             stack,
+            stack_size: self.stack_size,
             rodata,
             code,
             relro,
@@ -1,8 +1,12 @@
 use memory::virt::{Page, VirtAddr, VirtualRange};
+use memory::PAGE_SIZE;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KernelImageInfo {
+    /// The stack area. It contains one stack per core, each preceded by a guard page.
     pub stack: VirtualRange,
+    /// The size of a single stack in bytes, not including its guard page.
+    pub stack_size: usize,
     pub rodata: Option<VirtualRange>,
     pub code: VirtualRange,
     pub relro: Option<VirtualRange>,
@@ -14,6 +18,7 @@ impl KernelImageInfo {
     pub const fn empty() -> Self {
         KernelImageInfo {
             stack: VirtualRange::zero(),
+            stack_size: 0,
             rodata: None,
             code: VirtualRange::zero(),
             relro: None,
@@ -38,9 +43,54 @@ impl KernelImageInfo {
         self.end() - self.start()
     }
 
+    /// The number of stacks in the stack area.
+    pub fn num_stacks(&self) -> usize {
+        self.stack.num_pages() * PAGE_SIZE / self.stack_stride()
+    }
+
+    /// Returns the stack of the core with the given processor id, without its guard page.
+    pub fn core_stack(&self, proc_id: usize) -> Option<VirtualRange> {
+        assert!(
+            self.stack_size % PAGE_SIZE == 0,
+            "the stack size must be page-aligned"
+        );
+
+        let guard = self.stack_guard_page(proc_id)?;
+        Some(VirtualRange::with_size(
+            guard.add(1),
+            self.stack_size / PAGE_SIZE,
+        ))
+    }
+
+    /// Returns the guard page below the stack of the core with the given processor id.
+    pub fn stack_guard_page(&self, proc_id: usize) -> Option<Page> {
+        if proc_id >= self.num_stacks() {
+            return None;
+        }
+
+        Some(
+            self.stack
+                .start()
+                .add(proc_id * self.stack_stride() / PAGE_SIZE),
+        )
+    }
+
+    /// Returns the processor id of the core whose stack guard page contains `addr`.
+    pub fn stack_guard_owner(&self, addr: VirtAddr) -> Option<usize> {
+        let page = Page::new(addr.page_align_down());
+
+        (0..self.num_stacks()).find(|proc_id| self.stack_guard_page(*proc_id) == Some(page))
+    }
+
+    /// The distance between the start of two consecutive stacks.
+    fn stack_stride(&self) -> usize {
+        self.stack_size + PAGE_SIZE
+    }
+
     pub fn to_higher_half(&self) -> Self {
         KernelImageInfo {
             stack: translate_range_to_higher_half(self.stack),
+            stack_size: self.stack_size,
             rodata: translate_optional_range_to_higher_half(self.rodata),
             code: translate_range_to_higher_half(self.code),
             relro: translate_optional_range_to_higher_half(self.relro),
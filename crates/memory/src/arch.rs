@@ -1,5 +1,12 @@
 #[cfg(target_arch = "x86_64")]
 mod x86_64 {
+    /// Returns the end (exclusive) address of the user address space, which is the lower half
+    /// of the current paging mode. It spans 47 bits with four-level paging and 56 bits with
+    /// five-level paging.
+    pub fn user_end() -> usize {
+        1 << (crate::paging::virt_addr_bits() - 1)
+    }
+
     /// Base address of the kernel address space
     pub const KERNEL_BASE: usize = 0xfffff00000000000;
 
@@ -22,18 +29,29 @@ mod x86_64 {
     pub const FRAME_SIZE: u64 = 4096;
     pub const FRAME_SHIFT: u64 = 12;
 
-    pub const LAREGE_PAGE_SIZE: usize = 0x200000; // 2 MiB
-    pub const LAREGE_PAGE_SHIFT: usize = 21;
+    pub const LARGE_PAGE_SIZE: usize = 0x200000; // 2 MiB
+    pub const LARGE_PAGE_SHIFT: usize = 21;
 
     pub const LARGE_FRAME_SIZE: u64 = 0x200000; // 2 MiB
     pub const LARGE_FRAME_SHIFT: u64 = 21;
 
+    pub const GIANT_PAGE_SIZE: usize = 0x40000000; // 1 GiB
+    pub const GIANT_PAGE_SHIFT: usize = 30;
+
+    pub const GIANT_FRAME_SIZE: u64 = 0x40000000; // 1 GiB
+    pub const GIANT_FRAME_SHIFT: u64 = 30;
+
     /// The number of entries in a page table.
     pub const PAGE_TABLE_ENTRIES: usize = 512;
 }
 
 #[cfg(target_arch = "x86")]
 mod x86 {
+    /// Returns the end (exclusive) address of the user address space.
+    pub const fn user_end() -> usize {
+        KERNEL_BASE
+    }
+
     /// Base address of the kernel address space
     pub const KERNEL_BASE: usize = 0xc0000000;
 
@@ -56,10 +74,10 @@ mod x86 {
     pub const FRAME_SIZE: u32 = 4096;
     pub const FRAME_SHIFT: u32 = 12;
 
-    pub const LARGE_PAGE_SIZE: usize = 0x400000; // 2 MiB
+    pub const LARGE_PAGE_SIZE: usize = 0x400000; // 4 MiB
     pub const LARGE_PAGE_SHIFT: usize = 22;
 
-    pub const LARGE_FRAME_SIZE: u32 = 0x400000; // 2 MiB
+    pub const LARGE_FRAME_SIZE: u32 = 0x400000; // 4 MiB
     pub const LARGE_FRAME_SHIFT: u32 = 22;
 
     /// The number of entries in a page table.
@@ -1,8 +1,5 @@
 use crate::phys::{Frame, Inner, PhysAddr, PhysicalRange};
-use arrayvec::ArrayVec;
-
-/// Maximum number of memory map entries
-pub const MEMORY_MAP_ENTRIES: usize = 64;
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryMapEntryKind {
@@ -22,6 +19,43 @@ pub enum MemoryMapEntryKind {
     BootInfo,
     /// Memory is used by the kernel image
     KernelImage,
+    /// ACPI tables which can be reused once they have been parsed
+    AcpiReclaimable,
+    /// ACPI non-volatile storage which must be preserved across sleep states
+    AcpiNvs,
+    /// Memory mapped I/O
+    Mmio,
+    /// Non-volatile memory
+    PersistentMemory,
+    /// Memory is used by the frame buffer
+    FrameBuffer,
+    /// No memory was reported for this range
+    Hole,
+}
+
+/// The number of different priorities of `MemoryMapEntryKind`s.
+const NUM_PRIORITIES: usize = 14;
+
+impl MemoryMapEntryKind {
+    /// Decides which kind wins if two entries overlap.
+    fn priority(self) -> u8 {
+        match self {
+            MemoryMapEntryKind::Defective => 13,
+            MemoryMapEntryKind::Reserved => 12,
+            MemoryMapEntryKind::AcpiNvs => 11,
+            MemoryMapEntryKind::Mmio => 10,
+            MemoryMapEntryKind::PersistentMemory => 9,
+            MemoryMapEntryKind::FrameBuffer => 8,
+            MemoryMapEntryKind::RuntimeServiceCode => 7,
+            MemoryMapEntryKind::RuntimeServiceData => 6,
+            MemoryMapEntryKind::AcpiReclaimable => 5,
+            MemoryMapEntryKind::KernelImage => 4,
+            MemoryMapEntryKind::BootInfo => 3,
+            MemoryMapEntryKind::Loader => 2,
+            MemoryMapEntryKind::Usable => 1,
+            MemoryMapEntryKind::Hole => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -70,37 +104,39 @@ impl MemoryMapEntry {
     }
 }
 
-#[derive(Debug)]
-pub struct MemoryMap {
-    entries: ArrayVec<MemoryMapEntry, MEMORY_MAP_ENTRIES>,
+/// The physical memory map.
+///
+/// The entries are sorted, do not overlap and there are no gaps between them,
+/// see `sanitize_memory_map()`.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMap<'a> {
+    entries: &'a [MemoryMapEntry],
 }
 
-impl MemoryMap {
-    pub const fn new(vec: ArrayVec<MemoryMapEntry, MEMORY_MAP_ENTRIES>) -> Self {
-        Self { entries: vec }
+impl<'a> MemoryMap<'a> {
+    /// Creates a memory map from the result of `sanitize_memory_map()`.
+    pub const fn new(entries: &'a [MemoryMapEntry]) -> Self {
+        Self { entries }
     }
 
-    pub fn from_slice(map: &[MemoryMapEntry]) -> Self {
-        assert!(map.len() <= MEMORY_MAP_ENTRIES);
-        let iter = map.iter().map(|e| *e);
-        let vec = ArrayVec::from_iter(iter);
-        Self::new(vec)
+    pub const fn empty() -> Self {
+        Self { entries: &[] }
     }
 
-    pub fn entries(&self) -> impl Iterator<Item = &MemoryMapEntry> {
-        self.entries.iter()
+    pub fn as_slice(&self) -> &'a [MemoryMapEntry] {
+        self.entries
     }
 
-    pub fn first(&self) -> &MemoryMapEntry {
-        self.entries().nth(0).expect("memory map is empty")
+    pub fn entries(&self) -> impl Iterator<Item = &'a MemoryMapEntry> {
+        self.entries.iter()
     }
 
-    pub fn last(&self) -> &MemoryMapEntry {
-        let num_entries = self.entries().count();
+    pub fn first(&self) -> &'a MemoryMapEntry {
+        self.entries.first().expect("memory map is empty")
+    }
 
-        self.entries()
-            .nth(num_entries - 1)
-            .expect("memory map is empty")
+    pub fn last(&self) -> &'a MemoryMapEntry {
+        self.entries.last().expect("memory map is empty")
     }
 
     pub fn start_addr(&self) -> PhysAddr {
@@ -121,11 +157,14 @@ impl MemoryMap {
     pub fn entries_for_range(
         &self,
         range: PhysicalRange,
-    ) -> Option<impl Iterator<Item = &MemoryMapEntry>> {
+    ) -> Option<impl Iterator<Item = &'a MemoryMapEntry>> {
         if self.is_covered(range) {
-            let iter = self
-                .entries()
-                .skip_while(move |entry| entry.end <= range.start().to_addr())
+            let first = self
+                .entries
+                .partition_point(|entry| entry.end <= range.start().to_addr());
+
+            let iter = self.entries[first..]
+                .iter()
                 .take_while(move |entry| entry.start < range.end().to_addr());
             Some(iter)
         } else {
@@ -141,3 +180,186 @@ impl MemoryMap {
         }
     }
 }
+
+/// The maximum number of entries `sanitize_memory_map()` returns for `num_entries` entries.
+///
+/// Note: the start and end addresses of the entries split the memory into at most
+/// `2 * num_entries - 1` ranges and each of them ends up in at most one entry.
+pub const fn max_sanitized_entries(num_entries: usize) -> usize {
+    2 * num_entries
+}
+
+/// Turns the memory map entries reported by the firmware into a sorted list of entries which
+/// neither overlap nor have gaps between them.
+///
+/// - Empty entries are dropped.
+/// - Where entries overlap, the kind with the highest priority wins:
+///   Defective > Reserved > AcpiNvs > Mmio > PersistentMemory > FrameBuffer > RuntimeServiceCode >
+///   RuntimeServiceData > AcpiReclaimable > KernelImage > BootInfo > Loader > Usable
+/// - Gaps between entries are recorded as `MemoryMapEntryKind::Hole`.
+/// - Adjacent entries of the same kind are merged.
+pub fn sanitize_memory_map(entries: &[MemoryMapEntry]) -> Vec<MemoryMapEntry> {
+    // every entry starts to cover the memory at its start address and stops at its end address
+    let mut bounds: Vec<(PhysAddr, bool, MemoryMapEntryKind)> = entries
+        .iter()
+        .filter(|entry| entry.start < entry.end)
+        .flat_map(|entry| {
+            [
+                (entry.start, true, entry.kind),
+                (entry.end, false, entry.kind),
+            ]
+        })
+        .collect();
+
+    bounds.sort_unstable_by_key(|(addr, _, _)| *addr);
+
+    // the number of entries of each priority which cover the current address
+    let mut covering = [0usize; NUM_PRIORITIES];
+    let mut kinds = [MemoryMapEntryKind::Hole; NUM_PRIORITIES];
+
+    let mut sanitized: Vec<MemoryMapEntry> = Vec::new();
+
+    for (idx, &(start, starts, kind)) in bounds.iter().enumerate() {
+        let priority = kind.priority() as usize;
+
+        if starts {
+            covering[priority] += 1;
+            kinds[priority] = kind;
+        } else {
+            covering[priority] -= 1;
+        }
+
+        // the range up to the next address is complete once all bounds at `start` are applied
+        let end = match bounds.get(idx + 1) {
+            Some(&(end, _, _)) if end != start => end,
+            _ => continue,
+        };
+
+        let kind = (0..NUM_PRIORITIES)
+            .rev()
+            .find(|priority| covering[*priority] != 0)
+            .map_or(MemoryMapEntryKind::Hole, |priority| kinds[priority]);
+
+        match sanitized.last_mut() {
+            Some(last) if last.kind == kind => last.end = end,
+            _ => sanitized.push(MemoryMapEntry::new(start, end, kind)),
+        }
+    }
+
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MemoryMapEntryKind::*;
+
+    fn entry(start: Inner, end: Inner, kind: MemoryMapEntryKind) -> MemoryMapEntry {
+        MemoryMapEntry::new(PhysAddr::new(start), PhysAddr::new(end), kind)
+    }
+
+    fn sanitize(entries: &[MemoryMapEntry]) -> Vec<(Inner, Inner, MemoryMapEntryKind)> {
+        let sanitized = sanitize_memory_map(entries);
+        assert!(sanitized.len() <= max_sanitized_entries(entries.len()));
+
+        sanitized
+            .iter()
+            .map(|entry| (entry.start.to_inner(), entry.end.to_inner(), entry.kind))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_entries_and_fills_gaps() {
+        let entries = [
+            entry(0x5000, 0x8000, Reserved),
+            entry(0x0000, 0x2000, Usable),
+            entry(0x3000, 0x5000, Usable),
+        ];
+
+        assert_eq!(
+            sanitize(&entries),
+            [
+                (0x0000, 0x2000, Usable),
+                (0x2000, 0x3000, Hole),
+                (0x3000, 0x5000, Usable),
+                (0x5000, 0x8000, Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_empty_entries_and_merges_adjacent_ones() {
+        let entries = [
+            entry(0x0000, 0x1000, Usable),
+            entry(0x1000, 0x1000, Reserved),
+            entry(0x1000, 0x3000, Usable),
+        ];
+
+        assert_eq!(sanitize(&entries), [(0x0000, 0x3000, Usable)]);
+    }
+
+    #[test]
+    fn higher_priorities_win_overlaps() {
+        let entries = [
+            entry(0x0000, 0x8000, Usable),
+            entry(0x2000, 0x4000, Loader),
+            entry(0x3000, 0x6000, Reserved),
+            entry(0x5000, 0x7000, AcpiReclaimable),
+        ];
+
+        assert_eq!(
+            sanitize(&entries),
+            [
+                (0x0000, 0x2000, Usable),
+                (0x2000, 0x3000, Loader),
+                (0x3000, 0x6000, Reserved),
+                (0x6000, 0x7000, AcpiReclaimable),
+                (0x7000, 0x8000, Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_entries_of_the_same_kind_are_counted() {
+        let entries = [
+            entry(0x0000, 0x4000, Usable),
+            entry(0x1000, 0x3000, Defective),
+            entry(0x1000, 0x2000, Defective),
+        ];
+
+        assert_eq!(
+            sanitize(&entries),
+            [
+                (0x0000, 0x1000, Usable),
+                (0x1000, 0x3000, Defective),
+                (0x3000, 0x4000, Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn priorities_are_distinct() {
+        let kinds = [
+            Usable,
+            Reserved,
+            Defective,
+            RuntimeServiceCode,
+            RuntimeServiceData,
+            Loader,
+            BootInfo,
+            KernelImage,
+            AcpiReclaimable,
+            AcpiNvs,
+            Mmio,
+            PersistentMemory,
+            FrameBuffer,
+            Hole,
+        ];
+
+        let mut priorities: Vec<u8> = kinds.iter().map(|kind| kind.priority()).collect();
+        priorities.sort_unstable();
+
+        let expected: Vec<u8> = (0..NUM_PRIORITIES as u8).collect();
+        assert_eq!(priorities, expected);
+    }
+}
@@ -0,0 +1,485 @@
+use zeroize::Zeroize;
+
+use crate::{
+    paging::{
+        Entry, EntryFlags, EntryUsage, Level1, Level2, MapError, MemoryType, PageSize, Table,
+        TlbFlush,
+    },
+    phys::{Frame, Inner, PageFrameAllocator, PhysAddr, PhysicalRange},
+    virt::{Page, VirtAddr, VirtualRange},
+    AccessFlags, KERNEL_BASE, PAGE_SIZE, PAGE_TABLE_ENTRIES,
+};
+
+/// Index of the first PD entry that belongs to the kernel address space.
+/// The page tables referenced by these entries are shared between all address spaces,
+/// so they are never freed by the `Mapper` and can not be replaced by 4 MiB pages.
+const KERNEL_P2_START_IDX: usize = (KERNEL_BASE >> 22) & 0x3FF;
+
+/// Index of the PD entry used for recursive mapping.
+pub(super) const RECURSIVE_P2_IDX: usize = PAGE_TABLE_ENTRIES - 1;
+
+/// The operation applied to the pages of a range.
+#[derive(Clone, Copy)]
+enum RangeOp {
+    Unmap,
+    Protect(AccessFlags),
+}
+
+/// A `Mapper` modifies the page tables of the active address space through the recursive mapping.
+///
+/// Page tables are allocated from `A` when needed and given back to `A`
+/// as soon as they become empty. The TLB entries of changed pages and tables are flushed with `F`.
+pub struct Mapper<'a, A: PageFrameAllocator, F: TlbFlush> {
+    p2: &'a mut Table<Level2>,
+    alloc: A,
+    tlb: F,
+    scratch: Page,
+    max_page_size: PageSize,
+}
+
+impl<'a, A: PageFrameAllocator, F: TlbFlush> Mapper<'a, A, F> {
+    /// Creates a new `Mapper`.
+    ///
+    /// When a 4 MiB page is split, the page table replacing it is filled through `scratch`
+    /// before it becomes visible. `max_page_size` is the largest page size the `Mapper` will create.
+    ///
+    /// # Safety
+    /// - `p2` must be the recursively mapped PD of the active address space
+    /// - the caller must have exclusive access to the recursive mapping area for `'a`
+    /// - `scratch` must be reserved for the `Mapper` and must not be part of a 4 MiB page
+    /// - CR4.PSE must be set if `max_page_size` is `PageSize::Large`
+    pub unsafe fn new(
+        p2: &'a mut Table<Level2>,
+        alloc: A,
+        tlb: F,
+        scratch: Page,
+        max_page_size: PageSize,
+    ) -> Self {
+        Self {
+            p2,
+            alloc,
+            tlb,
+            scratch,
+            max_page_size,
+        }
+    }
+
+    /// Returns the frame `page` is mapped to.
+    pub fn translate(&self, page: Page) -> Option<Frame> {
+        let (entry, num_pages) = self.entry(page);
+
+        match entry.usage() {
+            EntryUsage::Page => {
+                let offset = page.to_inner() % num_pages;
+                let frame = entry.page_frame(page_size(num_pages));
+                Some(frame.add(offset as Inner))
+            }
+            _ => None,
+        }
+    }
+
+    /// Translates a virtual address to the physical address it is mapped to.
+    pub fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let frame = self.translate(Page::new(addr))?;
+        let offset = addr.to_inner() % PAGE_SIZE;
+        Some(frame.to_addr() + offset as Inner)
+    }
+
+    /// Maps `page` to `frame` with the given access flags.
+    pub fn map(&mut self, page: Page, frame: Frame, access: AccessFlags) -> Result<(), MapError> {
+        self.map_sized(page, frame, PageSize::Normal, access)
+    }
+
+    /// Maps a page of the given size starting at `page` to the frames starting at `frame`.
+    /// Both `page` and `frame` must be aligned to `size`.
+    pub fn map_sized(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        size: PageSize,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.map_leaf(page, frame, size, access, MemoryType::WriteBack)
+    }
+
+    /// Maps every page of `pages` to the corresponding frame of `frames`.
+    /// The largest page size that fits the alignment of both ranges is used.
+    /// If a page can not be mapped, all pages mapped so far are unmapped again.
+    pub fn map_range(
+        &mut self,
+        pages: VirtualRange,
+        frames: PhysicalRange,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.map_range_with_type(pages, frames, access, MemoryType::WriteBack)
+    }
+
+    /// Like `map_range()`, but the pages are mapped with the given memory type.
+    pub fn map_range_with_type(
+        &mut self,
+        pages: VirtualRange,
+        frames: PhysicalRange,
+        access: AccessFlags,
+        memory_type: MemoryType,
+    ) -> Result<(), MapError> {
+        if pages.num_pages() as u64 != frames.num_frames() as u64 {
+            return Err(MapError::SizeMismatch);
+        }
+
+        let mut page = pages.start();
+        let mut frame = frames.start();
+
+        while page < pages.end() {
+            let size = self.best_page_size(page, frame, pages.end().diff(page));
+
+            if let Err(err) = self.map_leaf(page, frame, size, access, memory_type) {
+                self.unmap_range(VirtualRange::new(pages.start(), page))
+                    .expect("unable to undo a mapping made by map_range()");
+
+                return Err(err);
+            }
+
+            page = page.add(size.num_pages());
+            frame = frame.add(size.num_pages() as Inner);
+        }
+
+        Ok(())
+    }
+
+    fn map_leaf(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        size: PageSize,
+        access: AccessFlags,
+        memory_type: MemoryType,
+    ) -> Result<(), MapError> {
+        let (p2_idx, p1_idx) = Table::<Level2>::get_table_indices(page);
+
+        if p2_idx == RECURSIVE_P2_IDX {
+            return Err(MapError::InvalidAddress);
+        }
+
+        if !self.supports(page, size) {
+            return Err(MapError::UnsupportedPageSize);
+        }
+
+        if !is_aligned(page, frame, size) {
+            return Err(MapError::Unaligned);
+        }
+
+        let entry = leaf_entry(page, frame, size, access, memory_type);
+
+        if size == PageSize::Large {
+            return set_entry(&mut self.p2[p2_idx], entry);
+        }
+
+        let p1 = self.get_or_create_p1(p2_idx)?;
+        let res = set_entry(&mut p1[p1_idx], entry);
+
+        if res.is_err() {
+            // give back the page table if it has been created for this mapping
+            self.free_unused_p1(p2_idx);
+        }
+
+        res
+    }
+
+    /// Removes the mapping of `page` and returns the frame it was mapped to.
+    /// The frame itself is not deallocated.
+    ///
+    /// If `page` is part of a 4 MiB page, the 4 MiB page is split first.
+    pub fn unmap(&mut self, page: Page) -> Result<Frame, MapError> {
+        let frame = self.translate(page).ok_or(MapError::NotMapped)?;
+        self.apply(VirtualRange::with_size(page, 1), RangeOp::Unmap)?;
+        Ok(frame)
+    }
+
+    /// Removes the mappings of all pages in `range`. Pages which are not mapped are skipped
+    /// and the frames are not deallocated.
+    ///
+    /// 4 MiB pages which are only partially contained in `range` are split first.
+    pub fn unmap_range(&mut self, range: VirtualRange) -> Result<(), MapError> {
+        self.apply(range, RangeOp::Unmap)
+    }
+
+    /// Changes the access flags of the already mapped `page`.
+    ///
+    /// If `page` is part of a 4 MiB page, the 4 MiB page is split first.
+    pub fn protect(&mut self, page: Page, access: AccessFlags) -> Result<(), MapError> {
+        self.translate(page).ok_or(MapError::NotMapped)?;
+        self.apply(VirtualRange::with_size(page, 1), RangeOp::Protect(access))
+    }
+
+    /// Changes the access flags of all mapped pages in `range`. Pages which are not
+    /// mapped are skipped.
+    ///
+    /// 4 MiB pages which are only partially contained in `range` are split first.
+    pub fn protect_range(
+        &mut self,
+        range: VirtualRange,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.apply(range, RangeOp::Protect(access))
+    }
+
+    /// Checks if a page of the given size can be mapped at `page`.
+    fn supports(&self, page: Page, size: PageSize) -> bool {
+        let (p2_idx, _) = Table::<Level2>::get_table_indices(page);
+        size <= self.max_page_size && (size == PageSize::Normal || p2_idx < KERNEL_P2_START_IDX)
+    }
+
+    /// Returns the largest supported page size that can be used to map `page` to `frame`
+    /// without mapping more than `num_pages` pages.
+    fn best_page_size(&self, page: Page, frame: Frame, num_pages: usize) -> PageSize {
+        PageSize::ALL
+            .into_iter()
+            .find(|size| {
+                self.supports(page, *size)
+                    && size.num_pages() <= num_pages
+                    && is_aligned(page, frame, *size)
+            })
+            .unwrap_or(PageSize::Normal)
+    }
+
+    /// Applies `op` to every mapped page in `range`.
+    fn apply(&mut self, range: VirtualRange, op: RangeOp) -> Result<(), MapError> {
+        let mut page = range.start();
+        let mut table_dirty = false;
+
+        while page < range.end() {
+            let (p2_idx, _) = Table::<Level2>::get_table_indices(page);
+
+            if p2_idx == RECURSIVE_P2_IDX {
+                return Err(MapError::InvalidAddress);
+            }
+
+            let (entry, num_pages) = self.entry_mut(page);
+            let start = Page::from_inner(page.to_inner() - page.to_inner() % num_pages);
+            let end = start.add(num_pages);
+
+            match entry.usage() {
+                EntryUsage::None => {}
+                EntryUsage::Page if range.start() <= start && end <= range.end() => {
+                    let size = page_size(num_pages);
+
+                    *entry = match op {
+                        RangeOp::Unmap => Entry::empty(),
+                        RangeOp::Protect(access) => {
+                            let frame = entry.page_frame(size);
+                            leaf_entry(start, frame, size, access, entry.memory_type(size))
+                        }
+                    };
+
+                    self.tlb.flush(start.to_addr());
+                    table_dirty |= matches!(op, RangeOp::Unmap) && size == PageSize::Normal;
+                }
+                EntryUsage::Page => {
+                    // the 4 MiB page is only partially contained in `range`
+                    self.split(page)?;
+                    continue;
+                }
+                _ => return Err(MapError::InvalidTableLayout),
+            }
+
+            // only look for an empty page table once all of its entries have been visited
+            let table_done = end.to_inner().is_multiple_of(PageSize::Large.num_pages());
+
+            if table_dirty && (table_done || end >= range.end()) {
+                self.free_unused_p1(p2_idx);
+                table_dirty = false;
+            }
+
+            page = end;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the 4 MiB page containing `page` with a page table mapping the same
+    /// frames with the same flags.
+    fn split(&mut self, page: Page) -> Result<(), MapError> {
+        let (p2_idx, _) = Table::<Level2>::get_table_indices(page);
+        let huge = self.p2[p2_idx];
+
+        let table_frame = self.alloc.alloc().ok_or(MapError::OutOfMemory)?;
+
+        // The new table must be complete before it replaces the 4 MiB page, since the
+        // 4 MiB page might be in use right now, e.g. by our own stack.
+        let table = match self.map_scratch(table_frame) {
+            Ok(table) => table,
+            Err(err) => {
+                self.alloc.dealloc(table_frame);
+                return Err(err);
+            }
+        };
+
+        for idx in 0..PAGE_TABLE_ENTRIES {
+            let frame = huge.page_frame(PageSize::Large).add(idx as Inner);
+            let mut flags = huge.flags();
+            flags.remove(EntryFlags::PAGE_SIZE);
+
+            let mut child = huge;
+            child.set_addr(frame.to_addr().to_inner());
+            child.set_flags(flags);
+
+            // the PAT bit is at a different position in entries mapping 4 KiB pages
+            child.set_memory_type(huge.memory_type(PageSize::Large), PageSize::Normal);
+
+            table[idx] = child;
+        }
+
+        self.unmap_scratch();
+
+        // 4 MiB pages are only used in the user half
+        self.p2[p2_idx] = user_table_entry(table_frame);
+
+        // the recursive mapping of the new table might still be cached as part of the 4 MiB page
+        self.tlb.flush_all();
+
+        Ok(())
+    }
+
+    /// Maps `frame` to the scratch page in order to initialize it as a page table.
+    fn map_scratch(&mut self, frame: Frame) -> Result<&mut Table<Level1>, MapError> {
+        self.map(self.scratch, frame, AccessFlags::READ_WRITE)?;
+        Ok(unsafe { &mut *self.scratch.to_addr().as_ptr_mut::<Table<Level1>>() })
+    }
+
+    /// Removes the scratch mapping but keeps the page table leading to it.
+    fn unmap_scratch(&mut self) {
+        let (entry, _) = self.entry_mut(self.scratch);
+        *entry = Entry::empty();
+        self.tlb.flush(self.scratch.to_addr());
+    }
+
+    /// Returns the entry that maps `page` together with the number of pages it spans.
+    /// If `page` is not mapped, the empty entry that would reference the page table is returned.
+    fn entry(&self, page: Page) -> (&Entry, usize) {
+        let (p2_idx, p1_idx) = Table::<Level2>::get_table_indices(page);
+
+        match unsafe { self.p2.next_table(p2_idx) } {
+            Some(p1) => (&p1[p1_idx], PageSize::Normal.num_pages()),
+            None => (&self.p2[p2_idx], PageSize::Large.num_pages()),
+        }
+    }
+
+    /// Mutable version of `entry()`.
+    fn entry_mut(&mut self, page: Page) -> (&mut Entry, usize) {
+        let (p2_idx, p1_idx) = Table::<Level2>::get_table_indices(page);
+
+        if self.p2[p2_idx].usage() != EntryUsage::Table {
+            return (&mut self.p2[p2_idx], PageSize::Large.num_pages());
+        }
+
+        let p1 = unsafe { self.p2.next_table_mut(p2_idx) }.unwrap();
+
+        (&mut p1[p1_idx], PageSize::Normal.num_pages())
+    }
+
+    /// Returns the page table referenced by `p2[p2_idx]`.
+    /// If there is no such table, a new one is allocated.
+    fn get_or_create_p1(&mut self, p2_idx: usize) -> Result<&mut Table<Level1>, MapError> {
+        match self.p2[p2_idx].usage() {
+            EntryUsage::None => {
+                let frame = self.alloc.alloc().ok_or(MapError::OutOfMemory)?;
+                // page tables of the kernel half are preallocated, so this is a user page table
+                self.p2[p2_idx] = user_table_entry(frame);
+
+                let p1 = unsafe { self.p2.next_table_mut(p2_idx) }.unwrap();
+                self.tlb.flush(VirtAddr::new(p1 as *const _ as usize));
+                p1.zeroize();
+
+                Ok(p1)
+            }
+            EntryUsage::Table => Ok(unsafe { self.p2.next_table_mut(p2_idx) }.unwrap()),
+            // the page is already part of a 4 MiB page
+            EntryUsage::Page => Err(MapError::AlreadyMapped),
+            _ => Err(MapError::InvalidTableLayout),
+        }
+    }
+
+    /// Frees the page table referenced by `p2[p2_idx]` if none of its entries are in use.
+    /// Page tables of the kernel address space are never freed.
+    fn free_unused_p1(&mut self, p2_idx: usize) {
+        if p2_idx >= KERNEL_P2_START_IDX {
+            return;
+        }
+
+        let p1 = match unsafe { self.p2.next_table_mut(p2_idx) } {
+            Some(p1) => p1,
+            None => return,
+        };
+
+        if !p1.is_unused() {
+            return;
+        }
+
+        let p1_addr = VirtAddr::new(p1 as *const _ as usize);
+        let frame = self.p2[p2_idx].frame();
+
+        self.p2[p2_idx] = Entry::empty();
+        self.tlb.flush(p1_addr);
+        self.alloc.dealloc(frame);
+    }
+}
+
+/// Checks if both `page` and `frame` are aligned to `size`.
+fn is_aligned(page: Page, frame: Frame, size: PageSize) -> bool {
+    page.to_inner().is_multiple_of(size.num_pages())
+        && frame.to_inner().is_multiple_of(size.num_pages() as Inner)
+}
+
+/// Returns the page size of an entry spanning `num_pages` pages.
+fn page_size(num_pages: usize) -> PageSize {
+    if num_pages == PageSize::Large.num_pages() {
+        PageSize::Large
+    } else {
+        PageSize::Normal
+    }
+}
+
+/// Checks if `page` belongs to the user half of the address space.
+fn is_user(page: Page) -> bool {
+    page.to_addr().to_inner() < KERNEL_BASE
+}
+
+/// Creates the entry referencing the page table in `frame` of the user half.
+fn user_table_entry(frame: Frame) -> Entry {
+    let mut entry = Entry::table_entry(frame.to_addr());
+    entry.set_flags(entry.flags() | EntryFlags::USER);
+    entry
+}
+
+/// Creates the entry mapping a page of the given size at `page` to `frame`.
+/// Pages of the user half are accessible from user mode.
+fn leaf_entry(
+    page: Page,
+    frame: Frame,
+    size: PageSize,
+    access: AccessFlags,
+    memory_type: MemoryType,
+) -> Entry {
+    let mut entry = match size {
+        PageSize::Normal => Entry::page_entry(frame.to_addr(), access),
+        PageSize::Large => Entry::huge_page_entry(frame.to_addr(), access),
+    };
+
+    entry.set_memory_type(memory_type, size);
+
+    if is_user(page) {
+        entry.set_flags(entry.flags() | EntryFlags::USER);
+    }
+
+    entry
+}
+
+/// Writes `new` to the unused `entry`.
+fn set_entry(entry: &mut Entry, new: Entry) -> Result<(), MapError> {
+    if entry.usage() != EntryUsage::None {
+        return Err(MapError::AlreadyMapped);
+    }
+
+    *entry = new;
+    Ok(())
+}
@@ -0,0 +1,708 @@
+use zeroize::Zeroize;
+
+use crate::{
+    paging::{
+        virt_addr_bits, Entry, EntryFlags, EntryUsage, HierarchicalLevel, Level1, Level4, Level5,
+        MapError, MemoryType, PageSize, Table, TlbFlush,
+    },
+    phys::{Frame, Inner, PageFrameAllocator, PhysAddr, PhysicalRange},
+    user_end,
+    virt::{Page, VirtAddr, VirtualRange},
+    AccessFlags, KERNEL_BASE, PAGE_SIZE, PAGE_TABLE_ENTRIES,
+};
+
+/// Index of the first PML4T entry that belongs to the kernel address space.
+/// The PDPT's referenced by these entries are shared between all address spaces,
+/// so they are never freed by the `Mapper`.
+const KERNEL_P4_START_IDX: usize = (KERNEL_BASE >> 39) & 0x1FF;
+
+/// Index of the PML4T entry used for recursive mapping.
+pub(super) const RECURSIVE_P4_IDX: usize = PAGE_TABLE_ENTRIES - 1;
+
+/// The number of pages spanned by a single PML4T entry.
+const P4_ENTRY_PAGES: usize = PageSize::Giant.num_pages() * PAGE_TABLE_ENTRIES;
+
+/// Index of the PML5T entry referencing the PML4T of the higher half with five-level paging.
+const KERNEL_P5_IDX: usize = Table::<Level5>::KERNEL_P4_IDX;
+
+/// The number of pages spanned by a single PML5T entry.
+const P5_ENTRY_PAGES: usize = P4_ENTRY_PAGES * PAGE_TABLE_ENTRIES;
+
+/// The operation applied to the pages of a range.
+#[derive(Clone, Copy)]
+enum RangeOp {
+    Unmap,
+    Protect(AccessFlags),
+}
+
+/// A `Mapper` modifies the page tables of the active address space through the recursive mapping.
+///
+/// Intermediate tables are allocated from `A` when needed and given back to `A`
+/// as soon as they become empty. The TLB entries of changed pages and tables are flushed with `F`.
+pub struct Mapper<'a, A: PageFrameAllocator, F: TlbFlush> {
+    p4: &'a mut Table<Level4>,
+    /// The PML5T with five-level paging. It references the PML4T's of the user half, while `p4`
+    /// only covers the higher half then.
+    p5: Option<&'a mut Table<Level5>>,
+    alloc: A,
+    tlb: F,
+    scratch: Page,
+    max_page_size: PageSize,
+}
+
+impl<'a, A: PageFrameAllocator, F: TlbFlush> Mapper<'a, A, F> {
+    /// Creates a new `Mapper`.
+    ///
+    /// When a huge page is split, the table replacing it is filled through `scratch` before
+    /// it becomes visible. `max_page_size` is the largest page size the `Mapper` will create.
+    ///
+    /// # Safety
+    /// - `p4` must be the recursively mapped PML4T of the active address space, with five-level
+    ///   paging the one of the higher half
+    /// - with five-level paging `p5` must be the PML5T of the active address space, which is
+    ///   recursively mapped by its entry `Table::<Level5>::RECURSIVE_IDX`, otherwise it must be
+    ///   `None`
+    /// - the caller must have exclusive access to the recursive mapping area for `'a`
+    /// - `scratch` must be reserved for the `Mapper` and must not be part of a huge page
+    /// - the cpu must support pages of `max_page_size`
+    pub unsafe fn new(
+        p4: &'a mut Table<Level4>,
+        p5: Option<&'a mut Table<Level5>>,
+        alloc: A,
+        tlb: F,
+        scratch: Page,
+        max_page_size: PageSize,
+    ) -> Self {
+        Self {
+            p4,
+            p5,
+            alloc,
+            tlb,
+            scratch,
+            max_page_size,
+        }
+    }
+
+    /// Returns the frame `page` is mapped to.
+    pub fn translate(&self, page: Page) -> Option<Frame> {
+        if !page.is_canonical() {
+            return None;
+        }
+
+        let (entry, num_pages) = self.entry(page);
+
+        match entry.usage() {
+            EntryUsage::Page => {
+                let offset = page.to_inner() % num_pages;
+                let frame = entry.page_frame(page_size(num_pages).expect("invalid page size"));
+                Some(frame.add(offset as Inner))
+            }
+            _ => None,
+        }
+    }
+
+    /// Translates a virtual address to the physical address it is mapped to.
+    pub fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let frame = self.translate(Page::new(addr))?;
+        let offset = addr.to_inner() % PAGE_SIZE;
+        Some(frame.to_addr() + offset as Inner)
+    }
+
+    /// Maps `page` to `frame` with the given access flags.
+    pub fn map(&mut self, page: Page, frame: Frame, access: AccessFlags) -> Result<(), MapError> {
+        self.map_sized(page, frame, PageSize::Normal, access)
+    }
+
+    /// Maps a page of the given size starting at `page` to the frames starting at `frame`.
+    /// Both `page` and `frame` must be aligned to `size`.
+    pub fn map_sized(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        size: PageSize,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.map_leaf(page, frame, size, access, MemoryType::WriteBack)
+    }
+
+    /// Maps every page of `pages` to the corresponding frame of `frames`.
+    /// The largest page size that fits the alignment of both ranges is used.
+    /// If a page can not be mapped, all pages mapped so far are unmapped again.
+    pub fn map_range(
+        &mut self,
+        pages: VirtualRange,
+        frames: PhysicalRange,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.map_range_with_type(pages, frames, access, MemoryType::WriteBack)
+    }
+
+    /// Like `map_range()`, but the pages are mapped with the given memory type.
+    pub fn map_range_with_type(
+        &mut self,
+        pages: VirtualRange,
+        frames: PhysicalRange,
+        access: AccessFlags,
+        memory_type: MemoryType,
+    ) -> Result<(), MapError> {
+        if pages.num_pages() as u64 != frames.num_frames() {
+            return Err(MapError::SizeMismatch);
+        }
+
+        let mut page = pages.start();
+        let mut frame = frames.start();
+
+        while page < pages.end() {
+            let size = self.best_page_size(page, frame, pages.end().diff(page));
+
+            if let Err(err) = self.map_leaf(page, frame, size, access, memory_type) {
+                self.unmap_range(VirtualRange::new(pages.start(), page))
+                    .expect("unable to undo a mapping made by map_range()");
+
+                return Err(err);
+            }
+
+            page = page.add(size.num_pages());
+            frame = frame.add(size.num_pages() as Inner);
+        }
+
+        Ok(())
+    }
+
+    fn map_leaf(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        size: PageSize,
+        access: AccessFlags,
+        memory_type: MemoryType,
+    ) -> Result<(), MapError> {
+        if !is_mappable(page) {
+            return Err(MapError::InvalidAddress);
+        }
+
+        if size > self.max_page_size {
+            return Err(MapError::UnsupportedPageSize);
+        }
+
+        if !is_aligned(page, frame, size) {
+            return Err(MapError::Unaligned);
+        }
+
+        let entry = leaf_entry(page, frame, size, access, memory_type);
+
+        let res = unsafe { self.map_entry(page, size, entry) };
+
+        if res.is_err() {
+            // give back the tables that might have been created for this mapping
+            unsafe { self.free_unused_tables(page) };
+        }
+
+        res
+    }
+
+    /// Removes the mapping of `page` and returns the frame it was mapped to.
+    /// The frame itself is not deallocated.
+    ///
+    /// If `page` is part of a huge page, the huge page is split first.
+    pub fn unmap(&mut self, page: Page) -> Result<Frame, MapError> {
+        let frame = self.translate(page).ok_or(MapError::NotMapped)?;
+        self.apply(VirtualRange::with_size(page, 1), RangeOp::Unmap)?;
+        Ok(frame)
+    }
+
+    /// Removes the mappings of all pages in `range`. Pages which are not mapped are skipped
+    /// and the frames are not deallocated.
+    ///
+    /// Huge pages which are only partially contained in `range` are split first.
+    pub fn unmap_range(&mut self, range: VirtualRange) -> Result<(), MapError> {
+        self.apply(range, RangeOp::Unmap)
+    }
+
+    /// Changes the access flags of the already mapped `page`.
+    ///
+    /// If `page` is part of a huge page, the huge page is split first.
+    pub fn protect(&mut self, page: Page, access: AccessFlags) -> Result<(), MapError> {
+        self.translate(page).ok_or(MapError::NotMapped)?;
+        self.apply(VirtualRange::with_size(page, 1), RangeOp::Protect(access))
+    }
+
+    /// Changes the access flags of all mapped pages in `range`. Pages which are not
+    /// mapped are skipped.
+    ///
+    /// Huge pages which are only partially contained in `range` are split first.
+    pub fn protect_range(
+        &mut self,
+        range: VirtualRange,
+        access: AccessFlags,
+    ) -> Result<(), MapError> {
+        self.apply(range, RangeOp::Protect(access))
+    }
+
+    /// Returns the largest supported page size that can be used to map `page` to `frame`
+    /// without mapping more than `num_pages` pages.
+    fn best_page_size(&self, page: Page, frame: Frame, num_pages: usize) -> PageSize {
+        PageSize::ALL
+            .into_iter()
+            .find(|size| {
+                *size <= self.max_page_size
+                    && size.num_pages() <= num_pages
+                    && is_aligned(page, frame, *size)
+            })
+            .unwrap_or(PageSize::Normal)
+    }
+
+    unsafe fn map_entry(
+        &mut self,
+        page: Page,
+        size: PageSize,
+        entry: Entry,
+    ) -> Result<(), MapError> {
+        let (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx) = Table::<Level5>::get_table_indices(page);
+        let alloc = &mut self.alloc;
+        let tlb = &mut self.tlb;
+        let user = is_user(page);
+
+        unsafe {
+            let p4 = match self.p5.as_deref_mut() {
+                Some(p5) if p5_idx != KERNEL_P5_IDX => {
+                    get_or_create_table(p5, p5_idx, alloc, tlb, user)?
+                }
+                _ => &mut *self.p4,
+            };
+
+            let p3 = get_or_create_table(p4, p4_idx, alloc, tlb, user)?;
+
+            if size == PageSize::Giant {
+                return set_entry(&mut p3[p3_idx], entry);
+            }
+
+            let p2 = get_or_create_table(p3, p3_idx, alloc, tlb, user)?;
+
+            if size == PageSize::Large {
+                return set_entry(&mut p2[p2_idx], entry);
+            }
+
+            let p1 = get_or_create_table(p2, p2_idx, alloc, tlb, user)?;
+            set_entry(&mut p1[p1_idx], entry)
+        }
+    }
+
+    /// Applies `op` to every mapped page in `range`.
+    fn apply(&mut self, range: VirtualRange, op: RangeOp) -> Result<(), MapError> {
+        let mut page = range.start();
+        let mut tables_dirty = false;
+
+        while page < range.end() {
+            if !is_mappable(page) {
+                return Err(MapError::InvalidAddress);
+            }
+
+            let (entry, num_pages) = self.entry_mut(page);
+            let start = Page::from_inner(page.to_inner() - page.to_inner() % num_pages);
+            let end = start.add(num_pages);
+
+            match entry.usage() {
+                EntryUsage::None => {}
+                EntryUsage::Page if range.start() <= start && end <= range.end() => {
+                    let size = page_size(num_pages).ok_or(MapError::InvalidTableLayout)?;
+
+                    *entry = match op {
+                        RangeOp::Unmap => Entry::empty(),
+                        RangeOp::Protect(access) => {
+                            let frame = entry.page_frame(size);
+                            leaf_entry(start, frame, size, access, entry.memory_type(size))
+                        }
+                    };
+
+                    self.tlb.flush(start.to_addr());
+                    tables_dirty |= matches!(op, RangeOp::Unmap);
+                }
+                EntryUsage::Page => {
+                    // the huge page is only partially contained in `range`
+                    self.split(page)?;
+                    continue;
+                }
+                _ => return Err(MapError::InvalidTableLayout),
+            }
+
+            // only look for empty tables once all entries of the current table have been visited
+            let table_pages = num_pages * PAGE_TABLE_ENTRIES;
+            let table_done = end.to_inner().is_multiple_of(table_pages);
+
+            if tables_dirty && (table_done || end >= range.end()) {
+                unsafe { self.free_unused_tables(start) };
+                tables_dirty = false;
+            }
+
+            page = end;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the huge page containing `page` with a table of pages of the next smaller size.
+    /// The new table maps the same frames with the same flags.
+    fn split(&mut self, page: Page) -> Result<(), MapError> {
+        let (entry, num_pages) = self.entry(page);
+        let huge = *entry;
+
+        let size = page_size(num_pages).ok_or(MapError::InvalidTableLayout)?;
+        let child_size = match size {
+            PageSize::Giant => PageSize::Large,
+            PageSize::Large => PageSize::Normal,
+            PageSize::Normal => return Err(MapError::InvalidTableLayout),
+        };
+
+        let table_frame = self.alloc.alloc().ok_or(MapError::OutOfMemory)?;
+
+        // The new table must be complete before it replaces the huge page, since the
+        // huge page might be in use right now, e.g. by our own stack.
+        let table = match self.map_scratch(table_frame) {
+            Ok(table) => table,
+            Err(err) => {
+                self.alloc.dealloc(table_frame);
+                return Err(err);
+            }
+        };
+
+        for idx in 0..PAGE_TABLE_ENTRIES {
+            let frame = huge
+                .page_frame(size)
+                .add((idx * child_size.num_pages()) as Inner);
+            let mut child = huge;
+            child.set_addr(frame.to_addr().to_inner());
+
+            if child_size == PageSize::Normal {
+                let mut flags = huge.flags();
+                flags.remove(EntryFlags::PAGE_SIZE);
+                child.set_flags(flags);
+            }
+
+            // the PAT bit is at a different position in entries mapping 4 KiB pages
+            child.set_memory_type(huge.memory_type(size), child_size);
+
+            table[idx] = child;
+        }
+
+        self.unmap_scratch();
+
+        let (entry, _) = self.entry_mut(page);
+        *entry = Entry::table_entry(table_frame.to_addr());
+
+        if is_user(page) {
+            entry.set_flags(entry.flags() | EntryFlags::USER);
+        }
+
+        // the recursive mapping of the new table might still be cached as part of the huge page
+        self.tlb.flush_all();
+
+        Ok(())
+    }
+
+    /// Maps `frame` to the scratch page in order to initialize it as a page table.
+    fn map_scratch(&mut self, frame: Frame) -> Result<&mut Table<Level1>, MapError> {
+        self.map(self.scratch, frame, AccessFlags::READ_WRITE)?;
+
+        // Note: the table is only used to write entries, so its level does not matter.
+        Ok(unsafe { &mut *self.scratch.to_addr().as_ptr_mut::<Table<Level1>>() })
+    }
+
+    /// Removes the scratch mapping but keeps the tables leading to it.
+    fn unmap_scratch(&mut self) {
+        let (entry, _) = self.entry_mut(self.scratch);
+        *entry = Entry::empty();
+        self.tlb.flush(self.scratch.to_addr());
+    }
+
+    /// Returns the entry that maps `page` together with the number of pages it spans.
+    /// If `page` is not mapped, the empty entry that would reference the next table is returned.
+    /// `page` must be canonical.
+    fn entry(&self, page: Page) -> (&Entry, usize) {
+        let (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx) = Table::<Level5>::get_table_indices(page);
+
+        let p4 = match self.p5.as_deref() {
+            Some(p5) if p5_idx != KERNEL_P5_IDX => match unsafe { p5.next_table(p5_idx) } {
+                Some(p4) => p4,
+                None => return (&p5[p5_idx], P5_ENTRY_PAGES),
+            },
+            _ => &*self.p4,
+        };
+
+        let p3 = match unsafe { p4.next_table(p4_idx) } {
+            Some(p3) => p3,
+            None => return (&p4[p4_idx], P4_ENTRY_PAGES),
+        };
+
+        let p2 = match unsafe { p3.next_table(p3_idx) } {
+            Some(p2) => p2,
+            None => return (&p3[p3_idx], PageSize::Giant.num_pages()),
+        };
+
+        let p1 = match unsafe { p2.next_table(p2_idx) } {
+            Some(p1) => p1,
+            None => return (&p2[p2_idx], PageSize::Large.num_pages()),
+        };
+
+        (&p1[p1_idx], PageSize::Normal.num_pages())
+    }
+
+    /// Mutable version of `entry()`.
+    fn entry_mut(&mut self, page: Page) -> (&mut Entry, usize) {
+        let (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx) = Table::<Level5>::get_table_indices(page);
+
+        let p4 = match self.p5.as_deref_mut() {
+            Some(p5) if p5_idx != KERNEL_P5_IDX => {
+                if p5[p5_idx].usage() != EntryUsage::Table {
+                    return (&mut p5[p5_idx], P5_ENTRY_PAGES);
+                }
+
+                unsafe { p5.next_table_mut(p5_idx) }.unwrap()
+            }
+            _ => &mut *self.p4,
+        };
+
+        if p4[p4_idx].usage() != EntryUsage::Table {
+            return (&mut p4[p4_idx], P4_ENTRY_PAGES);
+        }
+
+        let p3 = unsafe { p4.next_table_mut(p4_idx) }.unwrap();
+
+        if p3[p3_idx].usage() != EntryUsage::Table {
+            return (&mut p3[p3_idx], PageSize::Giant.num_pages());
+        }
+
+        let p2 = unsafe { p3.next_table_mut(p3_idx) }.unwrap();
+
+        if p2[p2_idx].usage() != EntryUsage::Table {
+            return (&mut p2[p2_idx], PageSize::Large.num_pages());
+        }
+
+        let p1 = unsafe { p2.next_table_mut(p2_idx) }.unwrap();
+
+        (&mut p1[p1_idx], PageSize::Normal.num_pages())
+    }
+
+    /// Frees the PT, PD, PDPT and the PML4T of the user half on the path to `page` if they no
+    /// longer contain any entries.
+    unsafe fn free_unused_tables(&mut self, page: Page) {
+        let (p5_idx, p4_idx, p3_idx, p2_idx, _) = Table::<Level5>::get_table_indices(page);
+        let alloc = &mut self.alloc;
+        let tlb = &mut self.tlb;
+        let user = p5_idx != KERNEL_P5_IDX;
+
+        unsafe {
+            let (p4, shared) = match self.p5.as_deref_mut() {
+                Some(p5) if user => match p5.next_table_mut(p5_idx) {
+                    Some(p4) => (p4, false),
+                    None => return,
+                },
+                _ => (&mut *self.p4, p4_idx >= KERNEL_P4_START_IDX),
+            };
+
+            let p2 = p4
+                .next_table_mut(p4_idx)
+                .and_then(|p3| p3.next_table_mut(p3_idx));
+
+            if let Some(p2) = p2 {
+                free_table_if_unused(p2, p2_idx, alloc, tlb);
+            }
+
+            if let Some(p3) = p4.next_table_mut(p4_idx) {
+                free_table_if_unused(p3, p3_idx, alloc, tlb);
+            }
+
+            if !shared {
+                free_table_if_unused(p4, p4_idx, alloc, tlb);
+            }
+
+            if let Some(p5) = self.p5.as_deref_mut().filter(|_| user) {
+                free_table_if_unused(p5, p5_idx, alloc, tlb);
+            }
+        }
+    }
+}
+
+/// Checks if `page` can be mapped in the current paging mode, see `is_mappable_with()`.
+fn is_mappable(page: Page) -> bool {
+    is_mappable_with(page, virt_addr_bits())
+}
+
+/// Checks if `page` can be mapped with virtual addresses of `bits` bits, i.e. if it is canonical
+/// and does not belong to the recursive mapping area. With five-level paging the higher half only
+/// consists of the last PML5T entry, which references the kernel PML4T.
+fn is_mappable_with(page: Page, bits: usize) -> bool {
+    let (p5_idx, p4_idx, _, _, _) = Table::<Level5>::get_table_indices(page);
+    let recursive = p4_idx == RECURSIVE_P4_IDX;
+
+    let in_layout = if bits > 48 {
+        p5_idx < Table::<Level5>::USER_ENTRIES || (p5_idx == KERNEL_P5_IDX && !recursive)
+    } else {
+        !recursive
+    };
+
+    page.is_canonical_with(bits) && in_layout
+}
+
+/// Checks if both `page` and `frame` are aligned to `size`.
+fn is_aligned(page: Page, frame: Frame, size: PageSize) -> bool {
+    page.to_inner().is_multiple_of(size.num_pages())
+        && frame.to_inner().is_multiple_of(size.num_pages() as Inner)
+}
+
+/// Returns the page size of an entry spanning `num_pages` pages.
+fn page_size(num_pages: usize) -> Option<PageSize> {
+    PageSize::ALL
+        .into_iter()
+        .find(|size| size.num_pages() == num_pages)
+}
+
+/// Checks if `page` belongs to the user half of the address space.
+fn is_user(page: Page) -> bool {
+    page.to_addr().to_inner() < user_end()
+}
+
+/// Creates the entry mapping a page of the given size at `page` to `frame`.
+/// Pages of the user half are accessible from user mode.
+fn leaf_entry(
+    page: Page,
+    frame: Frame,
+    size: PageSize,
+    access: AccessFlags,
+    memory_type: MemoryType,
+) -> Entry {
+    let mut entry = match size {
+        PageSize::Normal => Entry::page_entry(frame.to_addr(), access),
+        _ => Entry::huge_page_entry(frame.to_addr(), access),
+    };
+
+    entry.set_memory_type(memory_type, size);
+
+    if is_user(page) {
+        entry.set_flags(entry.flags() | EntryFlags::USER);
+    }
+
+    entry
+}
+
+/// Writes `new` to the unused `entry`.
+fn set_entry(entry: &mut Entry, new: Entry) -> Result<(), MapError> {
+    if entry.usage() != EntryUsage::None {
+        return Err(MapError::AlreadyMapped);
+    }
+
+    *entry = new;
+    Ok(())
+}
+
+/// Returns the table referenced by `parent[idx]`. If there is no such table,
+/// a new one is allocated from `alloc`. Tables created for the user half
+/// must be accessible from user mode.
+///
+/// # Safety
+/// `parent` must be accessed through the recursive mapping.
+unsafe fn get_or_create_table<'b, L: HierarchicalLevel, A: PageFrameAllocator, F: TlbFlush>(
+    parent: &'b mut Table<L>,
+    idx: usize,
+    alloc: &mut A,
+    tlb: &mut F,
+    user: bool,
+) -> Result<&'b mut Table<L::NextLevel>, MapError> {
+    match parent[idx].usage() {
+        EntryUsage::None => {
+            let frame = alloc.alloc().ok_or(MapError::OutOfMemory)?;
+            let mut entry = Entry::table_entry(frame.to_addr());
+
+            if user {
+                entry.set_flags(entry.flags() | EntryFlags::USER);
+            }
+
+            parent[idx] = entry;
+
+            let table = unsafe { parent.next_table_mut(idx) }.unwrap();
+            tlb.flush(VirtAddr::new(table as *const _ as usize));
+            table.zeroize();
+
+            Ok(table)
+        }
+        EntryUsage::Table => Ok(unsafe { parent.next_table_mut(idx) }.unwrap()),
+        // the page is already part of a huge page
+        EntryUsage::Page => Err(MapError::AlreadyMapped),
+        _ => Err(MapError::InvalidTableLayout),
+    }
+}
+
+/// Frees the table referenced by `parent[idx]` if none of its entries are in use.
+/// Returns whether the table has been freed.
+///
+/// # Safety
+/// `parent` must be accessed through the recursive mapping.
+unsafe fn free_table_if_unused<L: HierarchicalLevel, A: PageFrameAllocator, F: TlbFlush>(
+    parent: &mut Table<L>,
+    idx: usize,
+    alloc: &mut A,
+    tlb: &mut F,
+) -> bool {
+    let table = match unsafe { parent.next_table_mut(idx) } {
+        Some(table) => table,
+        None => return false,
+    };
+
+    if !table.is_unused() {
+        return false;
+    }
+
+    let table_addr = VirtAddr::new(table as *const _ as usize);
+    let frame = parent[idx].frame();
+
+    parent[idx] = Entry::empty();
+    tlb.flush(table_addr);
+    alloc.dealloc(frame);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(addr: usize) -> Page {
+        Page::new(VirtAddr::new(addr))
+    }
+
+    #[test]
+    fn only_canonical_addresses_are_mappable() {
+        assert!(is_mappable_with(page(0x1000), 48));
+        assert!(is_mappable_with(page(0x0000_7fff_ffff_f000), 48));
+        assert!(is_mappable_with(page(KERNEL_BASE), 48));
+
+        assert!(!is_mappable_with(page(0x0000_8000_0000_0000), 48));
+        assert!(!is_mappable_with(page(0x0100_0000_0000_1000), 48));
+        assert!(!is_mappable_with(page(0xff00_0000_0000_0000), 48));
+    }
+
+    #[test]
+    fn the_user_half_spans_56_bits_with_five_level_paging() {
+        assert!(is_mappable_with(page(0x1000), 57));
+        assert!(is_mappable_with(page(0x0000_8000_0000_0000), 57));
+        assert!(is_mappable_with(page(0x00ff_ffff_ffff_f000), 57));
+        assert!(is_mappable_with(page(KERNEL_BASE), 57));
+
+        assert!(!is_mappable_with(page(0x0100_0000_0000_0000), 57));
+
+        // the higher half only consists of the kernel PML4T
+        assert!(!is_mappable_with(page(0xff00_0000_0000_0000), 57));
+        assert!(!is_mappable_with(page(0xff80_0000_0000_0000), 57));
+    }
+
+    #[test]
+    fn the_recursive_mapping_area_is_not_mappable() {
+        for bits in [48, 57] {
+            assert!(!is_mappable_with(page(0xffff_ff80_0000_0000), bits));
+            assert!(!is_mappable_with(page(0xffff_ffff_ffff_f000), bits));
+        }
+
+        // the recursive mapping area of the PML5T
+        assert!(!is_mappable_with(page(0xfffe_ff7f_bfdf_e000), 57));
+    }
+}
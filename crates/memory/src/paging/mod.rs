@@ -1,11 +1,301 @@
 #[cfg(target_arch = "x86_64")]
+mod mapper_x86_64;
+#[cfg(target_arch = "x86_64")]
 mod paging_x86_64;
+#[cfg(target_arch = "x86_64")]
+mod walker_x86_64;
 
+#[cfg(target_arch = "x86")]
+mod mapper_i686;
 #[cfg(target_arch = "x86")]
 mod paging_i686;
+#[cfg(target_arch = "x86")]
+mod walker_i686;
 
+#[cfg(target_arch = "x86_64")]
+pub use mapper_x86_64::*;
 #[cfg(target_arch = "x86_64")]
 pub use paging_x86_64::*;
+#[cfg(target_arch = "x86_64")]
+pub use walker_x86_64::*;
 
+#[cfg(target_arch = "x86")]
+pub use mapper_i686::*;
 #[cfg(target_arch = "x86")]
 pub use paging_i686::*;
+#[cfg(target_arch = "x86")]
+pub use walker_i686::*;
+
+use crate::phys::PhysicalRange;
+use crate::virt::{VirtAddr, VirtualRange};
+use crate::AccessFlags;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// A frame for a page table could not be allocated.
+    OutOfMemory,
+    /// The page is already mapped.
+    AlreadyMapped,
+    /// The page is not mapped.
+    NotMapped,
+    /// The page tables contain an entry with an unexpected usage.
+    InvalidTableLayout,
+    /// The address can not be mapped, i.e. because it belongs to the recursive mapping area.
+    InvalidAddress,
+    /// The virtual and physical range passed to `map_range()` differ in size.
+    SizeMismatch,
+    /// The page or frame is not aligned to the requested page size.
+    Unaligned,
+    /// The requested page size is not supported at this address.
+    UnsupportedPageSize,
+}
+
+/// The memory type of a mapping, which determines how accesses are cached.
+///
+/// The memory type of an entry is selected through its PAT, PCD and PWT bits, which form an index
+/// into the PAT. This requires the PAT to be programmed with `PAT_VALUE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Normal cached memory (WB).
+    WriteBack,
+    /// Reads are cached, writes go directly to memory (WT).
+    WriteThrough,
+    /// Like `Uncached`, but can be overridden to write-combining by the MTRRs (UC-).
+    UncachedMinus,
+    /// Neither reads nor writes are cached. Required for device registers (UC).
+    Uncached,
+    /// Writes are collected in a buffer and reads are not cached. Suitable for framebuffers (WC).
+    WriteCombining,
+    /// Reads are cached, writes invalidate the cache lines on all cores (WP).
+    WriteProtected,
+}
+
+impl MemoryType {
+    /// Returns the index of the PAT entry holding this memory type.
+    pub const fn pat_index(self) -> usize {
+        match self {
+            MemoryType::WriteBack => 0,
+            MemoryType::WriteThrough => 1,
+            MemoryType::UncachedMinus => 2,
+            MemoryType::Uncached => 3,
+            MemoryType::WriteCombining => 4,
+            MemoryType::WriteProtected => 5,
+        }
+    }
+
+    /// Returns the memory type of the PAT entry at `index`.
+    pub const fn from_pat_index(index: usize) -> MemoryType {
+        match index & 0x7 {
+            0 => MemoryType::WriteBack,
+            1 => MemoryType::WriteThrough,
+            2 | 6 => MemoryType::UncachedMinus,
+            3 | 7 => MemoryType::Uncached,
+            4 => MemoryType::WriteCombining,
+            _ => MemoryType::WriteProtected,
+        }
+    }
+
+    /// Checks if this memory type can only be used with a programmed PAT. The other types
+    /// are part of the power-on default of the PAT.
+    pub const fn requires_pat(self) -> bool {
+        self.pat_index() >= 4
+    }
+}
+
+/// The value of the IA32_PAT MSR `MemoryType` relies on. Entries 0 to 3 keep their power-on
+/// defaults (WB, WT, UC-, UC), entry 4 is WC and entry 5 is WP. Entries 6 and 7 are unused.
+pub const PAT_VALUE: u64 = 0x0007_0501_0007_0406;
+
+/// An entry visited while walking the page tables.
+#[derive(Clone, Copy)]
+pub struct WalkEntry {
+    /// The level of the table containing the entry, where 1 is the lowest level.
+    pub level: usize,
+    /// The index of the entry in its table.
+    pub index: usize,
+    pub entry: Entry,
+}
+
+impl core::fmt::Debug for WalkEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "L{}[{}]: {:?}", self.level, self.index, self.entry)
+    }
+}
+
+/// The entries used to translate an address, from the top level table down to the entry
+/// which ended the walk, i.e. an entry which maps a page or is unused.
+#[derive(Debug, Clone, Copy)]
+pub struct Walk {
+    entries: [WalkEntry; PAGING_LEVELS],
+    len: usize,
+}
+
+impl Walk {
+    fn new() -> Self {
+        let empty = WalkEntry {
+            level: 0,
+            index: 0,
+            entry: Entry::empty(),
+        };
+
+        Self {
+            entries: [empty; PAGING_LEVELS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, level: usize, index: usize, entry: Entry) {
+        self.entries[self.len] = WalkEntry {
+            level,
+            index,
+            entry,
+        };
+        self.len += 1;
+    }
+
+    pub fn entries(&self) -> &[WalkEntry] {
+        &self.entries[..self.len]
+    }
+
+    /// Returns the entry which maps the page, if the address is mapped.
+    pub fn leaf(&self) -> Option<&WalkEntry> {
+        self.entries()
+            .last()
+            .filter(|e| e.entry.usage() == EntryUsage::Page)
+    }
+}
+
+/// A range of pages which are mapped to contiguous frames with the same attributes.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub pages: VirtualRange,
+    pub frames: PhysicalRange,
+    /// The size of the pages used for this range.
+    pub page_size: PageSize,
+    pub access: AccessFlags,
+    pub user: bool,
+    pub memory_type: MemoryType,
+}
+
+impl Mapping {
+    /// Appends `next` to this mapping if it directly follows it and has the same attributes.
+    fn try_extend(&mut self, next: &Mapping) -> bool {
+        let contiguous =
+            self.pages.end() == next.pages.start() && self.frames.end() == next.frames.start();
+
+        let same_attributes = self.page_size == next.page_size
+            && self.access.bits() == next.access.bits()
+            && self.user == next.user
+            && self.memory_type == next.memory_type;
+
+        if contiguous && same_attributes {
+            self.pages = VirtualRange::new(self.pages.start(), next.pages.end());
+            self.frames = PhysicalRange::new(self.frames.start(), next.frames.end());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl core::fmt::Display for Mapping {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let access = self.access;
+
+        write!(
+            f,
+            "{:#x}-{:#x} -> {:#x} {}{}{} {} {:?} {}K",
+            self.pages.start_addr().to_inner(),
+            self.pages.end_addr().to_inner(),
+            self.frames.start_addr().to_inner(),
+            if access.contains(AccessFlags::READ) {
+                'r'
+            } else {
+                '-'
+            },
+            if access.contains(AccessFlags::WRITE) {
+                'w'
+            } else {
+                '-'
+            },
+            if access.contains(AccessFlags::EXEC) {
+                'x'
+            } else {
+                '-'
+            },
+            if self.user { "user" } else { "kernel" },
+            self.memory_type,
+            self.page_size.size() / 1024,
+        )
+    }
+}
+
+/// Merges consecutive mappings before passing them on to `f`.
+struct Coalescer<F: FnMut(&Mapping)> {
+    current: Option<Mapping>,
+    f: F,
+}
+
+impl<F: FnMut(&Mapping)> Coalescer<F> {
+    fn new(f: F) -> Self {
+        Self { current: None, f }
+    }
+
+    fn push(&mut self, mapping: Mapping) {
+        if let Some(ref mut current) = self.current {
+            if current.try_extend(&mapping) {
+                return;
+            }
+
+            (self.f)(current);
+        }
+
+        self.current = Some(mapping);
+    }
+
+    fn finish(mut self) {
+        if let Some(ref current) = self.current {
+            (self.f)(current);
+        }
+    }
+}
+
+/// Invalidates TLB entries of the current core after the page tables have been changed.
+///
+/// The `Mapper` flushes every entry it changes through this trait. This allows the kernel to also
+/// invalidate the entries which are cached for other address spaces, e.g. with PCIDs.
+pub trait TlbFlush {
+    /// Invalidates the TLB entry of the page containing `addr`.
+    fn flush(&mut self, addr: VirtAddr);
+
+    /// Invalidates all non-global TLB entries.
+    fn flush_all(&mut self);
+}
+
+/// Flushes the TLB of the current core with `flush_tlb()` and `flush_tlb_all()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalTlbFlush;
+
+impl TlbFlush for LocalTlbFlush {
+    fn flush(&mut self, addr: VirtAddr) {
+        flush_tlb(addr);
+    }
+
+    fn flush_all(&mut self) {
+        flush_tlb_all();
+    }
+}
+
+/// Invalidates the TLB entry of the page containing `addr` on the current core.
+pub fn flush_tlb(addr: VirtAddr) {
+    unsafe {
+        core::arch::asm!("invlpg [{}]", in(reg) addr.to_inner(), options(nostack, preserves_flags));
+    }
+}
+
+/// Invalidates all non-global TLB entries on the current core.
+pub fn flush_tlb_all() {
+    unsafe {
+        core::arch::asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(nostack, preserves_flags));
+    }
+}
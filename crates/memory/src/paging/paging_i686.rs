@@ -3,7 +3,12 @@ use core::marker::PhantomData;
 use bitflags::bitflags;
 use zeroize::Zeroize;
 
-use crate::{phys::PhysAddr, virt::Page, AccessFlags, PAGE_TABLE_ENTRIES};
+use crate::{
+    paging::MemoryType,
+    phys::{Frame, PhysAddr},
+    virt::Page,
+    AccessFlags, LARGE_PAGE_SIZE, PAGE_SIZE, PAGE_TABLE_ENTRIES,
+};
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -18,11 +23,15 @@ pub enum EntryUsage {
     Reserved5 = 7,
 }
 
+/// The number of table levels used for translating an address.
+pub const PAGING_LEVELS: usize = 2;
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Zeroize)]
 pub struct Entry(u32);
 
 bitflags! {
+    #[derive(Debug)]
     pub struct EntryFlags: u32 {
         const PRESENT =         1 << 0;
         const WRITABLE =        1 << 1;
@@ -38,6 +47,8 @@ bitflags! {
 
 impl Entry {
     const USAGE_MASK: u32 = 0xE00;
+    /// The PAT bit of entries mapping huge pages.
+    const HUGE_PAT_BIT: u32 = 1 << 12;
     const USAGE_SHIFT: u32 = 9;
 
     const ADDR_MASK: u32 = 0xfffff000;
@@ -93,6 +104,19 @@ impl Entry {
         self.0 & Self::ADDR_MASK
     }
 
+    /// Get the frame this entry is pointing to.
+    pub fn frame(&self) -> Frame {
+        Frame::new(PhysAddr::new(self.addr()))
+    }
+
+    /// Checks if this entry maps a 4 MiB page instead of referencing a table.
+    ///
+    /// Note: in entries mapping 4 KiB pages the same bit selects the memory type, thus
+    /// this is only meaningful for entries above the lowest level.
+    pub fn is_huge(&self) -> bool {
+        self.usage() == EntryUsage::Page && self.flags().contains(EntryFlags::PAGE_SIZE)
+    }
+
     /// Set the physical address this entry should point to.
     pub fn set_addr(&mut self, addr: u32) {
         // clear all addr bits
@@ -135,6 +159,84 @@ impl Entry {
         entry
     }
 
+    /// Returns the access permitted by a present entry, or no access if it is not present.
+    pub fn access(&self) -> AccessFlags {
+        let flags = self.flags();
+        let mut access = AccessFlags::empty();
+
+        if !flags.contains(EntryFlags::PRESENT) {
+            return access;
+        }
+
+        access.insert(AccessFlags::READ);
+
+        if flags.contains(EntryFlags::WRITABLE) {
+            access.insert(AccessFlags::WRITE);
+        }
+
+        // there is no no-execute bit without PAE
+        access.insert(AccessFlags::EXEC);
+
+        access
+    }
+
+    /// Returns the memory type of an entry mapping a page of the given size.
+    pub fn memory_type(&self, size: PageSize) -> MemoryType {
+        let flags = self.flags();
+
+        let pat = match size {
+            PageSize::Normal => flags.contains(EntryFlags::PAGE_SIZE),
+            _ => self.0 & Self::HUGE_PAT_BIT != 0,
+        };
+
+        let index = (pat as usize) << 2
+            | (flags.contains(EntryFlags::NO_CACHE) as usize) << 1
+            | flags.contains(EntryFlags::WRITE_THROUGH) as usize;
+
+        MemoryType::from_pat_index(index)
+    }
+
+    /// Sets the memory type of an entry mapping a page of the given size.
+    ///
+    /// Note: in entries mapping 4 KiB pages the PAT bit is the `PAGE_SIZE` bit, while in entries
+    /// mapping huge pages it is the lowest address bit, which is always zero for huge frames.
+    pub fn set_memory_type(&mut self, memory_type: MemoryType, size: PageSize) {
+        let index = memory_type.pat_index();
+        let pat = index & 0x4 != 0;
+
+        let mut flags = self.flags();
+        flags.set(EntryFlags::WRITE_THROUGH, index & 0x1 != 0);
+        flags.set(EntryFlags::NO_CACHE, index & 0x2 != 0);
+
+        match size {
+            PageSize::Normal => flags.set(EntryFlags::PAGE_SIZE, pat),
+            _ if pat => self.0 |= Self::HUGE_PAT_BIT,
+            _ => self.0 &= !Self::HUGE_PAT_BIT,
+        }
+
+        self.set_flags(flags);
+    }
+
+    /// Returns the first frame of the page of the given size mapped by this entry.
+    /// Unlike `frame()` this excludes the PAT bit of huge pages.
+    pub fn page_frame(&self, size: PageSize) -> Frame {
+        match size {
+            PageSize::Normal => self.frame(),
+            _ => Frame::new(PhysAddr::new(self.addr() & !Self::HUGE_PAT_BIT)),
+        }
+    }
+
+    /// Creates a PD entry mapping a 4 MiB page frame located at `addr`
+    /// with access flags according to `access`.
+    /// The entry's usage will be `EntryUsage::Page`.
+    ///
+    /// Note: 4 MiB pages require CR4.PSE to be set.
+    pub fn huge_page_entry(addr: PhysAddr, access: AccessFlags) -> Self {
+        let mut entry = Self::page_entry(addr, access);
+        entry.set_flags(entry.flags() | EntryFlags::PAGE_SIZE);
+        entry
+    }
+
     /// Creates a table entry pointing to the table located at `addr`.
     /// This entry will have a usage of `EntryUsage::Table`.
     pub fn table_entry(addr: PhysAddr) -> Self {
@@ -146,6 +248,16 @@ impl Entry {
     }
 }
 
+impl core::fmt::Debug for Entry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Entry")
+            .field("addr", &format_args!("{:#x}", self.addr()))
+            .field("usage", &self.usage())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
 pub enum Level {
     Level1,
     Level2,
@@ -161,6 +273,33 @@ pub enum Level1 {}
 /// Level2 represents the page directory (PD).
 pub enum Level2 {}
 
+/// The sizes of the pages that can be mapped.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum PageSize {
+    /// A 4 KiB page mapped by a PT entry.
+    Normal,
+    /// A 4 MiB page mapped by a PD entry.
+    Large,
+}
+
+impl PageSize {
+    /// All page sizes from the largest to the smallest.
+    pub const ALL: [PageSize; 2] = [PageSize::Large, PageSize::Normal];
+
+    /// The size in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            PageSize::Normal => PAGE_SIZE,
+            PageSize::Large => LARGE_PAGE_SIZE,
+        }
+    }
+
+    /// The number of 4 KiB pages a page of this size spans.
+    pub const fn num_pages(self) -> usize {
+        self.size() / PAGE_SIZE
+    }
+}
+
 impl TableLevel for Level1 {
     const LEVEL: Level = Level::Level1;
 }
@@ -193,6 +332,15 @@ impl<L: TableLevel> core::ops::IndexMut<usize> for Table<L> {
     }
 }
 
+impl<L: TableLevel> Table<L> {
+    /// Checks if all entries of this table have `EntryUsage::None`.
+    pub fn is_unused(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| entry.usage() == EntryUsage::None)
+    }
+}
+
 impl Table<Level2> {
     /// This function calculates the address of a next table using the
     /// recursive mapping technique.
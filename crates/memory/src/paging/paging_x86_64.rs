@@ -1,9 +1,15 @@
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use bitflags::bitflags;
 use zeroize::Zeroize;
 
-use crate::{phys::PhysAddr, virt::Page, AccessFlags, FRAME_SIZE, PAGE_TABLE_ENTRIES};
+use crate::{
+    paging::MemoryType,
+    phys::{Frame, PhysAddr},
+    virt::Page,
+    AccessFlags, FRAME_SIZE, GIANT_PAGE_SIZE, LARGE_PAGE_SIZE, PAGE_SIZE, PAGE_TABLE_ENTRIES,
+};
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -18,11 +24,57 @@ pub enum EntryUsage {
     Reserved5 = 7,
 }
 
+/// The maximum number of table levels used for translating an address.
+pub const PAGING_LEVELS: usize = 5;
+
+/// The bit in CR4 which enables five-level paging (LA57).
+const CR4_LA57: usize = 1 << 12;
+
+/// The number of levels of the paging mode, or zero until it has been read from CR4.
+static PAGING_MODE: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks if five-level paging is enabled, i.e. if the root table is a PML5T.
+///
+/// Note: CR4 is only read on the first call. The paging mode can not be changed in long mode and
+/// the application processors use the paging mode of the bootstrap processor.
+pub fn five_level_paging() -> bool {
+    let mut levels = PAGING_MODE.load(Ordering::Relaxed);
+
+    if levels == 0 {
+        let cr4: usize;
+
+        unsafe {
+            core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        }
+
+        levels = if cr4 & CR4_LA57 != 0 { 5 } else { 4 };
+        PAGING_MODE.store(levels, Ordering::Relaxed);
+    }
+
+    levels == 5
+}
+
+/// Returns the number of implemented bits of a virtual address in the current paging mode.
+pub fn virt_addr_bits() -> usize {
+    if five_level_paging() {
+        57
+    } else {
+        48
+    }
+}
+
+/// Sign extends the lowest `bits` bits of `addr` to a canonical address.
+pub(crate) const fn canonicalize(addr: usize, bits: usize) -> usize {
+    let shift = usize::BITS as usize - bits;
+    (((addr << shift) as isize) >> shift) as usize
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Zeroize)]
 pub struct Entry(u64);
 
 bitflags! {
+    #[derive(Debug)]
     pub struct EntryFlags: u64 {
         const PRESENT =         1 << 0;
         const WRITABLE =        1 << 1;
@@ -39,6 +91,8 @@ bitflags! {
 
 impl Entry {
     const USAGE_MASK: u64 = 0xE00;
+    /// The PAT bit of entries mapping huge pages.
+    const HUGE_PAT_BIT: u64 = 1 << 12;
     const USAGE_SHIFT: u64 = 9;
 
     const ADDR_MASK: u64 = 0x000fffff_fffff000;
@@ -94,6 +148,19 @@ impl Entry {
         self.0 & Self::ADDR_MASK
     }
 
+    /// Get the frame this entry is pointing to.
+    pub fn frame(&self) -> Frame {
+        Frame::new(PhysAddr::new(self.addr()))
+    }
+
+    /// Checks if this entry maps a 2 MiB or 1 GiB page instead of referencing a table.
+    ///
+    /// Note: in entries mapping 4 KiB pages the same bit selects the memory type, thus
+    /// this is only meaningful for entries above the lowest level.
+    pub fn is_huge(&self) -> bool {
+        self.usage() == EntryUsage::Page && self.flags().contains(EntryFlags::PAGE_SIZE)
+    }
+
     /// Set the physical address this entry should point to.
     pub fn set_addr(&mut self, addr: u64) {
         // clear all addr bits
@@ -128,11 +195,10 @@ impl Entry {
             flags.insert(EntryFlags::WRITABLE);
         }
 
-        //TODO: only insert NO_EXECUTE once this feature is enabled
-        /*
+        // Note: this requires EFER.NXE to be set
         if !access.contains(AccessFlags::EXEC) {
             flags.insert(EntryFlags::NO_EXECUTE);
-        }*/
+        }
 
         let mut entry = Entry(0);
         entry.set_addr(addr.to_inner());
@@ -142,6 +208,84 @@ impl Entry {
         entry
     }
 
+    /// Returns the access permitted by a present entry, or no access if it is not present.
+    pub fn access(&self) -> AccessFlags {
+        let flags = self.flags();
+        let mut access = AccessFlags::empty();
+
+        if !flags.contains(EntryFlags::PRESENT) {
+            return access;
+        }
+
+        access.insert(AccessFlags::READ);
+
+        if flags.contains(EntryFlags::WRITABLE) {
+            access.insert(AccessFlags::WRITE);
+        }
+
+        // Note: this requires EFER.NXE to be set
+        if !flags.contains(EntryFlags::NO_EXECUTE) {
+            access.insert(AccessFlags::EXEC);
+        }
+
+        access
+    }
+
+    /// Returns the memory type of an entry mapping a page of the given size.
+    pub fn memory_type(&self, size: PageSize) -> MemoryType {
+        let flags = self.flags();
+
+        let pat = match size {
+            PageSize::Normal => flags.contains(EntryFlags::PAGE_SIZE),
+            _ => self.0 & Self::HUGE_PAT_BIT != 0,
+        };
+
+        let index = (pat as usize) << 2
+            | (flags.contains(EntryFlags::NO_CACHE) as usize) << 1
+            | flags.contains(EntryFlags::WRITE_THROUGH) as usize;
+
+        MemoryType::from_pat_index(index)
+    }
+
+    /// Sets the memory type of an entry mapping a page of the given size.
+    ///
+    /// Note: in entries mapping 4 KiB pages the PAT bit is the `PAGE_SIZE` bit, while in entries
+    /// mapping huge pages it is the lowest address bit, which is always zero for huge frames.
+    pub fn set_memory_type(&mut self, memory_type: MemoryType, size: PageSize) {
+        let index = memory_type.pat_index();
+        let pat = index & 0x4 != 0;
+
+        let mut flags = self.flags();
+        flags.set(EntryFlags::WRITE_THROUGH, index & 0x1 != 0);
+        flags.set(EntryFlags::NO_CACHE, index & 0x2 != 0);
+
+        match size {
+            PageSize::Normal => flags.set(EntryFlags::PAGE_SIZE, pat),
+            _ if pat => self.0 |= Self::HUGE_PAT_BIT,
+            _ => self.0 &= !Self::HUGE_PAT_BIT,
+        }
+
+        self.set_flags(flags);
+    }
+
+    /// Returns the first frame of the page of the given size mapped by this entry.
+    /// Unlike `frame()` this excludes the PAT bit of huge pages.
+    pub fn page_frame(&self, size: PageSize) -> Frame {
+        match size {
+            PageSize::Normal => self.frame(),
+            _ => Frame::new(PhysAddr::new(self.addr() & !Self::HUGE_PAT_BIT)),
+        }
+    }
+
+    /// Creates a PD or PDPT entry mapping a 2 MiB or 1 GiB page frame located at `addr`
+    /// with access flags according to `access`.
+    /// The entry's usage will be `EntryUsage::Page`.
+    pub fn huge_page_entry(addr: PhysAddr, access: AccessFlags) -> Self {
+        let mut entry = Self::page_entry(addr, access);
+        entry.set_flags(entry.flags() | EntryFlags::PAGE_SIZE);
+        entry
+    }
+
     /// Creates a table entry pointing to the table located at `addr`.
     /// This entry will have a usage of `EntryUsage::Table`.
     pub fn table_entry(addr: PhysAddr) -> Self {
@@ -153,11 +297,22 @@ impl Entry {
     }
 }
 
+impl core::fmt::Debug for Entry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Entry")
+            .field("addr", &format_args!("{:#x}", self.addr()))
+            .field("usage", &self.usage())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
 pub enum Level {
     Level1,
     Level2,
     Level3,
     Level4,
+    Level5,
 }
 
 pub trait TableLevel {
@@ -184,6 +339,9 @@ pub enum Level3 {}
 /// Level4 represents the page map level 4 table (PML4T).
 pub enum Level4 {}
 
+/// Level5 represents the page map level 5 table (PML5T).
+pub enum Level5 {}
+
 impl TableLevel for Level1 {
     const LEVEL: Level = Level::Level1;
 }
@@ -200,6 +358,10 @@ impl TableLevel for Level4 {
     const LEVEL: Level = Level::Level4;
 }
 
+impl TableLevel for Level5 {
+    const LEVEL: Level = Level::Level5;
+}
+
 impl PagingLevel for Level1 {
     const PAGE_FRAME_SIZE: u64 = FRAME_SIZE; // 4KiB
 }
@@ -212,6 +374,40 @@ impl PagingLevel for Level3 {
     const PAGE_FRAME_SIZE: u64 = FRAME_SIZE * 512 * 512; // 1GiB
 }
 
+/// The sizes of the pages that can be mapped.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum PageSize {
+    /// A 4 KiB page mapped by a PT entry.
+    Normal,
+    /// A 2 MiB page mapped by a PD entry.
+    Large,
+    /// A 1 GiB page mapped by a PDPT entry.
+    Giant,
+}
+
+impl PageSize {
+    /// All page sizes from the largest to the smallest.
+    pub const ALL: [PageSize; 3] = [PageSize::Giant, PageSize::Large, PageSize::Normal];
+
+    /// The size in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            PageSize::Normal => PAGE_SIZE,
+            PageSize::Large => LARGE_PAGE_SIZE,
+            PageSize::Giant => GIANT_PAGE_SIZE,
+        }
+    }
+
+    /// The number of 4 KiB pages a page of this size spans.
+    pub const fn num_pages(self) -> usize {
+        self.size() / PAGE_SIZE
+    }
+}
+
+impl HierarchicalLevel for Level5 {
+    type NextLevel = Level4;
+}
+
 impl HierarchicalLevel for Level4 {
     type NextLevel = Level3;
 }
@@ -224,6 +420,12 @@ impl HierarchicalLevel for Level2 {
     type NextLevel = Level1;
 }
 
+/// Returns the address of the table referenced by the entry at `index` of the recursively mapped
+/// table at `table_addr` in the layout with virtual addresses of `bits` bits.
+const fn recursive_next_table_address(table_addr: usize, index: usize, bits: usize) -> usize {
+    canonicalize((table_addr << 9) | (index << 12), bits)
+}
+
 /// A `Table` represents any of the x86_64 paging tables.
 /// The type argument `L` defines which paging structure it refers to.
 /// All `Table` structs have a size of 4KiB.
@@ -248,6 +450,15 @@ impl<L: TableLevel> core::ops::IndexMut<usize> for Table<L> {
     }
 }
 
+impl<L: TableLevel> Table<L> {
+    /// Checks if all entries of this table have `EntryUsage::None`.
+    pub fn is_unused(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| entry.usage() == EntryUsage::None)
+    }
+}
+
 impl<L: HierarchicalLevel> Table<L> {
     /// This function calculates the address of a next table using the
     /// recursive mapping technique.
@@ -256,7 +467,7 @@ impl<L: HierarchicalLevel> Table<L> {
     /// This function can only return a reliable address if `self` is a
     /// table using recursive mapping and recursive mapping is properly set up.
     unsafe fn next_table_address_unchecked(&self, index: usize) -> usize {
-        ((self as *const _ as usize) << 9) | (index << 12)
+        recursive_next_table_address(self as *const _ as usize, index, virt_addr_bits())
     }
 
     /// Calculates the virtual address of the table at `index`.
@@ -314,3 +525,128 @@ impl Table<Level4> {
         (p4, p3, p2, p1)
     }
 }
+
+impl Table<Level5> {
+    /// The number of entries covering the user half. Each of them references a PML4T of its own.
+    pub const USER_ENTRIES: usize = PAGE_TABLE_ENTRIES / 2;
+
+    /// The index of the entry used for recursive mapping.
+    ///
+    /// Note: the last entry is taken by the kernel PML4T, which is recursively mapped by its own
+    /// last entry.
+    pub const RECURSIVE_IDX: usize = PAGE_TABLE_ENTRIES - 2;
+
+    /// The index of the entry referencing the PML4T of the higher half.
+    ///
+    /// Since the sign extension bits of a 48-bit higher half address are the same as this index,
+    /// `KERNEL_BASE`, the kernel PML4T entries and their recursive mapping stay valid with
+    /// five-level paging.
+    pub const KERNEL_P4_IDX: usize = PAGE_TABLE_ENTRIES - 1;
+
+    /// This function calculates the indices into the 5 paging tables from the given address.
+    pub fn get_table_indices(page: Page) -> (usize, usize, usize, usize, usize) {
+        let (p4, p3, p2, p1) = Table::<Level4>::get_table_indices(page);
+        let p5 = (page.to_addr().to_inner() >> 48) & 0x1ff;
+
+        (p5, p4, p3, p2, p1)
+    }
+
+    /// Sets the entries referencing the PML4T of the first 256 TiB of the lower half and the
+    /// PML4T of the higher half. The lower half is accessible from user mode.
+    ///
+    /// Note: the two PML4T's must be different tables, otherwise each half would also be visible
+    /// in the other one.
+    pub fn set_p4s(&mut self, user_p4_addr: PhysAddr, kernel_p4_addr: PhysAddr) {
+        assert_ne!(user_p4_addr, kernel_p4_addr);
+
+        let mut user_entry = Entry::table_entry(user_p4_addr);
+        user_entry.set_flags(user_entry.flags() | EntryFlags::USER);
+
+        self[0] = user_entry;
+        self[Self::KERNEL_P4_IDX] = Entry::table_entry(kernel_p4_addr);
+    }
+
+    /// Sets the recursive entry to the PML5T at `p5_addr`, i.e. this table, and references the
+    /// PML4T of the higher half. The PML4T's of the user half are created by the `Mapper`.
+    pub fn set_recursive_and_kernel_p4(&mut self, p5_addr: PhysAddr, kernel_p4_addr: PhysAddr) {
+        self[Self::RECURSIVE_IDX] = Entry::table_entry(p5_addr);
+        self[Self::KERNEL_P4_IDX] = Entry::table_entry(kernel_p4_addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virt::VirtAddr;
+
+    /// Returns the address of the PT mapping `addr` by following the recursive mapping from the
+    /// PML4T at `p4_addr`.
+    fn p1_address(p4_addr: usize, addr: usize, bits: usize) -> usize {
+        let (p4_idx, p3_idx, p2_idx, _) =
+            Table::<Level4>::get_table_indices(Page::new(VirtAddr::new(addr)));
+
+        let p3 = recursive_next_table_address(p4_addr, p4_idx, bits);
+        let p2 = recursive_next_table_address(p3, p3_idx, bits);
+        recursive_next_table_address(p2, p2_idx, bits)
+    }
+
+    #[test]
+    fn recursive_addresses_of_the_higher_half() {
+        let p4_addr = 0xffff_ffff_ffff_f000;
+
+        for bits in [48, 57] {
+            assert_eq!(
+                recursive_next_table_address(p4_addr, 3, bits),
+                0xffff_ffff_ffe0_3000
+            );
+            assert_eq!(
+                p1_address(p4_addr, 0xffff_f000_1234_5000, bits),
+                0xffff_ff80_0000_0000 | ((0xffff_f000_1234_5000 >> 9) & 0x7f_ffff_f000)
+            );
+        }
+    }
+
+    #[test]
+    fn recursive_addresses_of_the_user_half_with_five_level_paging() {
+        // the PML5T is mapped by its entry 510
+        let p5_addr = canonicalize(0x1fe_ff7f_bfdf_e000, 57);
+        assert_eq!(p5_addr, 0xfffe_ff7f_bfdf_e000);
+
+        let addr = 0x00ab_cd12_3456_7000;
+        let (p5_idx, p4_idx, p3_idx, p2_idx, _) =
+            Table::<Level5>::get_table_indices(Page::new(VirtAddr::new(addr)));
+
+        let p4 = recursive_next_table_address(p5_addr, p5_idx, 57);
+        assert_eq!(p4, (0xfffe_ff7f_bfdf_e000 & !0x1ff000) | (p5_idx << 12));
+
+        let p3 = recursive_next_table_address(p4, p4_idx, 57);
+        let p2 = recursive_next_table_address(p3, p3_idx, 57);
+        let p1 = recursive_next_table_address(p2, p2_idx, 57);
+
+        // the PT is found by shifting the address through the recursive entry
+        assert_eq!(
+            p1,
+            0xfffe_0000_0000_0000 | ((addr >> 9) & 0x00ff_ffff_ffff_f000)
+        );
+    }
+
+    #[test]
+    fn canonical_addresses() {
+        assert_eq!(
+            canonicalize(0x0000_8000_0000_0000, 48),
+            0xffff_8000_0000_0000
+        );
+        assert_eq!(
+            canonicalize(0x0000_7fff_ffff_f000, 48),
+            0x0000_7fff_ffff_f000
+        );
+        assert_eq!(
+            canonicalize(0x0000_8000_0000_0000, 57),
+            0x0000_8000_0000_0000
+        );
+        assert_eq!(
+            canonicalize(0x0100_0000_0000_0000, 57),
+            0xff00_0000_0000_0000
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    paging::{
+        mapper_i686::RECURSIVE_P2_IDX, Coalescer, Entry, EntryFlags, EntryUsage, Level2, Mapping,
+        PageSize, Table, Walk,
+    },
+    phys::PhysicalRange,
+    virt::{Page, VirtAddr, VirtualRange},
+    PAGE_TABLE_ENTRIES,
+};
+
+/// The `Walker` inspects the page tables of the active address space through the recursive
+/// mapping without modifying them.
+pub struct Walker<'a> {
+    p2: &'a Table<Level2>,
+}
+
+impl<'a> Walker<'a> {
+    /// Creates a new `Walker`.
+    ///
+    /// # Safety
+    /// `p2` must be the recursively mapped PD and the tables must not be modified while the
+    /// `Walker` is in use.
+    pub unsafe fn new(p2: &'a Table<Level2>) -> Self {
+        Self { p2 }
+    }
+
+    /// Returns the entries of all levels which are used to translate `page`.
+    pub fn walk(&self, page: Page) -> Walk {
+        let (p2_idx, p1_idx) = Table::<Level2>::get_table_indices(page);
+        let mut walk = Walk::new();
+
+        walk.push(2, p2_idx, self.p2[p2_idx]);
+
+        // the recursive entry would be interpreted as a PT
+        if p2_idx == RECURSIVE_P2_IDX {
+            return walk;
+        }
+
+        let Some(p1) = (unsafe { self.p2.next_table(p2_idx) }) else {
+            return walk;
+        };
+
+        walk.push(1, p1_idx, p1[p1_idx]);
+        walk
+    }
+
+    /// Calls `f` for every mapped range in ascending order. Consecutive pages mapped to
+    /// contiguous frames with the same attributes are reported as a single `Mapping`.
+    ///
+    /// The recursive mapping area is skipped.
+    pub fn for_each_mapping(&self, f: impl FnMut(&Mapping)) {
+        let mut coalescer = Coalescer::new(f);
+
+        for p2_idx in (0..PAGE_TABLE_ENTRIES).filter(|idx| *idx != RECURSIVE_P2_IDX) {
+            visit_leaf(
+                &mut coalescer,
+                (p2_idx, 0),
+                &self.p2[p2_idx],
+                PageSize::Large,
+            );
+
+            let Some(p1) = (unsafe { self.p2.next_table(p2_idx) }) else {
+                continue;
+            };
+
+            for p1_idx in 0..PAGE_TABLE_ENTRIES {
+                visit_leaf(
+                    &mut coalescer,
+                    (p2_idx, p1_idx),
+                    &p1[p1_idx],
+                    PageSize::Normal,
+                );
+            }
+        }
+
+        coalescer.finish();
+    }
+}
+
+/// Reports `entry` if it maps a page of the given size.
+fn visit_leaf<F: FnMut(&Mapping)>(
+    coalescer: &mut Coalescer<F>,
+    (p2_idx, p1_idx): (usize, usize),
+    entry: &Entry,
+    size: PageSize,
+) {
+    if entry.usage() != EntryUsage::Page {
+        return;
+    }
+
+    let flags = entry.flags();
+    let num_pages = size.num_pages();
+    let page = Page::new(VirtAddr::new(p2_idx << 22 | p1_idx << 12));
+
+    coalescer.push(Mapping {
+        pages: VirtualRange::with_size(page, num_pages),
+        frames: PhysicalRange::with_size(entry.page_frame(size), num_pages as u32),
+        page_size: size,
+        access: entry.access(),
+        user: flags.contains(EntryFlags::USER),
+        memory_type: entry.memory_type(size),
+    });
+}
@@ -0,0 +1,179 @@
+use crate::{
+    paging::{
+        canonicalize, mapper_x86_64::RECURSIVE_P4_IDX, virt_addr_bits, Coalescer, Entry,
+        EntryFlags, EntryUsage, Level4, Level5, Mapping, PageSize, Table, Walk,
+    },
+    phys::PhysicalRange,
+    virt::{Page, VirtAddr, VirtualRange},
+    PAGE_TABLE_ENTRIES,
+};
+
+/// The `Walker` inspects the page tables of the active address space through the recursive
+/// mapping without modifying them.
+pub struct Walker<'a> {
+    p4: &'a Table<Level4>,
+    /// The PML5T with five-level paging, see `Mapper`.
+    p5: Option<&'a Table<Level5>>,
+}
+
+impl<'a> Walker<'a> {
+    /// Creates a new `Walker`.
+    ///
+    /// # Safety
+    /// - `p4` and `p5` must be the recursively mapped tables as for `Mapper::new()`
+    /// - the tables must not be modified while the `Walker` is in use
+    pub unsafe fn new(p4: &'a Table<Level4>, p5: Option<&'a Table<Level5>>) -> Self {
+        Self { p4, p5 }
+    }
+
+    /// Returns the entries of all levels which are used to translate `page`.
+    /// The walk is empty if `page` is not canonical.
+    pub fn walk(&self, page: Page) -> Walk {
+        let (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx) = Table::<Level5>::get_table_indices(page);
+        let mut walk = Walk::new();
+
+        if !page.is_canonical() {
+            return walk;
+        }
+
+        let p4 = match self.p5 {
+            Some(p5) => {
+                walk.push(5, p5_idx, p5[p5_idx]);
+
+                match p5_idx {
+                    // the recursive entry would be interpreted as a PML4T
+                    Table::<Level5>::RECURSIVE_IDX => return walk,
+                    Table::<Level5>::KERNEL_P4_IDX => self.p4,
+                    _ => match unsafe { p5.next_table(p5_idx) } {
+                        Some(p4) => p4,
+                        None => return walk,
+                    },
+                }
+            }
+            None => self.p4,
+        };
+
+        walk.push(4, p4_idx, p4[p4_idx]);
+
+        // the recursive entry would be interpreted as a PDPT
+        if core::ptr::eq(p4, self.p4) && p4_idx == RECURSIVE_P4_IDX {
+            return walk;
+        }
+
+        let Some(p3) = (unsafe { p4.next_table(p4_idx) }) else {
+            return walk;
+        };
+
+        walk.push(3, p3_idx, p3[p3_idx]);
+
+        let Some(p2) = (unsafe { p3.next_table(p3_idx) }) else {
+            return walk;
+        };
+
+        walk.push(2, p2_idx, p2[p2_idx]);
+
+        let Some(p1) = (unsafe { p2.next_table(p2_idx) }) else {
+            return walk;
+        };
+
+        walk.push(1, p1_idx, p1[p1_idx]);
+        walk
+    }
+
+    /// Calls `f` for every mapped range in ascending order. Consecutive pages mapped to
+    /// contiguous frames with the same attributes are reported as a single `Mapping`.
+    ///
+    /// The recursive mapping areas are skipped.
+    pub fn for_each_mapping(&self, f: impl FnMut(&Mapping)) {
+        let mut coalescer = Coalescer::new(f);
+
+        match self.p5 {
+            Some(p5) => {
+                for p5_idx in 0..Table::<Level5>::USER_ENTRIES {
+                    if let Some(p4) = unsafe { p5.next_table(p5_idx) } {
+                        visit_p4(&mut coalescer, p5_idx, p4, None);
+                    }
+                }
+
+                let p5_idx = Table::<Level5>::KERNEL_P4_IDX;
+                visit_p4(&mut coalescer, p5_idx, self.p4, Some(RECURSIVE_P4_IDX));
+            }
+            None => visit_p4(&mut coalescer, 0, self.p4, Some(RECURSIVE_P4_IDX)),
+        }
+
+        coalescer.finish();
+    }
+}
+
+/// Reports the mappings of the PML4T `p4` referenced by the PML5T entry at `p5_idx`. The entry at
+/// `recursive_idx` is skipped.
+fn visit_p4<F: FnMut(&Mapping)>(
+    coalescer: &mut Coalescer<F>,
+    p5_idx: usize,
+    p4: &Table<Level4>,
+    recursive_idx: Option<usize>,
+) {
+    for p4_idx in (0..PAGE_TABLE_ENTRIES).filter(|idx| Some(*idx) != recursive_idx) {
+        let Some(p3) = (unsafe { p4.next_table(p4_idx) }) else {
+            continue;
+        };
+
+        for p3_idx in 0..PAGE_TABLE_ENTRIES {
+            let indices = (p5_idx, p4_idx, p3_idx, 0, 0);
+            visit_leaf(coalescer, indices, &p3[p3_idx], PageSize::Giant);
+
+            let Some(p2) = (unsafe { p3.next_table(p3_idx) }) else {
+                continue;
+            };
+
+            for p2_idx in 0..PAGE_TABLE_ENTRIES {
+                let indices = (p5_idx, p4_idx, p3_idx, p2_idx, 0);
+                visit_leaf(coalescer, indices, &p2[p2_idx], PageSize::Large);
+
+                let Some(p1) = (unsafe { p2.next_table(p2_idx) }) else {
+                    continue;
+                };
+
+                for p1_idx in 0..PAGE_TABLE_ENTRIES {
+                    let indices = (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx);
+                    visit_leaf(coalescer, indices, &p1[p1_idx], PageSize::Normal);
+                }
+            }
+        }
+    }
+}
+
+/// Reports `entry` if it maps a page of the given size.
+fn visit_leaf<F: FnMut(&Mapping)>(
+    coalescer: &mut Coalescer<F>,
+    indices: (usize, usize, usize, usize, usize),
+    entry: &Entry,
+    size: PageSize,
+) {
+    if entry.usage() != EntryUsage::Page {
+        return;
+    }
+
+    let flags = entry.flags();
+    let num_pages = size.num_pages();
+
+    coalescer.push(Mapping {
+        pages: VirtualRange::with_size(page_from_indices(indices), num_pages),
+        frames: PhysicalRange::with_size(entry.page_frame(size), num_pages as u64),
+        page_size: size,
+        access: entry.access(),
+        user: flags.contains(EntryFlags::USER),
+        memory_type: entry.memory_type(size),
+    });
+}
+
+/// Returns the page translated through the given table indices. The index of the PML5T is
+/// ignored with four-level paging.
+fn page_from_indices(
+    (p5_idx, p4_idx, p3_idx, p2_idx, p1_idx): (usize, usize, usize, usize, usize),
+) -> Page {
+    let addr = p5_idx << 48 | p4_idx << 39 | p3_idx << 30 | p2_idx << 21 | p1_idx << 12;
+
+    // sign extend to a canonical address
+    Page::new(VirtAddr::new(canonicalize(addr, virt_addr_bits())))
+}
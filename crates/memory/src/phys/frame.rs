@@ -61,10 +61,14 @@ impl Frame {
 }
 
 impl core::iter::Step for Frame {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> {
-        let diff = end.to_inner().checked_sub(start.to_inner())?;
-        let diff = diff.try_into().ok()?;
-        Some(diff)
+    fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
+        match end.to_inner().checked_sub(start.to_inner()) {
+            Some(diff) => match diff.try_into() {
+                Ok(diff) => (diff, Some(diff)),
+                Err(_) => (usize::MAX, None),
+            },
+            None => (0, None),
+        }
     }
 
     fn forward_checked(start: Self, count: usize) -> Option<Self> {
@@ -1,6 +1,36 @@
-use crate::phys::Frame;
+use crate::phys::{Frame, PhysAddr, PhysicalRange};
 use alloc::boxed::Box;
 
+/// An upper bound for the physical address of allocated memory.
+/// Legacy ISA DMA can only reach the first 16 MiB and many PCI devices only the first 4 GiB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressLimit {
+    Below16M,
+    Below4G,
+}
+
+impl AddressLimit {
+    /// Returns the first frame which is no longer below the limit or `None` if the limit
+    /// covers the whole physical address space.
+    pub fn end(self) -> Option<Frame> {
+        let end: u64 = match self {
+            AddressLimit::Below16M => 0x100_0000,
+            AddressLimit::Below4G => 0x1_0000_0000,
+        };
+
+        let end = end.try_into().ok()?;
+        Some(Frame::new(PhysAddr::new(end)))
+    }
+
+    /// Checks if the whole `range` lies below this limit.
+    pub fn allows(self, range: PhysicalRange) -> bool {
+        match self.end() {
+            Some(end) => range.end() <= end,
+            None => true,
+        }
+    }
+}
+
 pub trait PageFrameAllocator {
     /// Allocates a frame from this allocator.
     fn alloc(&mut self) -> Option<Frame>;
@@ -8,6 +38,20 @@ pub trait PageFrameAllocator {
     /// Allocate multiple frames at once from this allocator.
     fn alloc_multiple(&mut self, num_frames: usize) -> Option<Box<[Frame]>>;
 
+    /// Allocate physically contiguous frames, i.e. for DMA buffers.
+    /// # Parameters
+    /// - `num_frames` the number of frames in the returned range
+    /// - `alignment` the requested alignment in frames, must be a power of two
+    /// - `limit` an optional upper bound for the physical addresses of the range
+    ///
+    /// The frames of the returned range are released individually using `dealloc()`.
+    fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange>;
+
     /// Mark a specific frame as allocated. If that frame has already been allocated previously
     /// `None` is returned. This function is useful to get memory for a specific address i.e. device
     /// memory or a frame buffer.
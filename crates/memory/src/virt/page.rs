@@ -12,10 +12,17 @@ impl Page {
         Page(addr.to_inner() >> PAGE_SHIFT)
     }
 
+    /// Checks if this page is canonical in the current paging mode.
     #[cfg(target_arch = "x86_64")]
-    pub const fn is_canonical(&self) -> bool {
+    pub fn is_canonical(&self) -> bool {
+        self.is_canonical_with(crate::paging::virt_addr_bits())
+    }
+
+    /// Checks if this page is canonical for virtual addresses of `bits` bits.
+    #[cfg(target_arch = "x86_64")]
+    pub const fn is_canonical_with(&self, bits: usize) -> bool {
         let addr = self.to_addr().to_inner();
-        addr < 0x0000_8000_0000_0000 || addr >= 0xffff_8000_0000_0000
+        crate::paging::canonicalize(addr, bits) == addr
     }
 
     pub const fn from_inner(inner: Inner) -> Self {
@@ -66,9 +73,11 @@ impl Page {
 }
 
 impl core::iter::Step for Page {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> {
-        let diff = end.to_inner().checked_sub(start.to_inner())?;
-        Some(diff)
+    fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
+        match end.to_inner().checked_sub(start.to_inner()) {
+            Some(diff) => (diff, Some(diff)),
+            None => (0, None),
+        }
     }
 
     fn forward_checked(start: Self, count: usize) -> Option<Self> {
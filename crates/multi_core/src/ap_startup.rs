@@ -3,11 +3,14 @@ use acpi::{platform::ProcessorState, AcpiHandler, AcpiTables};
 use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use kernel_image::KernelImage;
 use memory::phys::PhysAddr;
+use memory::PAGE_SIZE;
 use x86::apic::{ApicControl, ApicId};
 
 #[no_mangle]
 static KERNEL_STACKS_VADDR: AtomicUsize = AtomicUsize::new(0);
 
+/// The distance between two stacks in the stack area, i.e. the size of a stack and its guard page.
+/// The trampoline uses it to compute the top of the stack of each core.
 #[no_mangle]
 static KERNEL_STACK_SIZE: AtomicUsize = AtomicUsize::new(0);
 
@@ -17,6 +20,13 @@ static NUM_CORES: AtomicU32 = AtomicU32::new(0);
 #[no_mangle]
 static PAGE_TABLE_ADDRESS: AtomicU32 = AtomicU32::new(0);
 
+/// Non-zero if the trampoline has to enable five-level paging, i.e. if the table at
+/// `PAGE_TABLE_ADDRESS` is a PML5T. The application processors use the paging mode of the
+/// bootstrap processor.
+#[cfg(target_arch = "x86_64")]
+#[no_mangle]
+static FIVE_LEVEL_PAGING: AtomicU32 = AtomicU32::new(0);
+
 #[derive(Debug, Copy, Clone)]
 pub enum ApStartupError {
     BadPageTableAddress,
@@ -32,7 +42,7 @@ pub fn startup_all_application_processors<H: AcpiHandler, SleepMicroSecondsFn: F
     sleep_us: SleepMicroSecondsFn,
 ) -> Result<(), ApStartupError> {
     let num_cores = kernel_image.num_cores().try_into().unwrap();
-    let stack_size = kernel_image.kernel_stack_size();
+    let stack_size = kernel_image.kernel_stack_size() + PAGE_SIZE;
     let stacks_addr = kernel_image
         .kernel_image_info()
         .stack
@@ -51,6 +61,12 @@ pub fn startup_all_application_processors<H: AcpiHandler, SleepMicroSecondsFn: F
     NUM_CORES.store(num_cores, Ordering::Relaxed);
     PAGE_TABLE_ADDRESS.store(page_table_address, Ordering::Relaxed);
 
+    #[cfg(target_arch = "x86_64")]
+    FIVE_LEVEL_PAGING.store(
+        memory::paging::five_level_paging() as u32,
+        Ordering::Relaxed,
+    );
+
     let processor_info = acpi_tables
         .platform_info()
         .map_err(|_| ApStartupError::AcpiPlatformInfoNotPresent)?
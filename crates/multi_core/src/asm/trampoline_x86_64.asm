@@ -90,8 +90,8 @@ prot_mode:
      *  loading a 64-bit GDT. Most of the work here is simmilar to boot.s.      *
      * ------------------------------------------------------------------------ */
 
-    // tell the cpu where to find our PML4T by setting the cr3 register
-    // Note: we get the address of the PML4T from a global variable set in ap_startup.rs
+    // tell the cpu where to find our PML4T (or PML5T) by setting the cr3 register
+    // Note: we get the address of the table from a global variable set in ap_startup.rs
 
     movl PAGE_TABLE_ADDRESS, %eax
     movl %eax, %cr3
@@ -99,6 +99,12 @@ prot_mode:
     // enable PAE which is required for long mode
     movl %cr4, %eax
     or $(1 << 5), %eax                  // set bit 5 which is the PAE-bit
+
+    // enable five-level paging if the bootstrap processor uses it
+    cmpl $0, FIVE_LEVEL_PAGING
+    je 1f
+    or $(1 << 12), %eax                 // set bit 12 which is the LA57-bit
+1:
     movl %eax, %cr4
 
     // set the LM-bit in the EFER MSR
@@ -0,0 +1,171 @@
+use crate::mm::with_temporary_mapping;
+use alloc::vec::Vec;
+use boot_info::platform_info::pc_x86::Rsdp;
+use boot_info::platform_info::PlatformInfo;
+use memory::phys::{Frame, Inner, PhysAddr};
+use memory::FRAME_SIZE;
+
+/// The length of the header every system description table starts with.
+const HEADER_LEN: usize = 36;
+
+/// The length of an RSDP of revision 2 or later, which contains the address of the XSDT.
+const RSDP_V2_LEN: usize = 36;
+
+/// A copy of an ACPI system description table, including its header.
+pub struct AcpiTable {
+    data: Vec<u8>,
+}
+
+impl AcpiTable {
+    /// Copies the table at `addr`. Its length is taken from the header.
+    fn copy(addr: u64) -> Option<Self> {
+        // tables above 4 GiB are not accessible on i686
+        let addr = PhysAddr::new(Inner::try_from(addr).ok()?);
+        let header = read_phys(addr, HEADER_LEN)?;
+        let len = read_u32(&header, 4)? as usize;
+
+        if len < HEADER_LEN {
+            return None;
+        }
+
+        Some(AcpiTable {
+            data: read_phys(addr, len)?,
+        })
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.data[..4]
+    }
+
+    /// Returns the whole table, including its header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn body(&self) -> &[u8] {
+        &self.data[HEADER_LEN..]
+    }
+}
+
+/// Copies of the ACPI tables, taken before the ACPI reclaimable memory is given back to the
+/// frame allocator. The addresses in the RSDP of `PlatformInfo` are stale after that.
+pub struct AcpiTables {
+    tables: Vec<AcpiTable>,
+}
+
+impl AcpiTables {
+    /// Copies the root table (XSDT or RSDT), every table it references and the DSDT.
+    ///
+    /// Returns `None` if the platform provides no RSDP or any table could not be copied.
+    pub fn copy(platform_info: &PlatformInfo) -> Option<Self> {
+        let (root_addr, entry_len) = match platform_info {
+            PlatformInfo::PCX86(info) => match &info.rsdp {
+                Rsdp::V1(rsdp) => root_table(rsdp.rsdt_addr, None),
+                Rsdp::V2(rsdp) => root_table(rsdp.rsdt_addr, Some(rsdp.xsdt_addr)),
+            },
+            PlatformInfo::UEFI(info) => read_rsdp(info.rsdp_address)?,
+            PlatformInfo::None => return None,
+        };
+
+        let root = AcpiTable::copy(root_addr)?;
+
+        let mut tables = Vec::new();
+        for entry in root.body().chunks_exact(entry_len) {
+            let addr = match entry_len {
+                8 => read_u64(entry, 0)?,
+                _ => read_u32(entry, 0)?.into(),
+            };
+
+            tables.push(AcpiTable::copy(addr)?);
+        }
+
+        // the DSDT is not listed in the root table, only the FADT references it
+        let dsdt_addr = tables
+            .iter()
+            .find(|table| table.signature() == b"FACP")
+            .and_then(dsdt_addr);
+
+        if let Some(addr) = dsdt_addr {
+            tables.push(AcpiTable::copy(addr)?);
+        }
+
+        tables.insert(0, root);
+
+        Some(AcpiTables { tables })
+    }
+
+    /// Returns the first table with the given signature.
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&AcpiTable> {
+        self.tables
+            .iter()
+            .find(|table| table.signature() == signature)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AcpiTable> {
+        self.tables.iter()
+    }
+}
+
+/// Returns the address of the root table and the size of its entries.
+/// The XSDT is preferred if the RSDP contains its address.
+fn root_table(rsdt_addr: u32, xsdt_addr: Option<u64>) -> (u64, usize) {
+    match xsdt_addr {
+        Some(addr) if addr != 0 => (addr, 8),
+        _ => (rsdt_addr.into(), 4),
+    }
+}
+
+/// Reads the RSDP at `addr` and returns its root table like `root_table()`.
+fn read_rsdp(addr: PhysAddr) -> Option<(u64, usize)> {
+    let rsdp = read_phys(addr, RSDP_V2_LEN)?;
+
+    let revision = rsdp[15];
+    let rsdt_addr = read_u32(&rsdp, 16)?;
+    let xsdt_addr = if revision >= 2 {
+        Some(read_u64(&rsdp, 24)?)
+    } else {
+        None
+    };
+
+    Some(root_table(rsdt_addr, xsdt_addr))
+}
+
+/// Returns the address of the DSDT referenced by the FADT `fadt`.
+fn dsdt_addr(fadt: &AcpiTable) -> Option<u64> {
+    // the 64-bit X_DSDT field takes precedence, if the table is long enough to contain it
+    let x_dsdt = read_u64(fadt.data(), 140).filter(|&addr| addr != 0);
+    let dsdt = read_u32(fadt.data(), 40).filter(|&addr| addr != 0);
+
+    x_dsdt.or(dsdt.map(u64::from))
+}
+
+/// Copies `len` bytes of physical memory starting at `addr` to the kernel heap.
+fn read_phys(addr: PhysAddr, len: usize) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    data.try_reserve_exact(len).ok()?;
+
+    while data.len() < len {
+        let pos = PhysAddr::new(addr.to_inner().checked_add(data.len() as Inner)?);
+        let offset = (pos.to_inner() % FRAME_SIZE) as usize;
+        let count = core::cmp::min(len - data.len(), FRAME_SIZE as usize - offset);
+
+        with_temporary_mapping(Frame::new(pos.frame_align_down()), |page| {
+            // Safety: the whole frame is mapped and `offset + count` does not exceed it
+            let src =
+                unsafe { core::slice::from_raw_parts(page.as_ptr::<u8>().add(offset), count) };
+            data.extend_from_slice(src);
+        })?;
+    }
+
+    Some(data)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
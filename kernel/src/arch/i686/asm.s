@@ -32,3 +32,15 @@ load_cs_2:
     movl %ebp, %esp
     popl %ebp
     ret
+
+
+// The entry point of the double fault task.
+// The cpu pushes the error code onto the stack of the task, so the call
+// passes it as the first parameter to double_fault_task.
+.global double_fault_task_entry
+double_fault_task_entry:
+    call double_fault_task
+
+double_fault_task_entry_1:
+    hlt
+    jmp double_fault_task_entry_1
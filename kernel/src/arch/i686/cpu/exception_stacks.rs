@@ -0,0 +1,60 @@
+//! A double fault is handled by a separate task with its own TSS and stack, so that it can be
+//! reported even if the kernel stack is unusable.
+//!
+//! Note: unlike on x86_64, non-maskable interrupts and machine checks still run on the current
+//! stack, since a task gate cannot be entered again while its task is busy.
+
+use super::gdt;
+use alloc::boxed::Box;
+use memory::PAGE_SIZE;
+use x86::bits32::task::TaskStateSegment;
+use x86::controlregs::cr3;
+
+const EXCEPTION_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// The reserved bit 1 of the eflags register is always set.
+const EFLAGS_RESERVED: u32 = 1 << 1;
+
+#[repr(C, align(16))]
+struct ExceptionStack([u8; EXCEPTION_STACK_SIZE]);
+
+extern "C" {
+    /// - double_fault_task_entry implemented in asm.s -
+    /// The entry point of the double fault task.
+    fn double_fault_task_entry() -> !;
+}
+
+/// Allocates the double fault stack of the current core and prepares `tss` to run the
+/// double fault task on it.
+///
+/// The task uses the page tables which are active right now. They must be replaced with
+/// `set_page_tables()` once they are no longer valid.
+pub(super) fn init(tss: &mut TaskStateSegment) {
+    let stack =
+        Box::<ExceptionStack>::try_new_uninit().expect("unable to allocate exception stack");
+
+    // the stack is used as long as the core is running
+    let stack = Box::leak(stack);
+    let top = stack.as_ptr() as usize + EXCEPTION_STACK_SIZE;
+
+    tss.eip = double_fault_task_entry as *const () as usize as u32;
+    tss.esp = top as u32;
+    tss.eflags = EFLAGS_RESERVED;
+    tss.cr3 = unsafe { cr3() } as u32;
+
+    tss.cs = gdt::KERNEL_CODE_SEL.bits();
+    tss.ss = gdt::KERNEL_DATA_SEL.bits();
+    tss.ds = gdt::KERNEL_DATA_SEL.bits();
+    tss.es = gdt::KERNEL_DATA_SEL.bits();
+    tss.fs = gdt::KERNEL_DATA_SEL.bits();
+    tss.gs = gdt::KERNEL_CPU_LOCAL_DATA_SEL.bits();
+
+    // See https://wiki.osdev.org/Task_State_Segment on meaning of this value.
+    tss.iobp_offset = core::mem::size_of::<TaskStateSegment>() as u16;
+}
+
+/// Sets the page tables the double fault task of the current core switches to.
+/// They must stay valid as long as the core is running.
+pub fn set_page_tables(p2_addr: u32) {
+    super::local::get().borrow_mut().double_fault_tss_mut().cr3 = p2_addr;
+}
@@ -1,4 +1,26 @@
+use x86::cpuid::CpuId;
 
 pub fn verify() {
     
-}
\ No newline at end of file
+}
+
+/// Checks if the cpu supports the page attribute table.
+pub fn has_pat() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_pat())
+}
+
+/// Checks if the cpu has a local apic.
+pub fn has_apic() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_apic())
+}
+
+/// Checks if the local apic supports x2apic mode.
+pub fn has_x2apic() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_x2apic())
+}
@@ -15,6 +15,7 @@ pub const KERNEL_CPU_LOCAL_DATA_SEL: SegmentSelector = SegmentSelector::new(3, R
 pub const USER_CODE_SEL: SegmentSelector = SegmentSelector::new(4, Ring::Ring3);
 pub const USER_DATA_SEL: SegmentSelector = SegmentSelector::new(5, Ring::Ring3);
 pub const TSS_SEL: SegmentSelector = SegmentSelector::new(6, Ring::Ring0);
+pub const DOUBLE_FAULT_TSS_SEL: SegmentSelector = SegmentSelector::new(7, Ring::Ring0);
 
 #[repr(C, align(8))]
 pub struct GlobalDescriptorTable {
@@ -25,6 +26,7 @@ pub struct GlobalDescriptorTable {
     user_code: Descriptor,
     user_data: Descriptor,
     tss_desc: Descriptor,
+    double_fault_tss_desc: Descriptor,
 }
 
 impl GlobalDescriptorTable {
@@ -85,6 +87,7 @@ impl GlobalDescriptorTable {
             user_code,
             user_data,
             tss_desc,
+            double_fault_tss_desc: tss_desc,
         }
     }
 
@@ -94,6 +97,12 @@ impl GlobalDescriptorTable {
         self.tss_desc.set_base_limit(base, limit);
     }
 
+    pub fn set_double_fault_tss_desc(&mut self, addr: VirtAddr, size_in_bytes: usize) {
+        let base = addr.to_inner() as u32;
+        let limit = size_in_bytes as u32;
+        self.double_fault_tss_desc.set_base_limit(base, limit);
+    }
+
     pub fn set_cpu_local(&mut self, addr: VirtAddr, size_in_bytes: usize) {
         let base = addr.to_inner() as u32;
         let limit = size_in_bytes as u32;
@@ -13,11 +13,13 @@ use x86::{
     },
     segmentation::{
         BuildDescriptor, Descriptor, DescriptorBuilder, GateDescriptorBuilder, SegmentSelector,
+        TaskGateDescriptorBuilder,
     },
     Ring,
 };
 
 use crate::arch::cpu::exceptions;
+use crate::arch::interrupts::apic;
 
 use super::gdt;
 
@@ -86,6 +88,7 @@ impl InterruptDescriptorTable {
         // Safety: it is assumed that these entries are all properly configured.
         unsafe {
             idt.set_exception_handlers();
+            idt.set_interrupt_handlers();
         }
 
         idt
@@ -150,6 +153,15 @@ impl InterruptDescriptorTable {
             .finish();
     }
 
+    /// Set a task gate entry. The cpu switches to the task with the TSS `tss_sel` instead of calling a handler.
+    /// Every core must have a valid TSS at `tss_sel` in its GDT.
+    pub unsafe fn set_task_gate(&mut self, index: u8, tss_sel: SegmentSelector) {
+        self.entries[index as usize] = DescriptorBuilder::task_gate_descriptor(tss_sel)
+            .present()
+            .dpl(Ring::Ring0)
+            .finish();
+    }
+
     pub unsafe fn set_exception_handler(&mut self, index: u8, function: InterruptHandler) {
         unsafe {
             self.set_trap_handler(index, gdt::KERNEL_CODE_SEL, Ring::Ring0, function);
@@ -191,7 +203,7 @@ impl InterruptDescriptorTable {
                 exceptions::device_not_available,
             );
 
-            self.set_exception_handler_error_code(DOUBLE_FAULT_VECTOR, exceptions::double_fault);
+            self.set_task_gate(DOUBLE_FAULT_VECTOR, gdt::DOUBLE_FAULT_TSS_SEL);
             self.set_exception_handler_error_code(INVALID_TSS_VECTOR, exceptions::invalid_tss);
             self.set_exception_handler_error_code(
                 SEGMENT_NOT_PRESENT_VECTOR,
@@ -227,6 +239,13 @@ impl InterruptDescriptorTable {
         }
     }
 
+    unsafe fn set_interrupt_handlers(&mut self) {
+        unsafe {
+            self.set_kernel_interrupt_handler(apic::TLB_SHOOTDOWN_VECTOR, apic::tlb_shootdown);
+            self.set_kernel_interrupt_handler(apic::SPURIOUS_VECTOR, apic::spurious);
+        }
+    }
+
     #[cfg(debug_assertions)]
     fn verify_index<T: InterruptHandlerFunction>(index: u8) {
         let has_err_code = T::HAS_ERROR_CODE;
@@ -1,14 +1,19 @@
 use core::{arch::asm, cell::RefCell, marker::PhantomData, ptr::NonNull};
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use memory::virt::VirtAddr;
 use x86::{
     bits32::task::TaskStateSegment,
-    segmentation::{load_ds, load_es, load_fs, load_gs, load_ss},
+    segmentation::{gs, load_ds, load_es, load_fs, load_gs, load_ss},
     task::load_tr,
 };
 
+use super::exception_stacks;
 use super::gdt::{self, GlobalDescriptorTable};
+use crate::arch::paging::AddressSpace;
+use crate::mm::{register_frame_magazine, FrameMagazine};
+use spin::Mutex;
 
 /// This type can be used to make a struct !Send .
 type PhantomUnsend = PhantomData<*mut ()>;
@@ -29,6 +34,10 @@ pub struct Local {
     gdt: GlobalDescriptorTable,
     /// The TSS which holds the stack pointer for system calls.
     tss: TaskStateSegment,
+    /// The TSS of the task which handles double faults.
+    double_fault_tss: TaskStateSegment,
+    /// The address space which is active on this core or `None` for the initial address space.
+    address_space: Option<Arc<AddressSpace>>,
 }
 
 /// Each core/cpu will hold a pointer to a `LocalWrapper` object using the gs
@@ -37,8 +46,14 @@ pub struct Local {
 struct LocalWrapper {
     /// A pointer to this `LocalWrapper` struct.
     self_ref: NonNull<LocalWrapper>,
+    /// The processor id (apic id) of this core. It is kept outside of `Local`,
+    /// so that it can be read while `Local` is borrowed.
+    proc_id: usize,
     /// The `Local` struct with dynamic borrow checking through a `RefCell`.
     local: RefCell<Local>,
+    /// Free frames cached for this CPU by the `GlobalFrameAllocator`. They are kept outside of
+    /// `Local`, since other cores drain them when the global pool runs out of frames.
+    frame_magazine: Mutex<FrameMagazine>,
     /// This is here to make LocalWrapper !Send because it should never be
     /// used across other cores/threads.
     _phantom: PhantomUnsend,
@@ -49,7 +64,9 @@ impl Local {
         Self {
             proc_id,
             tss: TaskStateSegment::new(),
+            double_fault_tss: TaskStateSegment::new(),
             gdt: GlobalDescriptorTable::new(),
+            address_space: None,
         }
     }
 
@@ -68,12 +85,30 @@ impl Local {
     pub fn tss_mut(&mut self) -> &mut TaskStateSegment {
         &mut self.tss
     }
+
+    pub fn double_fault_tss_mut(&mut self) -> &mut TaskStateSegment {
+        &mut self.double_fault_tss
+    }
+
+    pub fn address_space(&self) -> Option<&Arc<AddressSpace>> {
+        self.address_space.as_ref()
+    }
+
+    /// Sets the active address space and returns the previous one.
+    pub fn set_address_space(
+        &mut self,
+        address_space: Option<Arc<AddressSpace>>,
+    ) -> Option<Arc<AddressSpace>> {
+        core::mem::replace(&mut self.address_space, address_space)
+    }
 }
 
 pub(super) fn init(proc_id: usize) {
     let mut wrapper = Box::new(LocalWrapper {
         self_ref: NonNull::dangling(),
+        proc_id,
         local: RefCell::new(Local::new(proc_id)),
+        frame_magazine: Mutex::new(FrameMagazine::new()),
         _phantom: PhantomData,
     });
 
@@ -120,18 +155,45 @@ pub(super) fn init(proc_id: usize) {
         load_tr(gdt::TSS_SEL);
     }
 
+    // initialize the double fault task
+    {
+        let local = &mut *local;
+
+        exception_stacks::init(&mut local.double_fault_tss);
+
+        let tss_addr = VirtAddr::new(&local.double_fault_tss as *const TaskStateSegment as usize);
+        let tss_size = core::mem::size_of::<TaskStateSegment>();
+
+        local.gdt.set_double_fault_tss_desc(tss_addr, tss_size);
+    }
+
     drop(local);
 
     // do not deallocate the memory
-    Box::leak(wrapper);
+    let wrapper = Box::leak(wrapper);
+    register_frame_magazine(proc_id, &wrapper.frame_magazine);
 }
 
 pub fn get() -> &'static RefCell<Local> {
+    &wrapper().local
+}
+
+/// Returns the processor id (apic id) of the current core.
+pub fn proc_id() -> usize {
+    wrapper().proc_id
+}
+
+/// Checks if `init()` has been called on the current core.
+pub fn is_initialized() -> bool {
+    // the loader leaves its data selector in gs, which is replaced in init()
+    gs() == gdt::KERNEL_CPU_LOCAL_DATA_SEL
+}
+
+fn wrapper() -> &'static LocalWrapper {
     unsafe {
         let addr = gs_deref();
         let ptr = addr as *mut LocalWrapper;
-        let wrapper = &*ptr;
-        &wrapper.local
+        &*ptr
     }
 }
 
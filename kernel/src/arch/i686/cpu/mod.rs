@@ -1,5 +1,8 @@
 use core::arch::asm;
+use memory::paging::{flush_tlb_all, PAT_VALUE};
+use x86::msr::{wrmsr, IA32_PAT};
 
+pub mod exception_stacks;
 pub mod exceptions;
 pub mod features;
 pub mod gdt;
@@ -10,6 +13,17 @@ pub fn init(proc_id: usize) {
     local::init(proc_id);
 
     idt::init();
+
+    init_pat();
+}
+
+/// Programs the PAT with the layout expected by `memory::paging::MemoryType`.
+/// Every core must use the same layout.
+fn init_pat() {
+    if features::has_pat() {
+        unsafe { wrmsr(IA32_PAT, PAT_VALUE) };
+        flush_tlb_all();
+    }
 }
 
 pub fn halt() -> ! {
@@ -0,0 +1,248 @@
+use crate::arch::cpu::local;
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::paging::{
+    with_mapper, INITIAL_P2_ADDR, KERNEL_P1_ADDRS, KERNEL_P2_START_IDX, NUM_KERNEL_P1_TABLES,
+};
+use crate::mm::{
+    with_temporary_mapping, CpuSet, MemoryRegion, PageTableAllocator, RegionSetLock, TlbBatch,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use memory::paging::{Entry, EntryUsage, Level2, Table};
+use memory::phys::{Frame, PageFrameAllocator, PhysAddr};
+use memory::virt::VirtualRange;
+use memory::{AccessFlags, KERNEL_BASE, PAGE_TABLE_ENTRIES};
+use x86::controlregs::{cr3, cr3_write};
+use zeroize::Zeroize;
+
+const RECURSIVE_P2_IDX: usize = PAGE_TABLE_ENTRIES - 1;
+
+/// An `AddressSpace` owns a PD with its own user half. The kernel half is shared with all other
+/// address spaces, since every PD references the same kernel PT's from `KERNEL_P1_ADDRS`.
+///
+/// All page tables of the user half are freed when the `AddressSpace` is dropped. The frames mapped
+/// by these tables are owned by the memory regions of the `AddressSpace`.
+pub struct AddressSpace {
+    p2_frame: Frame,
+    /// The memory regions of the user half.
+    regions: RegionSetLock,
+    /// The cores on which this address space is active.
+    active_cores: CpuSet,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user half.
+    pub fn new() -> Option<Self> {
+        let p2_frame = PageTableAllocator.alloc()?;
+
+        let res = with_temporary_mapping(p2_frame, |addr| {
+            let p2 = unsafe { &mut *addr.as_ptr_mut::<Table<Level2>>() };
+            p2.zeroize();
+
+            for i in 0..NUM_KERNEL_P1_TABLES {
+                // Safety: KERNEL_P1_ADDRS is immutable after paging has been initialized
+                let p1_addr = unsafe { KERNEL_P1_ADDRS[i] };
+                p2[KERNEL_P2_START_IDX + i] = Entry::table_entry(p1_addr);
+            }
+
+            p2[RECURSIVE_P2_IDX] = Entry::table_entry(p2_frame.to_addr());
+        });
+
+        if res.is_none() {
+            PageTableAllocator.dealloc(p2_frame);
+            return None;
+        }
+
+        Some(Self {
+            p2_frame,
+            regions: RegionSetLock::new(),
+            active_cores: CpuSet::new(),
+        })
+    }
+
+    /// Returns the physical address of the PD.
+    pub fn p2_addr(&self) -> PhysAddr {
+        self.p2_frame.to_addr()
+    }
+
+    /// Returns the address space which is active on the current core.
+    /// Returns `None` if the initial address space is active.
+    pub fn current() -> Option<Arc<AddressSpace>> {
+        local::get().try_borrow().ok()?.address_space().cloned()
+    }
+
+    /// Checks if this address space is active on the current core.
+    pub fn is_active(&self) -> bool {
+        current_p2_addr() == self.p2_addr()
+    }
+
+    /// Makes this address space the active one on the current core.
+    /// The core keeps a reference to it until another address space is activated.
+    pub fn activate(self: &Arc<Self>) {
+        if !self.is_active() {
+            // Safety: the kernel half is the same in all address spaces
+            unsafe { cr3_write(self.p2_addr().to_inner() as u64) };
+        }
+
+        let prev = {
+            let mut local = local::get().borrow_mut();
+            let id = local.proc_id();
+
+            self.active_cores.insert(id);
+            let prev = local.set_address_space(Some(self.clone()));
+
+            if let Some(ref prev) = prev {
+                if !Arc::ptr_eq(prev, self) {
+                    prev.active_cores.remove(id);
+                }
+            }
+
+            prev
+        };
+
+        // Note: the previous address space must be dropped after the borrow ended,
+        // since freeing its tables requires the cpu local data.
+        drop(prev);
+    }
+
+    /// Switches the current core back to the initial address space if this address space is active.
+    pub fn deactivate(&self) {
+        if self.is_active() {
+            // Safety: the initial PD is never freed
+            unsafe { cr3_write(INITIAL_P2_ADDR.to_inner() as u64) };
+
+            let prev = {
+                let mut local = local::get().borrow_mut();
+                self.active_cores.remove(local.proc_id());
+                local.set_address_space(None)
+            };
+
+            drop(prev);
+        }
+    }
+
+    pub fn regions(&self) -> &RegionSetLock {
+        &self.regions
+    }
+
+    /// Adds `region` to the user half of this address space. Its pages are mapped when they are
+    /// first accessed. Returns `None` if the region is not part of the user half or overlaps with
+    /// another region.
+    pub fn add_region(&self, region: MemoryRegion) -> Option<()> {
+        if region.range().end_addr().to_inner() > KERNEL_BASE {
+            return None;
+        }
+
+        self.regions.lock().insert(region)
+    }
+
+    /// Removes the region with the given `range` and unmaps all its pages.
+    pub fn remove_region(&self, range: VirtualRange) -> Option<()> {
+        let region = self.regions.lock().remove(range)?;
+
+        // the region has been removed, so its pages are no longer accessible
+        self.with_active(|| with_mapper(|mapper| mapper.unmap_range(range)))
+            .expect("unable to unmap memory region");
+
+        let mut batch = TlbBatch::new();
+        batch.add(range);
+        self.flush_tlb(&batch);
+
+        // the frames of the region are freed after they have been unmapped
+        drop(region);
+        Some(())
+    }
+
+    /// Creates a copy-on-write clone of this address space. Both address spaces share the frames
+    /// of anonymous regions until one of them writes to a frame.
+    pub fn cow_clone(&self) -> Option<AddressSpace> {
+        let space = AddressSpace::new()?;
+        let regions = self.regions.lock();
+        let cloned = regions.cow_clone()?;
+
+        // Writes to the shared frames must fault from now on. The clone itself starts
+        // without any mappings, so its pages are mapped read-only when they are first accessed.
+        let mut batch = TlbBatch::new();
+
+        self.with_active(|| {
+            with_mapper(|mapper| {
+                for region in regions.iter().filter(|r| r.needs_write_protect()) {
+                    let access = region.access().difference(AccessFlags::WRITE);
+
+                    mapper
+                        .protect_range(region.range(), access)
+                        .expect("unable to write-protect memory region");
+
+                    batch.add(region.range());
+                }
+            })
+        });
+
+        self.flush_tlb(&batch);
+
+        *space.regions.lock() = cloned;
+        Some(space)
+    }
+
+    /// Flushes the ranges of `batch` from the TLBs of all other cores on which this address space
+    /// is active. The current core has already been flushed by the `Mapper`.
+    pub fn flush_tlb(&self, batch: &TlbBatch) {
+        batch.flush(&self.active_cores);
+    }
+
+    /// Runs `f` while this address space is active on the current core. This is required to
+    /// modify the user half, since page tables are only accessible through the recursive mapping.
+    ///
+    /// Interrupts stay disabled until the previous address space is loaded again, so no interrupt
+    /// handler runs with the foreign user half. Meanwhile the core is part of `active_cores`, so
+    /// shootdowns of this address space reach it as well.
+    ///
+    /// Note: `f` must not access the user half of the previously active address space.
+    fn with_active<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.is_active() {
+            return f();
+        }
+
+        without_interrupts(|| {
+            let id = local::proc_id();
+            let prev = unsafe { cr3() };
+
+            self.active_cores.insert(id);
+
+            // Safety: the kernel half is the same in all address spaces and interrupts are disabled
+            unsafe { cr3_write(self.p2_addr().to_inner() as u64) };
+            let res = f();
+            unsafe { cr3_write(prev) };
+
+            self.active_cores.remove(id);
+            res
+        })
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Note: every core holds a reference to its active address space
+        assert!(!self.is_active(), "dropping an active address space");
+
+        let p1_frames = with_temporary_mapping(self.p2_frame, |addr| {
+            let p2 = unsafe { &*addr.as_ptr::<Table<Level2>>() };
+
+            (0..KERNEL_P2_START_IDX)
+                .filter(|idx| p2[*idx].usage() == EntryUsage::Table)
+                .map(|idx| p2[idx].frame())
+                .collect::<Vec<Frame>>()
+        })
+        .expect("unable to map page directory");
+
+        for p1_frame in p1_frames {
+            PageTableAllocator.dealloc(p1_frame);
+        }
+
+        PageTableAllocator.dealloc(self.p2_frame);
+    }
+}
+
+fn current_p2_addr() -> PhysAddr {
+    PhysAddr::new(unsafe { cr3() } as u32 & !0xfff)
+}
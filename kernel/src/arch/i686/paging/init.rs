@@ -1,8 +1,9 @@
+use crate::arch::cpu::exception_stacks;
 use crate::arch::paging::{
     INITIAL_P2_ADDR, KERNEL_P1_ADDRS, KERNEL_P2_START_IDX, NUM_KERNEL_P1_TABLES,
 };
 use crate::mm::{
-    get_initial_kernel_regions, GlobalFrameAllocator, InitPagingError, InitialKernelRegion,
+    get_initial_kernel_regions, InitPagingError, InitialKernelRegion, PageTableAllocator,
 };
 use boot_info::BootInfoHeader;
 use memory::{
@@ -12,7 +13,7 @@ use memory::{
     AccessFlags, PAGE_TABLE_ENTRIES,
 };
 use spin::Once;
-use x86::controlregs::cr3_write;
+use x86::controlregs::{cr3_write, cr4, cr4_write, Cr4};
 use zeroize::Zeroize;
 
 const KERNEL_P2_RECURSIVE_IDX: usize = PAGE_TABLE_ENTRIES - 1;
@@ -28,7 +29,8 @@ pub fn init(boot_info: &BootInfoHeader) {
 fn init_once(boot_info: &BootInfoHeader) -> Result<(), InitPagingError> {
     init_p2_and_p1s()?;
 
-    let regions = get_initial_kernel_regions(&boot_info.memory_map, &boot_info.kernel_image_info)?;
+    let regions =
+        get_initial_kernel_regions(&boot_info.memory_map(), &boot_info.kernel_image_info)?;
     for region in regions {
         unsafe {
             map_initial_kernel_region(region)?;
@@ -129,7 +131,7 @@ unsafe fn alloc_table<'a, L: TableLevel>() -> Result<(&'a mut Table<L>, PhysAddr
 
 /// This function simply allocates a new frame using the global frame allocator.
 fn alloc_table_memory() -> Result<PhysAddr, InitPagingError> {
-    match GlobalFrameAllocator.alloc() {
+    match PageTableAllocator.alloc() {
         None => Err(InitPagingError::OutOfMemory),
         Some(frame) => Ok(frame.to_addr()),
     }
@@ -151,5 +153,11 @@ unsafe fn get_init_table<'a, L: TableLevel>(
 }
 
 fn init_all() {
+    // 4 MiB pages created by the Mapper require PSE
+    unsafe { cr4_write(cr4() | Cr4::CR4_ENABLE_PSE) };
+
     unsafe { cr3_write(INITIAL_P2_ADDR.to_inner() as u64) };
+
+    // the page tables of the loader are reclaimed after boot
+    exception_stacks::set_page_tables(unsafe { INITIAL_P2_ADDR.to_inner() });
 }
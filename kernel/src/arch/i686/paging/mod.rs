@@ -1,14 +1,25 @@
-use memory::{KERNEL_BASE, PAGE_TABLE_ENTRIES};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory::virt::{Page, VirtAddr, VirtualRange};
+use memory::{KERNEL_BASE, KERNEL_END, PAGE_SIZE, PAGE_TABLE_ENTRIES};
+use spin::{Mutex, MutexGuard};
 
+mod address_space;
 mod init;
 
+use crate::arch::cpu::local;
+use crate::mm::{flush_range, FaultLock, PageTableAllocator};
+pub use address_space::AddressSpace;
 pub use init::init;
+use memory::paging::{flush_tlb_all, Level2, LocalTlbFlush, Mapper, PageSize, Table, Walker};
 use memory::phys::PhysAddr;
 
 const KERNEL_P2_START_IDX: usize = (KERNEL_BASE >> 22) & 0x3FF;
 const KERNEL_P2_END_IDX: usize = PAGE_TABLE_ENTRIES - 1;
 const NUM_KERNEL_P1_TABLES: usize = KERNEL_P2_END_IDX - KERNEL_P2_START_IDX;
 
+/// This is the address of the PD when using recursive mapping.
+const P2: *mut Table<Level2> = 0xffff_f000 as *mut Table<Level2>;
+
 /// This global variable holds the physical address of the PD that is used during initialization
 /// until the PD's are managed by the process manager / scheduler.
 ///
@@ -24,3 +35,126 @@ static mut INITIAL_P2_ADDR: PhysAddr = PhysAddr::zero();
 /// modifies the kernel address space it will become immediately visible to all other processes.
 static mut KERNEL_P1_ADDRS: [PhysAddr; NUM_KERNEL_P1_TABLES] =
     [PhysAddr::zero(); NUM_KERNEL_P1_TABLES];
+
+/// This page is reserved for the `Mapper`. It is used to fill the page tables that replace split 4 MiB pages.
+pub const SCRATCH_PAGE: Page = Page::new(VirtAddr::new(KERNEL_END - PAGE_SIZE));
+
+/// This is the global page lock. It must be held whenever the recursive mapping area is accessed.
+///
+/// Note: the kernel heap takes the page lock to grow, so the heap must not be used while holding it.
+static PAGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The id of the core which holds the page lock or `NO_OWNER`.
+static PAGE_LOCK_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+const NO_OWNER: usize = usize::MAX;
+
+/// Holds the page lock and remembers the core which holds it.
+struct PageLockGuard {
+    // Note: the fields are dropped in order, so the lock is released before the fault lock
+    _guard: MutexGuard<'static, ()>,
+    _fault_lock: FaultLock,
+}
+
+impl Drop for PageLockGuard {
+    fn drop(&mut self) {
+        // Note: this runs before the lock is released
+        PAGE_LOCK_OWNER.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+impl PageLockGuard {
+    fn new(guard: MutexGuard<'static, ()>) -> Self {
+        PAGE_LOCK_OWNER.store(current_core().unwrap_or(NO_OWNER), Ordering::Release);
+        Self {
+            _guard: guard,
+            _fault_lock: FaultLock::acquire(),
+        }
+    }
+}
+
+fn lock_pages() -> PageLockGuard {
+    debug_assert!(
+        !holds_page_lock(),
+        "the page lock is already held by this core"
+    );
+    PageLockGuard::new(PAGE_LOCK.lock())
+}
+
+fn try_lock_pages() -> Option<PageLockGuard> {
+    PAGE_LOCK.try_lock().map(PageLockGuard::new)
+}
+
+/// Checks if the current core holds the page lock.
+pub fn holds_page_lock() -> bool {
+    current_core().is_some_and(|id| PAGE_LOCK_OWNER.load(Ordering::Acquire) == id)
+}
+
+/// Returns the id of the current core or `None` if its cpu local data has not been set up yet.
+fn current_core() -> Option<usize> {
+    local::is_initialized().then(local::proc_id)
+}
+
+/// Runs `f` with a `Mapper` for the current address space while holding the page lock.
+/// Page tables are allocated from and freed to the `PageTableAllocator`.
+/// `f` must not allocate from the kernel heap.
+pub fn with_mapper<R>(f: impl FnOnce(&mut Mapper<PageTableAllocator, LocalTlbFlush>) -> R) -> R {
+    let _guard = lock_pages();
+
+    // Safety: P2 is the recursively mapped PD, we hold the page lock,
+    // SCRATCH_PAGE is reserved in the kernel virtual allocator and PSE is enabled in init_all()
+    let mut mapper = unsafe {
+        Mapper::new(
+            &mut *P2,
+            PageTableAllocator,
+            LocalTlbFlush,
+            SCRATCH_PAGE,
+            max_page_size(),
+        )
+    };
+    f(&mut mapper)
+}
+
+/// Runs `f` with a `Walker` for the current address space while holding the page lock.
+/// `f` must not allocate from the kernel heap.
+pub fn with_walker<R>(f: impl FnOnce(&Walker) -> R) -> R {
+    let _guard = lock_pages();
+
+    // Safety: P2 is the recursively mapped PD and we hold the page lock
+    let walker = unsafe { Walker::new(&*P2) };
+    f(&walker)
+}
+
+/// Like `with_walker()`, but returns `None` instead of waiting if the page lock is held.
+/// This allows to inspect the page tables from exception handlers.
+pub fn try_with_walker<R>(f: impl FnOnce(&Walker) -> R) -> Option<R> {
+    let _guard = try_lock_pages()?;
+
+    // Safety: P2 is the recursively mapped PD and we hold the page lock
+    let walker = unsafe { Walker::new(&*P2) };
+    Some(f(&walker))
+}
+
+/// Returns the largest page size supported by the cpu.
+pub fn max_page_size() -> PageSize {
+    PageSize::Large
+}
+
+/// Removes the mappings of all pages in `range` from the current address space and flushes them
+/// from the TLBs of all cores. Pages which are not mapped are skipped and the frames are not
+/// deallocated.
+///
+/// # Safety
+/// The pages in `range` must not be accessed anymore.
+pub unsafe fn unmap_range(range: VirtualRange) {
+    with_mapper(|mapper| mapper.unmap_range(range)).expect("unable to unmap range");
+    flush_range(range);
+}
+
+/// PCIDs are not available in 32-bit mode, so there are no other contexts to invalidate.
+pub fn invalidate_other_contexts() {}
+
+/// Flushes all non-global TLB entries of the current core.
+pub fn flush_all_contexts() {
+    flush_tlb_all();
+}
@@ -0,0 +1,65 @@
+//! Exceptions which can occur while the kernel stack is unusable are handled on dedicated
+//! stacks of the current core. The cpu switches to them through the interrupt stack table (IST)
+//! of the TSS.
+
+use super::local;
+use crate::arch::paging::with_mapper;
+use crate::mm::{GlobalFrameAllocator, KernelVirtualAllocator};
+use memory::phys::PageFrameAllocator;
+use memory::virt::{VirtualRange, VirtualRangeAllocator};
+use memory::AccessFlags;
+
+/// The IST index of the double fault stack. Index 0 means that the stack is not switched.
+pub const DOUBLE_FAULT_IST_INDEX: u8 = 1;
+
+/// The IST index of the non-maskable interrupt stack.
+pub const NMI_IST_INDEX: u8 = 2;
+
+/// The IST index of the machine check stack.
+pub const MACHINE_CHECK_IST_INDEX: u8 = 3;
+
+const EXCEPTION_STACK_PAGES: usize = 4;
+
+/// Allocates the exception stacks of the current core and stores them in the IST of its TSS.
+///
+/// The stacks are mapped into the kernel address space, so this must be called after the core
+/// has switched to the kernel page tables. Until then, the IST is empty.
+pub fn init() {
+    let indices = [
+        DOUBLE_FAULT_IST_INDEX,
+        NMI_IST_INDEX,
+        MACHINE_CHECK_IST_INDEX,
+    ];
+
+    let stacks = indices.map(|_| alloc_stack());
+
+    let mut local = local::get().borrow_mut();
+
+    for (index, stack) in indices.into_iter().zip(stacks) {
+        local.tss_mut().ist[index as usize - 1] = stack.end_addr().to_inner() as u64;
+    }
+}
+
+/// Allocates a stack which is preceded by an unmapped guard page, so that an overflow faults
+/// instead of corrupting other memory. The stack is used as long as the core is running.
+fn alloc_stack() -> VirtualRange {
+    let range = KernelVirtualAllocator
+        .alloc(EXCEPTION_STACK_PAGES + 1, 1)
+        .expect("unable to allocate exception stack");
+
+    let stack = VirtualRange::new(range.start().add(1), range.end());
+
+    with_mapper(|mapper| {
+        for page in stack.pages() {
+            let frame = GlobalFrameAllocator
+                .alloc()
+                .expect("unable to allocate exception stack");
+
+            mapper
+                .map(page, frame, AccessFlags::READ_WRITE)
+                .expect("unable to map exception stack");
+        }
+    });
+
+    stack
+}
@@ -1,7 +1,9 @@
 #![allow(improper_ctypes_definitions)]
 
 use super::idt::InterruptStackFrame;
-use core::arch::asm;
+use crate::mm::{dump_translation, handle_page_fault, stack_overflow_cpu};
+use memory::virt::VirtAddr;
+use memory::AccessFlags;
 use x86::controlregs::cr2;
 
 pub extern "x86-interrupt" fn divide_by_zero(_frame: InterruptStackFrame) {
@@ -36,39 +38,16 @@ pub extern "x86-interrupt" fn device_not_available(_frame: InterruptStackFrame)
     panic!("device not available");
 }
 
-pub extern "x86-interrupt" fn double_fault(_frame: InterruptStackFrame, _error_code: u64) {
-    unsafe {
-        asm!(
-            "mov dx, 0x3F8",
-            "mov al, 0x64",
-            "out dx, al",
-            "mov al, 0x6f",
-            "out dx, al",
-            "mov al, 0x75",
-            "out dx, al",
-            "mov al, 0x62",
-            "out dx, al",
-            "mov al, 0x6c",
-            "out dx, al",
-            "mov al, 0x65",
-            "out dx, al",
-            "mov al, 0x20",
-            "out dx, al",
-            "mov al, 0x66",
-            "out dx, al",
-            "mov al, 0x61",
-            "out dx, al",
-            "mov al, 0x75",
-            "out dx, al",
-            "mov al, 0x6c",
-            "out dx, al",
-            "mov al, 0x74",
-            "out dx, al",
-            "mov al, 0x0a",
-            "out dx, al",
-            "hlt",
-        );
+/// Runs on its own stack, so that a kernel stack overflow can be reported.
+pub extern "x86-interrupt" fn double_fault(_frame: InterruptStackFrame, error_code: u64) {
+    // the page fault which could not be delivered hit the guard page below the stack
+    let addr = VirtAddr::new(unsafe { cr2() });
+
+    if let Some(cpu) = stack_overflow_cpu(addr) {
+        panic!("kernel stack overflow on CPU {}", cpu);
     }
+
+    panic!("double fault: {:#x}", error_code);
 }
 
 pub extern "x86-interrupt" fn invalid_tss(_frame: InterruptStackFrame, error_code: u64) {
@@ -91,8 +70,21 @@ pub extern "x86-interrupt" fn general_protection_fault(
 }
 
 pub extern "x86-interrupt" fn page_fault(_frame: InterruptStackFrame, error_code: u64) {
-    let page_fault_address = unsafe { cr2() };
-    panic!("page fault at {:#x}: {:#x}", page_fault_address, error_code);
+    let addr = VirtAddr::new(unsafe { cr2() });
+
+    // bit 4 is set for instruction fetches and bit 1 for writes
+    let access = if error_code & (1 << 4) != 0 {
+        AccessFlags::EXEC
+    } else if error_code & (1 << 1) != 0 {
+        AccessFlags::WRITE
+    } else {
+        AccessFlags::READ
+    };
+
+    if let Err(err) = handle_page_fault(addr, access) {
+        dump_translation(addr);
+        panic!("page fault at {:#x}: {:?} ({:#x})", addr, err, error_code);
+    }
 }
 
 pub extern "x86-interrupt" fn floating_point_exception(_frame: InterruptStackFrame) {
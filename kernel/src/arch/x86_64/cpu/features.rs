@@ -6,9 +6,59 @@ pub fn verify() {
     let feature_info = cpuid.get_feature_info();
     let feature_info_ref = feature_info.as_ref();
 
-    let has_sse = feature_info_ref.map_or(false, |info| info.has_sse());
-    let has_sysenter_sysexit = feature_info_ref.map_or(false, |info| info.has_sysenter_sysexit());
+    let has_sse = feature_info_ref.is_some_and(|info| info.has_sse());
+    let has_sysenter_sysexit = feature_info_ref.is_some_and(|info| info.has_sysenter_sysexit());
 
     assert!(has_sse, "sse not supported");
     assert!(has_sysenter_sysexit, "sysenter/sysexit not supported");
+    assert!(has_no_execute(), "no-execute not supported");
+}
+
+/// Checks if the cpu supports the no-execute bit in page table entries.
+pub fn has_no_execute() -> bool {
+    CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .is_some_and(|info| info.has_execute_disable())
+}
+
+/// Checks if the cpu supports 1 GiB pages.
+pub fn has_giant_pages() -> bool {
+    CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .is_some_and(|info| info.has_1gib_pages())
+}
+
+/// Checks if the cpu supports process-context identifiers.
+pub fn has_pcid() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_pcid())
+}
+
+/// Checks if the cpu supports the invpcid instruction.
+pub fn has_invpcid() -> bool {
+    CpuId::new()
+        .get_extended_feature_info()
+        .is_some_and(|info| info.has_invpcid())
+}
+
+/// Checks if the cpu supports the page attribute table.
+pub fn has_pat() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_pat())
+}
+
+/// Checks if the cpu has a local apic.
+pub fn has_apic() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_apic())
+}
+
+/// Checks if the local apic supports x2apic mode.
+pub fn has_x2apic() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_x2apic())
 }
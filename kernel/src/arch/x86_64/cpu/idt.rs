@@ -16,9 +16,11 @@ use x86::{
 };
 
 use super::{
+    exception_stacks,
     exceptions::{self, breakpoint},
     gdt,
 };
+use crate::arch::interrupts::apic;
 
 const IDT_ENTRIES: usize = 256;
 
@@ -85,6 +87,7 @@ impl InterruptDescriptorTable {
         // Safety: it is assumed that these entries are all properly configured.
         unsafe {
             idt.set_exception_handlers();
+            idt.set_interrupt_handlers();
         }
 
         idt
@@ -109,6 +112,21 @@ impl InterruptDescriptorTable {
         code_sel: SegmentSelector,
         dpl: Ring,
         function: T,
+    ) {
+        unsafe {
+            self.set_trap_handler_with_stack(index, code_sel, dpl, function, 0);
+        }
+    }
+
+    /// Like `set_trap_handler()`, but the cpu switches to the stack with the index `ist` in the
+    /// interrupt stack table of the TSS before calling `function`. An index of 0 keeps the current stack.
+    pub unsafe fn set_trap_handler_with_stack<T: InterruptHandlerFunction>(
+        &mut self,
+        index: u8,
+        code_sel: SegmentSelector,
+        dpl: Ring,
+        function: T,
+        ist: u8,
     ) {
         #[cfg(debug_assertions)]
         Self::verify_index::<T>(index);
@@ -117,6 +135,7 @@ impl InterruptDescriptorTable {
             DescriptorBuilder::trap_gate_descriptor(code_sel, function.addr() as u64)
                 .present()
                 .dpl(dpl)
+                .ist(ist)
                 .finish();
     }
 
@@ -158,6 +177,41 @@ impl InterruptDescriptorTable {
         }
     }
 
+    /// Like `set_exception_handler()`, but `function` runs on the exception stack `ist`.
+    pub unsafe fn set_exception_handler_with_stack(
+        &mut self,
+        index: u8,
+        function: InterruptHandler,
+        ist: u8,
+    ) {
+        unsafe {
+            self.set_trap_handler_with_stack(
+                index,
+                gdt::KERNEL_CODE_SEL,
+                Ring::Ring0,
+                function,
+                ist,
+            );
+        }
+    }
+
+    pub unsafe fn set_exception_handler_error_code_with_stack(
+        &mut self,
+        index: u8,
+        function: InterruptHandlerErrorCode,
+        ist: u8,
+    ) {
+        unsafe {
+            self.set_trap_handler_with_stack(
+                index,
+                gdt::KERNEL_CODE_SEL,
+                Ring::Ring0,
+                function,
+                ist,
+            );
+        }
+    }
+
     pub unsafe fn set_kernel_interrupt_handler(&mut self, index: u8, function: InterruptHandler) {
         unsafe {
             self.set_interrupt_gate_handler(index, gdt::KERNEL_CODE_SEL, Ring::Ring0, function);
@@ -168,9 +222,10 @@ impl InterruptDescriptorTable {
         unsafe {
             self.set_exception_handler(DIVIDE_ERROR_VECTOR, exceptions::divide_by_zero);
             self.set_exception_handler(DEBUG_VECTOR, exceptions::debug);
-            self.set_exception_handler(
+            self.set_exception_handler_with_stack(
                 NONMASKABLE_INTERRUPT_VECTOR,
                 exceptions::non_maskable_interrupt,
+                exception_stacks::NMI_IST_INDEX,
             );
             self.set_exception_handler(BREAKPOINT_VECTOR, breakpoint);
             self.set_exception_handler(OVERFLOW_VECTOR, exceptions::overflow);
@@ -184,7 +239,11 @@ impl InterruptDescriptorTable {
                 exceptions::device_not_available,
             );
 
-            self.set_exception_handler_error_code(DOUBLE_FAULT_VECTOR, exceptions::double_fault);
+            self.set_exception_handler_error_code_with_stack(
+                DOUBLE_FAULT_VECTOR,
+                exceptions::double_fault,
+                exception_stacks::DOUBLE_FAULT_IST_INDEX,
+            );
             self.set_exception_handler_error_code(INVALID_TSS_VECTOR, exceptions::invalid_tss);
             self.set_exception_handler_error_code(
                 SEGMENT_NOT_PRESENT_VECTOR,
@@ -209,7 +268,11 @@ impl InterruptDescriptorTable {
             self.set_exception_handler_error_code(0x1E, exceptions::security_exception);
 
             self.set_exception_handler(X87_FPU_VECTOR, exceptions::floating_point_exception);
-            self.set_exception_handler(MACHINE_CHECK_VECTOR, exceptions::machine_check);
+            self.set_exception_handler_with_stack(
+                MACHINE_CHECK_VECTOR,
+                exceptions::machine_check,
+                exception_stacks::MACHINE_CHECK_IST_INDEX,
+            );
             self.set_exception_handler(
                 SIMD_FLOATING_POINT_VECTOR,
                 exceptions::simd_floating_point_exception,
@@ -220,6 +283,13 @@ impl InterruptDescriptorTable {
         }
     }
 
+    unsafe fn set_interrupt_handlers(&mut self) {
+        unsafe {
+            self.set_kernel_interrupt_handler(apic::TLB_SHOOTDOWN_VECTOR, apic::tlb_shootdown);
+            self.set_kernel_interrupt_handler(apic::SPURIOUS_VECTOR, apic::spurious);
+        }
+    }
+
     #[cfg(debug_assertions)]
     fn verify_index<T: InterruptHandlerFunction>(index: u8) {
         let has_err_code = T::HAS_ERROR_CODE;
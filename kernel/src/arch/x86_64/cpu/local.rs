@@ -1,6 +1,7 @@
 use core::{cell::RefCell, marker::PhantomData, ptr::NonNull};
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use memory::virt::VirtAddr;
 use x86::{
     bits64::task::TaskStateSegment,
@@ -11,6 +12,9 @@ use x86::{
 };
 
 use super::gdt::{self, GlobalDescriptorTable};
+use crate::arch::paging::{AddressSpace, PcidSet};
+use crate::mm::{register_frame_magazine, FrameMagazine};
+use spin::Mutex;
 
 /// This type can be used to make a struct !Send .
 type PhantomUnsend = PhantomData<*mut ()>;
@@ -24,6 +28,8 @@ pub struct Local {
     gdt: GlobalDescriptorTable,
     /// The TSS which holds the stack pointer for system calls.
     tss: TaskStateSegment,
+    /// The address space which is active on this core or `None` for the initial address space.
+    address_space: Option<Arc<AddressSpace>>,
 }
 
 /// Each core/cpu will hold a pointer to a `LocalWrapper` object in it's
@@ -32,8 +38,17 @@ pub struct Local {
 struct LocalWrapper {
     /// A pointer to this `LocalWrapper` struct.
     self_ref: NonNull<LocalWrapper>,
+    /// The processor id (apic id) of this core. It is kept outside of `Local`,
+    /// so that it can be read while `Local` is borrowed.
+    proc_id: usize,
     /// The `Local` struct with dynamic borrow checking through a `RefCell`.
     local: RefCell<Local>,
+    /// Free frames cached for this CPU by the `GlobalFrameAllocator`. They are kept outside of
+    /// `Local`, since other cores drain them when the global pool runs out of frames.
+    frame_magazine: Mutex<FrameMagazine>,
+    /// The PCID assignments of this core. They are kept outside of `Local`,
+    /// since TLB shootdowns update them in interrupt context.
+    pcids: PcidSet,
     /// This is here to make LocalWrapper !Send because it should never be
     /// used across other cores/threads.
     _phantom: PhantomUnsend,
@@ -45,6 +60,7 @@ impl Local {
             proc_id,
             tss: TaskStateSegment::new(),
             gdt: GlobalDescriptorTable::new(),
+            address_space: None,
         }
     }
 
@@ -63,12 +79,27 @@ impl Local {
     pub fn tss_mut(&mut self) -> &mut TaskStateSegment {
         &mut self.tss
     }
+
+    pub fn address_space(&self) -> Option<&Arc<AddressSpace>> {
+        self.address_space.as_ref()
+    }
+
+    /// Sets the active address space and returns the previous one.
+    pub fn set_address_space(
+        &mut self,
+        address_space: Option<Arc<AddressSpace>>,
+    ) -> Option<Arc<AddressSpace>> {
+        core::mem::replace(&mut self.address_space, address_space)
+    }
 }
 
 pub(super) fn init(proc_id: usize) {
     let mut wrapper = Box::try_new(LocalWrapper {
         self_ref: NonNull::dangling(),
+        proc_id,
         local: RefCell::new(Local::new(proc_id)),
+        frame_magazine: Mutex::new(FrameMagazine::new()),
+        pcids: PcidSet::new(),
         _phantom: PhantomData,
     })
     .expect("unable to allocate cpu local struct");
@@ -111,18 +142,40 @@ pub(super) fn init(proc_id: usize) {
         // See https://wiki.osdev.org/Task_State_Segment on meaning of this value.
         local.tss.iomap_base = core::mem::size_of::<TaskStateSegment>() as u16;
 
+        // Note: the exception stacks are added once the paging has been initialized
+
         load_tr(gdt::TSS_SEL);
     }
 
     drop(local);
-    Box::leak(wrapper);
+    let wrapper = Box::leak(wrapper);
+    register_frame_magazine(proc_id, &wrapper.frame_magazine);
 }
 
 pub fn get() -> &'static RefCell<Local> {
+    &wrapper().local
+}
+
+/// Returns the processor id (apic id) of the current core.
+pub fn proc_id() -> usize {
+    wrapper().proc_id
+}
+
+/// Returns the PCID assignments of the current core.
+pub fn pcids() -> &'static PcidSet {
+    &wrapper().pcids
+}
+
+/// Checks if `init()` has been called on the current core.
+pub fn is_initialized() -> bool {
+    // the loader leaves a data selector in gs, which is replaced by the null selector in init()
+    gs().index() == 0
+}
+
+fn wrapper() -> &'static LocalWrapper {
     #[cfg(debug_assertions)]
     {
-        let gs_val = gs();
-        if gs_val.index() != 0 {
+        if !is_initialized() {
             panic!("used local::get() before local::init()");
         }
     }
@@ -130,7 +183,6 @@ pub fn get() -> &'static RefCell<Local> {
     unsafe {
         let addr = gs_deref!(0) as usize;
         let ptr = addr as *mut LocalWrapper;
-        let wrapper = &*ptr;
-        &wrapper.local
+        &*ptr
     }
 }
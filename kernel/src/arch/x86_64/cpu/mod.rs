@@ -1,3 +1,4 @@
+pub mod exception_stacks;
 pub mod exceptions;
 pub mod features;
 pub mod gdt;
@@ -5,12 +6,39 @@ pub mod idt;
 pub mod local;
 
 use core::arch::asm;
+use memory::paging::{flush_tlb_all, PAT_VALUE};
+use x86::msr::{rdmsr, wrmsr, IA32_EFER, IA32_PAT};
+
+/// The no-execute enable bit in the EFER register.
+const EFER_NXE: u64 = 1 << 11;
 
 /// Initializes the local, gdt, idt and tss modules in the correct order for every core.
 pub fn init(proc_id: usize) {
     local::init(proc_id);
 
     idt::init();
+
+    enable_no_execute();
+
+    init_pat();
+}
+
+/// Sets EFER.NXE so the no-execute bit of page table entries is honored.
+/// This must happen before the kernel page tables are loaded, as the bit is reserved otherwise.
+fn enable_no_execute() {
+    unsafe {
+        let efer = rdmsr(IA32_EFER);
+        wrmsr(IA32_EFER, efer | EFER_NXE);
+    }
+}
+
+/// Programs the PAT with the layout expected by `memory::paging::MemoryType`.
+/// Every core must use the same layout.
+fn init_pat() {
+    if features::has_pat() {
+        unsafe { wrmsr(IA32_PAT, PAT_VALUE) };
+        flush_tlb_all();
+    }
 }
 
 pub fn halt() -> ! {
@@ -0,0 +1,161 @@
+//! The local apic of each core. It is used in x2apic mode if the cpu supports it and
+//! through its memory mapped registers otherwise.
+
+use crate::arch::cpu::features;
+use crate::arch::cpu::idt::InterruptStackFrame;
+use crate::mm::{handle_tlb_shootdown, ioremap};
+use memory::paging::MemoryType;
+use memory::phys::{Frame, Inner, PhysAddr, PhysicalRange};
+use memory::virt::VirtAddr;
+use spin::Once;
+use x86::apic::xapic::{XAPIC_EOI, XAPIC_ICR0, XAPIC_ICR1, XAPIC_SVR};
+use x86::apic::{
+    ApicId, DeliveryMode, DeliveryStatus, DestinationMode, DestinationShorthand, Icr, Level,
+    TriggerMode,
+};
+use x86::io::outb;
+use x86::msr::{rdmsr, wrmsr, IA32_APIC_BASE, IA32_X2APIC_EOI, IA32_X2APIC_ICR, IA32_X2APIC_SIVR};
+
+/// The vector of the IPI which requests a TLB shootdown.
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xF0;
+
+/// The vector the local apic uses for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0xffff_f000;
+
+const SVR_ENABLE: u32 = 1 << 8;
+
+const ICR_SEND_PENDING: u32 = 1 << 12;
+
+#[derive(Clone, Copy)]
+enum ApicMode {
+    X2Apic,
+    /// The registers are mapped at the given address.
+    XApic(VirtAddr),
+}
+
+/// The mode is the same on all cores. In xapic mode every core sees its own registers
+/// at the same physical address.
+static MODE: Once<ApicMode> = Once::new();
+
+/// Enables the local apic of the current core and masks the legacy pic.
+/// This requires the kernel address space to be initialized.
+pub fn init() {
+    let mode = *MODE.call_once(|| {
+        if features::has_x2apic() {
+            ApicMode::X2Apic
+        } else {
+            assert!(features::has_apic(), "local apic not supported");
+
+            let base = unsafe { rdmsr(IA32_APIC_BASE) } & APIC_BASE_ADDR_MASK;
+            let frame = Frame::new(PhysAddr::new(base as Inner));
+            let range = ioremap(PhysicalRange::with_size(frame, 1), MemoryType::Uncached)
+                .expect("unable to map the local apic");
+
+            ApicMode::XApic(range.start_addr())
+        }
+    });
+
+    mask_legacy_pic();
+
+    unsafe {
+        let base = rdmsr(IA32_APIC_BASE);
+
+        match mode {
+            ApicMode::X2Apic => wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC),
+            ApicMode::XApic(_) => wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE),
+        }
+    }
+
+    write(
+        IA32_X2APIC_SIVR,
+        XAPIC_SVR,
+        SVR_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+}
+
+/// Signals the end of the interrupt currently being handled.
+pub fn eoi() {
+    write(IA32_X2APIC_EOI, XAPIC_EOI, 0);
+}
+
+/// Sends an IPI with `vector` to the core with the apic id `id`.
+pub fn send_ipi(id: usize, vector: u8) {
+    match mode() {
+        ApicMode::X2Apic => {
+            let icr = Icr::for_x2apic(
+                vector,
+                ApicId::X2Apic(id as u32),
+                DestinationShorthand::NoShorthand,
+                DeliveryMode::Fixed,
+                DestinationMode::Physical,
+                DeliveryStatus::Idle,
+                Level::Assert,
+                TriggerMode::Edge,
+            );
+
+            let value = (icr.upper() as u64) << 32 | icr.lower() as u64;
+            unsafe { wrmsr(IA32_X2APIC_ICR, value) };
+        }
+        ApicMode::XApic(base) => {
+            let icr = Icr::for_xapic(
+                vector,
+                ApicId::XApic(id as u8),
+                DestinationShorthand::NoShorthand,
+                DeliveryMode::Fixed,
+                DestinationMode::Physical,
+                DeliveryStatus::Idle,
+                Level::Assert,
+                TriggerMode::Edge,
+            );
+
+            // writing the lower half sends the ipi
+            unsafe {
+                xapic_register(base, XAPIC_ICR1).write_volatile(icr.upper());
+                xapic_register(base, XAPIC_ICR0).write_volatile(icr.lower());
+
+                while xapic_register(base, XAPIC_ICR0).read_volatile() & ICR_SEND_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+#[allow(improper_ctypes_definitions)]
+pub extern "x86-interrupt" fn tlb_shootdown(_frame: InterruptStackFrame) {
+    handle_tlb_shootdown();
+    eoi();
+}
+
+#[allow(improper_ctypes_definitions)]
+pub extern "x86-interrupt" fn spurious(_frame: InterruptStackFrame) {
+    // spurious interrupts must not be acknowledged
+}
+
+fn mode() -> ApicMode {
+    *MODE.get().expect("local apic not initialized")
+}
+
+/// Writes the register which is the msr `msr` in x2apic mode and at `offset` in xapic mode.
+fn write(msr: u32, offset: u32, value: u32) {
+    match mode() {
+        ApicMode::X2Apic => unsafe { wrmsr(msr, value as u64) },
+        ApicMode::XApic(base) => unsafe { xapic_register(base, offset).write_volatile(value) },
+    }
+}
+
+fn xapic_register(base: VirtAddr, offset: u32) -> *mut u32 {
+    (base.to_inner() + offset as usize) as *mut u32
+}
+
+/// Masks all interrupts of the legacy pic, so that only the local apic delivers interrupts.
+fn mask_legacy_pic() {
+    unsafe {
+        outb(0x21, 0xff);
+        outb(0xa1, 0xff);
+    }
+}
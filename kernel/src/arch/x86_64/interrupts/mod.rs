@@ -1,3 +1,5 @@
+pub mod apic;
+
 use x86::bits64::rflags::{self, RFlags};
 
 /// This function checks if interrupts are enabled.
@@ -24,3 +26,28 @@ pub unsafe fn enable() {
 pub unsafe fn disable() {
     unsafe { x86::irq::disable() }
 }
+
+/// Runs `f` with interrupts disabled and restores the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = are_enabled();
+
+    // Safety: interrupts are only disabled for the duration of `f`
+    unsafe { disable() };
+
+    let res = f();
+
+    if were_enabled {
+        unsafe { enable() };
+    }
+
+    res
+}
+
+/// Sets up the local apic of the current core and enables interrupts.
+pub fn init() {
+    apic::init();
+
+    // Safety: the idt has been loaded by `cpu::init()` and all interrupts are delivered
+    // through the local apic
+    unsafe { enable() };
+}
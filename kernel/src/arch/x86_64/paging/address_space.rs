@@ -0,0 +1,315 @@
+use crate::arch::cpu::local;
+use crate::arch::interrupts::without_interrupts;
+use crate::arch::paging::pcid::{self, INITIAL_SPACE_ID};
+use crate::arch::paging::{
+    with_mapper, INITIAL_P4_ADDR, INITIAL_ROOT_ADDR, KERNEL_P3_ADDRS, KERNEL_P4_START_IDX,
+    NUM_KERNEL_P3_TABLES,
+};
+use crate::mm::{
+    with_temporary_mapping, CpuSet, MemoryRegion, PageTableAllocator, RegionSetLock, TlbBatch,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use memory::paging::{five_level_paging, Entry, EntryUsage, Level1, Level4, Level5, Table};
+use memory::phys::{Frame, PageFrameAllocator, PhysAddr};
+use memory::virt::VirtualRange;
+use memory::{user_end, AccessFlags, PAGE_TABLE_ENTRIES};
+use x86::controlregs::cr3;
+use zeroize::Zeroize;
+
+const RECURSIVE_P4_IDX: usize = PAGE_TABLE_ENTRIES - 1;
+
+/// The id of the next address space. Ids are never reused, so that a PCID which still caches an
+/// old address space is never mistaken for a new one.
+static NEXT_SPACE_ID: AtomicU64 = AtomicU64::new(INITIAL_SPACE_ID + 1);
+
+/// An `AddressSpace` owns a PML4T with its own user half. The kernel half is shared with all other
+/// address spaces, since every PML4T references the same kernel PDPT's from `KERNEL_P3_ADDRS`.
+///
+/// With five-level paging it owns a PML5T instead, which references the kernel PML4T of the
+/// initial address space. The PML4T's of the user half are created by the `Mapper` on demand.
+///
+/// All tables of the user half are freed when the `AddressSpace` is dropped. The frames mapped by
+/// these tables are owned by the memory regions of the `AddressSpace`.
+pub struct AddressSpace {
+    /// The PML4T or, with five-level paging, the PML5T which is loaded into CR3.
+    root_frame: Frame,
+    /// The id which identifies this address space in the PCID assignments of the cores.
+    id: u64,
+    /// Incremented whenever the TLBs are flushed for this address space. A core which loads the
+    /// address space with a PCID that cached an older generation has to flush it.
+    tlb_gen: AtomicU64,
+    /// The memory regions of the user half.
+    regions: RegionSetLock,
+    /// The cores on which this address space is active.
+    active_cores: CpuSet,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user half.
+    pub fn new() -> Option<Self> {
+        let root_frame = PageTableAllocator.alloc()?;
+
+        let res = with_temporary_mapping(root_frame, |addr| {
+            if five_level_paging() {
+                let p5 = unsafe { &mut *addr.as_ptr_mut::<Table<Level5>>() };
+                p5.zeroize();
+
+                // Safety: INITIAL_P4_ADDR is immutable after paging has been initialized
+                p5.set_recursive_and_kernel_p4(root_frame.to_addr(), unsafe { INITIAL_P4_ADDR });
+                return;
+            }
+
+            let p4 = unsafe { &mut *addr.as_ptr_mut::<Table<Level4>>() };
+            p4.zeroize();
+
+            for i in 0..NUM_KERNEL_P3_TABLES {
+                // Safety: KERNEL_P3_ADDRS is immutable after paging has been initialized
+                let p3_addr = unsafe { KERNEL_P3_ADDRS[i] };
+                p4[KERNEL_P4_START_IDX + i] = Entry::table_entry(p3_addr);
+            }
+
+            p4[RECURSIVE_P4_IDX] = Entry::table_entry(root_frame.to_addr());
+        });
+
+        if res.is_none() {
+            PageTableAllocator.dealloc(root_frame);
+            return None;
+        }
+
+        Some(Self {
+            root_frame,
+            id: NEXT_SPACE_ID.fetch_add(1, Ordering::Relaxed),
+            tlb_gen: AtomicU64::new(0),
+            regions: RegionSetLock::new(),
+            active_cores: CpuSet::new(),
+        })
+    }
+
+    /// Returns the physical address of the table which is loaded into CR3.
+    pub fn root_addr(&self) -> PhysAddr {
+        self.root_frame.to_addr()
+    }
+
+    /// Returns the address space which is active on the current core.
+    /// Returns `None` if the initial address space is active.
+    pub fn current() -> Option<Arc<AddressSpace>> {
+        local::get().try_borrow().ok()?.address_space().cloned()
+    }
+
+    /// Checks if this address space is active on the current core.
+    pub fn is_active(&self) -> bool {
+        current_root_addr() == self.root_addr()
+    }
+
+    /// Makes this address space the active one on the current core.
+    /// The core keeps a reference to it until another address space is activated.
+    pub fn activate(self: &Arc<Self>) {
+        let prev = without_interrupts(|| {
+            let mut local = local::get().borrow_mut();
+            let id = local.proc_id();
+
+            // Note: the core has to receive shootdowns before the generation is read,
+            // otherwise a concurrent `flush_tlb()` could miss it.
+            self.active_cores.insert(id);
+            fence(Ordering::SeqCst);
+
+            if !self.is_active() {
+                // Safety: the kernel half is the same in all address spaces
+                // and interrupts are disabled
+                unsafe { self.load() };
+            }
+
+            let prev = local.set_address_space(Some(self.clone()));
+
+            if let Some(ref prev) = prev {
+                if !Arc::ptr_eq(prev, self) {
+                    prev.active_cores.remove(id);
+                }
+            }
+
+            prev
+        });
+
+        // Note: the previous address space must be dropped after the borrow ended,
+        // since freeing its tables requires the cpu local data.
+        drop(prev);
+    }
+
+    /// Switches the current core back to the initial address space if this address space is active.
+    pub fn deactivate(&self) {
+        if self.is_active() {
+            // Safety: the initial page tables are never freed and interrupts are disabled
+            without_interrupts(|| unsafe { pcid::load(INITIAL_ROOT_ADDR, INITIAL_SPACE_ID, 0) });
+
+            let prev = {
+                let mut local = local::get().borrow_mut();
+                self.active_cores.remove(local.proc_id());
+                local.set_address_space(None)
+            };
+
+            drop(prev);
+        }
+    }
+
+    pub fn regions(&self) -> &RegionSetLock {
+        &self.regions
+    }
+
+    /// Adds `region` to the user half of this address space. Its pages are mapped when they are
+    /// first accessed. Returns `None` if the region is not part of the user half or overlaps with
+    /// another region.
+    pub fn add_region(&self, region: MemoryRegion) -> Option<()> {
+        if region.range().end_addr().to_inner() > user_end() {
+            return None;
+        }
+
+        self.regions.lock().insert(region)
+    }
+
+    /// Removes the region with the given `range` and unmaps all its pages.
+    pub fn remove_region(&self, range: VirtualRange) -> Option<()> {
+        let region = self.regions.lock().remove(range)?;
+
+        // the region has been removed, so its pages are no longer accessible
+        self.with_active(|| with_mapper(|mapper| mapper.unmap_range(range)))
+            .expect("unable to unmap memory region");
+
+        let mut batch = TlbBatch::new();
+        batch.add(range);
+        self.flush_tlb(&batch);
+
+        // the frames of the region are freed after they have been unmapped
+        drop(region);
+        Some(())
+    }
+
+    /// Creates a copy-on-write clone of this address space. Both address spaces share the frames
+    /// of anonymous regions until one of them writes to a frame.
+    pub fn cow_clone(&self) -> Option<AddressSpace> {
+        let space = AddressSpace::new()?;
+        let regions = self.regions.lock();
+        let cloned = regions.cow_clone()?;
+
+        // Writes to the shared frames must fault from now on. The clone itself starts
+        // without any mappings, so its pages are mapped read-only when they are first accessed.
+        let mut batch = TlbBatch::new();
+
+        self.with_active(|| {
+            with_mapper(|mapper| {
+                for region in regions.iter().filter(|r| r.needs_write_protect()) {
+                    let access = region.access().difference(AccessFlags::WRITE);
+
+                    mapper
+                        .protect_range(region.range(), access)
+                        .expect("unable to write-protect memory region");
+
+                    batch.add(region.range());
+                }
+            })
+        });
+
+        self.flush_tlb(&batch);
+
+        *space.regions.lock() = cloned;
+        Some(space)
+    }
+
+    /// Flushes the ranges of `batch` from the TLBs of all other cores on which this address space
+    /// is active. The current core has already been flushed by the `Mapper`.
+    pub fn flush_tlb(&self, batch: &TlbBatch) {
+        // cores which load this address space later on must not use their cached entries
+        self.tlb_gen.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+
+        batch.flush(&self.active_cores);
+    }
+
+    /// Runs `f` while this address space is active on the current core. This is required to
+    /// modify the user half, since page tables are only accessible through the recursive mapping.
+    ///
+    /// Interrupts stay disabled until the previous address space is loaded again, so no interrupt
+    /// handler runs with the foreign user half. Meanwhile the core is part of `active_cores`, so
+    /// shootdowns of this address space reach it as well.
+    ///
+    /// Note: `f` must not access the user half of the previously active address space.
+    fn with_active<R>(&self, f: impl FnOnce() -> R) -> R {
+        if self.is_active() {
+            return f();
+        }
+
+        without_interrupts(|| {
+            let id = local::proc_id();
+            let prev = unsafe { cr3() };
+
+            // see `activate()`
+            self.active_cores.insert(id);
+            fence(Ordering::SeqCst);
+
+            // Safety: the kernel half is the same in all address spaces and interrupts are disabled
+            unsafe { self.load() };
+            let res = f();
+            unsafe { pcid::restore(prev) };
+
+            self.active_cores.remove(id);
+            res
+        })
+    }
+
+    /// Loads this address space into CR3 with its PCID on the current core.
+    ///
+    /// # Safety
+    /// see `pcid::load()`
+    unsafe fn load(&self) {
+        let tlb_gen = self.tlb_gen.load(Ordering::SeqCst);
+        unsafe { pcid::load(self.root_addr(), self.id, tlb_gen) };
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Note: every core holds a reference to its active address space
+        assert!(!self.is_active(), "dropping an active address space");
+
+        // with five-level paging the user half starts with the PML4T's below the PML5T
+        let (user_entries, levels) = if five_level_paging() {
+            (0..Table::<Level5>::USER_ENTRIES, 4)
+        } else {
+            (0..KERNEL_P4_START_IDX, 3)
+        };
+
+        free_tables(self.root_frame, user_entries, levels);
+        PageTableAllocator.dealloc(self.root_frame);
+    }
+}
+
+/// Frees the tables referenced by the entries in `indices` of the table in `frame` together with
+/// the tables of the next `levels - 1` levels below them.
+fn free_tables(frame: Frame, indices: core::ops::Range<usize>, levels: usize) {
+    for child in table_frames(frame, indices) {
+        if levels > 1 {
+            free_tables(child, 0..PAGE_TABLE_ENTRIES, levels - 1);
+        }
+
+        PageTableAllocator.dealloc(child);
+    }
+}
+
+fn current_root_addr() -> PhysAddr {
+    PhysAddr::new(unsafe { cr3() } & !0xfff)
+}
+
+/// Returns the frames of the tables referenced by the entries in `indices` of the table in `frame`.
+fn table_frames(frame: Frame, indices: core::ops::Range<usize>) -> Vec<Frame> {
+    with_temporary_mapping(frame, |addr| {
+        // Note: only the entries are read, so the level of the table does not matter.
+        let table = unsafe { &*addr.as_ptr::<Table<Level1>>() };
+
+        indices
+            .filter(|idx| table[*idx].usage() == EntryUsage::Table)
+            .map(|idx| table[idx].frame())
+            .collect()
+    })
+    .expect("unable to map page table")
+}
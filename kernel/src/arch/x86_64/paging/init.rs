@@ -1,12 +1,17 @@
+use crate::arch::cpu::exception_stacks;
 use crate::arch::paging::{
-    INITIAL_P4_ADDR, KERNEL_P3_ADDRS, KERNEL_P4_START_IDX, NUM_KERNEL_P3_TABLES,
+    max_page_size, pcid, INITIAL_P4_ADDR, INITIAL_ROOT_ADDR, KERNEL_P3_ADDRS, KERNEL_P4_START_IDX,
+    NUM_KERNEL_P3_TABLES,
 };
 use crate::mm::{
-    get_initial_kernel_regions, GlobalFrameAllocator, InitPagingError, InitialKernelRegion,
+    get_initial_kernel_regions, InitPagingError, InitialKernelRegion, PageTableAllocator,
 };
 use boot_info::BootInfoHeader;
 use memory::{
-    paging::{Entry, EntryUsage, HierarchicalLevel, Level3, Level4, Table, TableLevel},
+    paging::{
+        five_level_paging, Entry, EntryUsage, HierarchicalLevel, Level3, Level4, Level5, PageSize,
+        Table, TableLevel,
+    },
     phys::{Frame, PageFrameAllocator, PhysAddr},
     virt::Page,
     AccessFlags, PAGE_TABLE_ENTRIES,
@@ -26,8 +31,10 @@ pub fn init(boot_info: &BootInfoHeader) {
 
 fn init_once(boot_info: &BootInfoHeader) -> Result<(), InitPagingError> {
     init_p4_and_p3s()?;
+    init_root()?;
 
-    let regions = get_initial_kernel_regions(&boot_info.memory_map, &boot_info.kernel_image_info)?;
+    let regions =
+        get_initial_kernel_regions(&boot_info.memory_map(), &boot_info.kernel_image_info)?;
     for region in regions {
         unsafe {
             map_initial_kernel_region(region)?;
@@ -38,18 +45,41 @@ fn init_once(boot_info: &BootInfoHeader) -> Result<(), InitPagingError> {
 }
 
 unsafe fn map_initial_kernel_region(region: InitialKernelRegion) -> Result<(), InitPagingError> {
-    for (page, frame) in region.virt_range.pages().zip(region.phys_range.frames()) {
+    let mut page = region.virt_range.start();
+    let mut frame = region.phys_range.start();
+
+    while page < region.virt_range.end() {
+        let size = initial_page_size(page, frame, region.virt_range.end().diff(page));
+
         unsafe {
-            map_initial_page(page, frame, region.access_flags)?;
+            map_initial_page(page, frame, size, region.access_flags)?;
         }
+
+        page = page.add(size.num_pages());
+        frame = frame.add(size.num_pages() as u64);
     }
 
     Ok(())
 }
 
+/// Returns the largest page size that can be used to map `page` to `frame`
+/// without mapping more than `num_pages` pages.
+fn initial_page_size(page: Page, frame: Frame, num_pages: usize) -> PageSize {
+    PageSize::ALL
+        .into_iter()
+        .find(|size| {
+            *size <= max_page_size()
+                && size.num_pages() <= num_pages
+                && page.to_inner().is_multiple_of(size.num_pages())
+                && frame.to_inner().is_multiple_of(size.num_pages() as u64)
+        })
+        .unwrap_or(PageSize::Normal)
+}
+
 unsafe fn map_initial_page(
     page: Page,
     frame: Frame,
+    size: PageSize,
     access_flags: AccessFlags,
 ) -> Result<(), InitPagingError> {
     let (p4_idx, p3_idx, p2_idx, p1_idx) = Table::<Level4>::get_table_indices(page);
@@ -57,7 +87,21 @@ unsafe fn map_initial_page(
     unsafe {
         let p4 = get_init_table::<Level4>(INITIAL_P4_ADDR)?;
         let p3 = get_or_create_table(p4, p4_idx)?;
+
+        if size == PageSize::Giant {
+            assert_eq!(p3[p3_idx].usage(), EntryUsage::None);
+            p3[p3_idx] = Entry::huge_page_entry(frame.to_addr(), access_flags);
+            return Ok(());
+        }
+
         let p2 = get_or_create_table(p3, p3_idx)?;
+
+        if size == PageSize::Large {
+            assert_eq!(p2[p2_idx].usage(), EntryUsage::None);
+            p2[p2_idx] = Entry::huge_page_entry(frame.to_addr(), access_flags);
+            return Ok(());
+        }
+
         let p1 = get_or_create_table(p2, p2_idx)?;
 
         assert_eq!(p1[p1_idx].usage(), EntryUsage::None);
@@ -117,6 +161,24 @@ fn init_p4_and_p3s() -> Result<(), InitPagingError> {
     Ok(())
 }
 
+/// This function initializes INITIAL_ROOT_ADDR. With five-level paging the loader has already
+/// enabled LA57, so a recursively mapped PML5T referencing the initial PML4T has to be allocated.
+fn init_root() -> Result<(), InitPagingError> {
+    let root_addr = if five_level_paging() {
+        let (p5, p5_addr) = unsafe { alloc_table::<Level5>()? };
+        p5.set_recursive_and_kernel_p4(p5_addr, unsafe { INITIAL_P4_ADDR });
+        p5_addr
+    } else {
+        unsafe { INITIAL_P4_ADDR }
+    };
+
+    unsafe {
+        INITIAL_ROOT_ADDR = root_addr;
+    }
+
+    Ok(())
+}
+
 /// This function allocates memory for a new page table and creates a mutable reference to it.
 /// This function also calls `Zeroize:::zeroize()` on the newly created table in order to clear all
 /// of its entries to zero.
@@ -130,7 +192,7 @@ unsafe fn alloc_table<'a, L: TableLevel>() -> Result<(&'a mut Table<L>, PhysAddr
 
 /// This function simply allocates a new frame using the global frame allocator.
 fn alloc_table_memory() -> Result<PhysAddr, InitPagingError> {
-    match GlobalFrameAllocator.alloc() {
+    match PageTableAllocator.alloc() {
         None => Err(InitPagingError::OutOfMemory),
         Some(frame) => Ok(frame.to_addr()),
     }
@@ -152,5 +214,9 @@ unsafe fn get_init_table<'a, L: TableLevel>(
 }
 
 fn init_all() {
-    unsafe { cr3_write(INITIAL_P4_ADDR.to_inner()) };
+    unsafe { cr3_write(INITIAL_ROOT_ADDR.to_inner()) };
+
+    pcid::init();
+
+    exception_stacks::init();
 }
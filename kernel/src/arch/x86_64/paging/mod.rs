@@ -1,27 +1,50 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use memory::phys::PhysAddr;
-use memory::{KERNEL_BASE, PAGE_TABLE_ENTRIES};
-use spin::Mutex;
+use memory::virt::{Page, VirtAddr, VirtualRange};
+use memory::{KERNEL_BASE, KERNEL_END, PAGE_SIZE, PAGE_TABLE_ENTRIES};
+use spin::{Mutex, MutexGuard, Once};
 
+mod address_space;
 mod init;
+mod pcid;
 
+use crate::arch::cpu::{features, local};
+use crate::mm::{flush_range, FaultLock, PageTableAllocator};
+pub use address_space::AddressSpace;
 pub use init::init;
-use memory::paging::{Level4, Table};
+use memory::paging::{five_level_paging, Level4, Level5, Mapper, PageSize, Table, Walker};
+pub use pcid::{flush_all_contexts, invalidate_other_contexts, PcidSet, PcidTlbFlush};
 
 const KERNEL_P4_START_IDX: usize = (KERNEL_BASE >> 39) & 0x1FF;
 const KERNEL_P4_END_IDX: usize = PAGE_TABLE_ENTRIES - 1;
 const NUM_KERNEL_P3_TABLES: usize = KERNEL_P4_END_IDX - KERNEL_P4_START_IDX;
 
 /// This is the address of the PML4T when using recursive mapping.
+/// With five-level paging the last PML5T entry references the PML4T of the higher half, so the
+/// address is the same.
 const P4: *mut Table<Level4> = 0xffff_ffff_ffff_f000 as *mut Table<Level4>;
 
+/// This is the address of the PML5T with five-level paging. It is recursively mapped by its entry
+/// `Table::<Level5>::RECURSIVE_IDX`, which also gives access to the PML4T's of the user half.
+const P5: *mut Table<Level5> = 0xfffe_ff7f_bfdf_e000 as *mut Table<Level5>;
+
 /// This global variable holds the physical address of the PML4T that is used during initialization
 /// until the PML4T's are managed by the process manager / scheduler.
+/// With five-level paging this PML4T only covers the higher half and is shared by all address spaces.
 ///
 /// # Safety
 /// This variable is initialized once during init_once() and is immutable after that.
 /// Thus, any read access to `INITIAL_P4_ADDR` after init_once() has completed is safe.
 static mut INITIAL_P4_ADDR: PhysAddr = PhysAddr::zero();
 
+/// This global variable holds the physical address of the root table of the initial address space,
+/// which is loaded into CR3. With five-level paging this is a PML5T referencing `INITIAL_P4_ADDR`
+/// and no PML4T's for the user half, otherwise it is `INITIAL_P4_ADDR` itself.
+///
+/// # Safety
+/// Same as `INITIAL_P4_ADDR`.
+static mut INITIAL_ROOT_ADDR: PhysAddr = PhysAddr::zero();
+
 /// This global array holds the physical addresses of the kernel PDPT's. These tables are allocated
 /// during init_once() and we allocate enough PDPT's to completely map the kernel's virtual
 /// address space. We do this, so we can later share the kernel address space between processes by
@@ -30,5 +53,135 @@ static mut INITIAL_P4_ADDR: PhysAddr = PhysAddr::zero();
 static mut KERNEL_P3_ADDRS: [PhysAddr; NUM_KERNEL_P3_TABLES] =
     [PhysAddr::zero(); NUM_KERNEL_P3_TABLES];
 
+/// This page is reserved for the `Mapper`. It is used to fill the tables that replace split huge pages.
+pub const SCRATCH_PAGE: Page = Page::new(VirtAddr::new(KERNEL_END - PAGE_SIZE));
+
+/// The largest page size supported by the cpu.
+static MAX_PAGE_SIZE: Once<PageSize> = Once::new();
+
 /// This is the global page lock. It must be held whenever the recursive mapping area is accessed.
+///
+/// Note: the kernel heap takes the page lock to grow, so the heap must not be used while holding it.
 static PAGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The id of the core which holds the page lock or `NO_OWNER`.
+static PAGE_LOCK_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+const NO_OWNER: usize = usize::MAX;
+
+/// Holds the page lock and remembers the core which holds it.
+struct PageLockGuard {
+    // Note: the fields are dropped in order, so the lock is released before the fault lock
+    _guard: MutexGuard<'static, ()>,
+    _fault_lock: FaultLock,
+}
+
+impl Drop for PageLockGuard {
+    fn drop(&mut self) {
+        // Note: this runs before the lock is released
+        PAGE_LOCK_OWNER.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+impl PageLockGuard {
+    fn new(guard: MutexGuard<'static, ()>) -> Self {
+        PAGE_LOCK_OWNER.store(current_core().unwrap_or(NO_OWNER), Ordering::Release);
+        Self {
+            _guard: guard,
+            _fault_lock: FaultLock::acquire(),
+        }
+    }
+}
+
+fn lock_pages() -> PageLockGuard {
+    debug_assert!(
+        !holds_page_lock(),
+        "the page lock is already held by this core"
+    );
+    PageLockGuard::new(PAGE_LOCK.lock())
+}
+
+fn try_lock_pages() -> Option<PageLockGuard> {
+    PAGE_LOCK.try_lock().map(PageLockGuard::new)
+}
+
+/// Checks if the current core holds the page lock.
+pub fn holds_page_lock() -> bool {
+    current_core().is_some_and(|id| PAGE_LOCK_OWNER.load(Ordering::Acquire) == id)
+}
+
+/// Returns the id of the current core or `None` if its cpu local data has not been set up yet.
+fn current_core() -> Option<usize> {
+    local::is_initialized().then(local::proc_id)
+}
+
+/// Runs `f` with a `Mapper` for the current address space while holding the page lock.
+/// Page tables are allocated from and freed to the `PageTableAllocator`.
+/// `f` must not allocate from the kernel heap.
+pub fn with_mapper<R>(f: impl FnOnce(&mut Mapper<PageTableAllocator, PcidTlbFlush>) -> R) -> R {
+    let _guard = lock_pages();
+
+    // Safety: P4 and P5 are the recursively mapped tables, we hold the page lock and
+    // SCRATCH_PAGE is reserved in the kernel virtual allocator
+    let mut mapper = unsafe {
+        Mapper::new(
+            &mut *P4,
+            p5(),
+            PageTableAllocator,
+            PcidTlbFlush,
+            SCRATCH_PAGE,
+            max_page_size(),
+        )
+    };
+    f(&mut mapper)
+}
+
+/// Runs `f` with a `Walker` for the current address space while holding the page lock.
+/// `f` must not allocate from the kernel heap.
+pub fn with_walker<R>(f: impl FnOnce(&Walker) -> R) -> R {
+    let _guard = lock_pages();
+
+    // Safety: P4 and P5 are the recursively mapped tables and we hold the page lock
+    let walker = unsafe { Walker::new(&*P4, p5().map(|p5| &*p5)) };
+    f(&walker)
+}
+
+/// Like `with_walker()`, but returns `None` instead of waiting if the page lock is held.
+/// This allows to inspect the page tables from exception handlers.
+pub fn try_with_walker<R>(f: impl FnOnce(&Walker) -> R) -> Option<R> {
+    let _guard = try_lock_pages()?;
+
+    // Safety: P4 and P5 are the recursively mapped tables and we hold the page lock
+    let walker = unsafe { Walker::new(&*P4, p5().map(|p5| &*p5)) };
+    Some(f(&walker))
+}
+
+/// Returns the recursively mapped PML5T if five-level paging is enabled.
+///
+/// # Safety
+/// The page lock must be held for `'a`.
+unsafe fn p5<'a>() -> Option<&'a mut Table<Level5>> {
+    five_level_paging().then(|| unsafe { &mut *P5 })
+}
+
+/// Returns the largest page size supported by the cpu.
+pub fn max_page_size() -> PageSize {
+    *MAX_PAGE_SIZE.call_once(|| {
+        if features::has_giant_pages() {
+            PageSize::Giant
+        } else {
+            PageSize::Large
+        }
+    })
+}
+
+/// Removes the mappings of all pages in `range` from the current address space and flushes them
+/// from the TLBs of all cores. Pages which are not mapped are skipped and the frames are not
+/// deallocated.
+///
+/// # Safety
+/// The pages in `range` must not be accessed anymore.
+pub unsafe fn unmap_range(range: VirtualRange) {
+    with_mapper(|mapper| mapper.unmap_range(range)).expect("unable to unmap range");
+    flush_range(range);
+}
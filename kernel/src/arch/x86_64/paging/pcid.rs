@@ -0,0 +1,279 @@
+//! Process-context identifiers (PCIDs) tag TLB entries with the address space they belong to, so
+//! that switching the address space does not have to flush the TLB.
+//!
+//! Every core assigns its PCIDs on its own. PCID 0 belongs to the initial address space, all other
+//! address spaces share the remaining PCIDs which are recycled round-robin. The TLB entries of a
+//! PCID are flushed when it is loaded if
+//! - it was assigned to another address space before
+//! - the address space has been flushed since it last used the PCID on this core
+//! - the kernel half has been flushed while the PCID was not loaded
+//!
+//! Note: kernel mappings are not global, thus they are cached separately for every PCID.
+//! Cpus without PCIDs simply flush the TLB on every address space switch.
+
+use crate::arch::cpu::{features, local};
+use core::arch::asm;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use memory::paging::{flush_tlb, flush_tlb_all, TlbFlush};
+use memory::phys::PhysAddr;
+use memory::virt::VirtAddr;
+use memory::KERNEL_BASE;
+use x86::controlregs::{cr3, cr3_write, cr4, cr4_write, Cr4};
+
+/// The number of PCIDs used on every core.
+const NUM_PCIDS: usize = 8;
+
+/// The id of the initial address space.
+pub const INITIAL_SPACE_ID: u64 = 0;
+
+/// If this bit is set in a value written to CR3, the TLB entries of the new PCID are kept.
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+/// The bits of CR3 which hold the current PCID.
+const CR3_PCID_MASK: u64 = 0xfff;
+
+/// The INVPCID type which invalidates all non-global mappings of all PCIDs.
+const INVPCID_ALL_CONTEXTS: u64 = 3;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static HAS_INVPCID: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    space_id: u64,
+    tlb_gen: u64,
+}
+
+/// The PCID assignments of a core.
+pub struct PcidSet {
+    /// The address space each PCID was last assigned to, indexed by the PCID.
+    slots: Cell<[Option<Slot>; NUM_PCIDS]>,
+    /// The PCID which is recycled next.
+    next: Cell<usize>,
+    /// One bit for every PCID whose kernel mappings must be flushed before it is loaded again.
+    /// Unlike the other fields, this is also updated by TLB shootdowns.
+    stale: AtomicU32,
+}
+
+impl PcidSet {
+    pub const fn new() -> Self {
+        Self {
+            slots: Cell::new([None; NUM_PCIDS]),
+            next: Cell::new(1),
+            stale: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the PCID for the address space `space_id` and whether its TLB entries must be
+    /// flushed when it is loaded.
+    fn assign(&self, space_id: u64, tlb_gen: u64) -> (usize, bool) {
+        let mut slots = self.slots.get();
+
+        let pcid = if space_id == INITIAL_SPACE_ID {
+            0
+        } else {
+            let found = (1..NUM_PCIDS)
+                .find(|pcid| matches!(slots[*pcid], Some(slot) if slot.space_id == space_id));
+
+            found.unwrap_or_else(|| {
+                let pcid = self.next.get();
+                self.next
+                    .set(if pcid + 1 == NUM_PCIDS { 1 } else { pcid + 1 });
+                pcid
+            })
+        };
+
+        let cached = matches!(
+            slots[pcid],
+            Some(slot) if slot.space_id == space_id && slot.tlb_gen == tlb_gen
+        );
+
+        slots[pcid] = Some(Slot { space_id, tlb_gen });
+        self.slots.set(slots);
+
+        let stale = self.clear_stale(pcid);
+
+        (pcid, !cached || stale)
+    }
+
+    /// Clears the stale bit of `pcid` and returns whether it was set.
+    fn clear_stale(&self, pcid: usize) -> bool {
+        let bit = 1 << pcid;
+        self.stale.fetch_and(!bit, Ordering::AcqRel) & bit != 0
+    }
+}
+
+/// Enables PCIDs on the current core if the cpu supports them.
+/// The initial address space must be loaded.
+pub fn init() {
+    if !features::has_pcid() {
+        return;
+    }
+
+    // Safety: CR4.PCIDE may be set since the PCID bits of CR3 are zero
+    unsafe { cr4_write(cr4() | Cr4::CR4_ENABLE_PCID) };
+
+    ENABLED.store(true, Ordering::Relaxed);
+    HAS_INVPCID.store(features::has_invpcid(), Ordering::Relaxed);
+}
+
+/// Checks if PCIDs are in use.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Loads the root table at `root_addr` with the PCID of the address space `space_id` on the current core.
+/// The TLB entries of the PCID are kept if they are still valid for `tlb_gen`.
+///
+/// # Safety
+/// - the kernel half of the PML4T must be the same as the one of the active PML4T
+/// - interrupts must be disabled, otherwise a shootdown could miss the new PCID
+pub unsafe fn load(root_addr: PhysAddr, space_id: u64, tlb_gen: u64) {
+    if !is_enabled() {
+        unsafe { cr3_write(root_addr.to_inner()) };
+        return;
+    }
+
+    let (pcid, flush) = local::pcids().assign(space_id, tlb_gen);
+
+    let value = if flush {
+        root_addr.to_inner() | pcid as u64
+    } else {
+        root_addr.to_inner() | pcid as u64 | CR3_NOFLUSH
+    };
+
+    unsafe { cr3_write(value) };
+}
+
+/// Loads a value previously read from CR3. The TLB entries of its PCID are flushed.
+///
+/// # Safety
+/// - the kernel half of the PML4T must be the same as the one of the active PML4T
+/// - interrupts must be disabled
+pub unsafe fn restore(value: u64) {
+    // Note: the no-flush bit is never set when CR3 is read
+    unsafe { cr3_write(value) };
+
+    if is_enabled() {
+        local::pcids().clear_stale((value & CR3_PCID_MASK) as usize);
+    }
+}
+
+/// Marks the kernel mappings of all PCIDs except the current one as stale.
+/// This is required after kernel mappings have been flushed from the TLB of the current core,
+/// since `invlpg` and CR3 writes only affect the current PCID.
+pub fn invalidate_other_contexts() {
+    if is_enabled() {
+        let current = unsafe { cr3() } & CR3_PCID_MASK;
+        let others = ((1 << NUM_PCIDS) - 1) & !(1 << current);
+
+        local::pcids().stale.fetch_or(others, Ordering::AcqRel);
+    }
+}
+
+/// Flushes all non-global TLB entries of all PCIDs on the current core.
+pub fn flush_all_contexts() {
+    if HAS_INVPCID.load(Ordering::Relaxed) {
+        unsafe { invpcid(INVPCID_ALL_CONTEXTS, 0, 0) };
+    } else {
+        // Note: reading CR3 includes the current PCID, so this is a tagged CR3 write
+        flush_tlb_all();
+        invalidate_other_contexts();
+    }
+}
+
+/// Flushes the TLB for the `Mapper`. Since kernel mappings are cached for every PCID, flushing one
+/// of them also marks the other PCIDs as stale.
+pub struct PcidTlbFlush;
+
+impl TlbFlush for PcidTlbFlush {
+    fn flush(&mut self, addr: VirtAddr) {
+        flush_tlb(addr);
+
+        if addr.to_inner() >= KERNEL_BASE {
+            invalidate_other_contexts();
+        }
+    }
+
+    fn flush_all(&mut self) {
+        flush_all_contexts();
+    }
+}
+
+/// Executes the `invpcid` instruction with the descriptor (`pcid`, `addr`).
+///
+/// # Safety
+/// The cpu must support INVPCID.
+unsafe fn invpcid(kind: u64, pcid: u64, addr: u64) {
+    let descriptor: [u64; 2] = [pcid, addr];
+
+    unsafe {
+        asm!(
+            "invpcid {0}, [{1}]",
+            in(reg) kind,
+            in(reg) &descriptor,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_initial_space_uses_pcid_0() {
+        let pcids = PcidSet::new();
+
+        assert_eq!(pcids.assign(INITIAL_SPACE_ID, 0), (0, true));
+        assert_eq!(pcids.assign(INITIAL_SPACE_ID, 0), (0, false));
+    }
+
+    #[test]
+    fn spaces_keep_their_pcid() {
+        let pcids = PcidSet::new();
+
+        assert_eq!(pcids.assign(10, 0), (1, true));
+        assert_eq!(pcids.assign(11, 0), (2, true));
+
+        assert_eq!(pcids.assign(10, 0), (1, false));
+        assert_eq!(pcids.assign(11, 0), (2, false));
+    }
+
+    #[test]
+    fn flushed_spaces_are_flushed_when_loaded() {
+        let pcids = PcidSet::new();
+
+        assert_eq!(pcids.assign(10, 0), (1, true));
+        assert_eq!(pcids.assign(10, 1), (1, true));
+        assert_eq!(pcids.assign(10, 1), (1, false));
+    }
+
+    #[test]
+    fn pcids_are_recycled_round_robin() {
+        let pcids = PcidSet::new();
+
+        for pcid in 1..NUM_PCIDS {
+            assert_eq!(pcids.assign(pcid as u64 + 100, 0), (pcid, true));
+        }
+
+        // the oldest assignment is replaced first and pcid 0 is never recycled
+        assert_eq!(pcids.assign(200, 0), (1, true));
+        assert_eq!(pcids.assign(101, 0), (2, true));
+        assert_eq!(pcids.assign(103, 0), (3, false));
+    }
+
+    #[test]
+    fn stale_pcids_are_flushed_once() {
+        let pcids = PcidSet::new();
+
+        assert_eq!(pcids.assign(10, 0), (1, true));
+
+        pcids.stale.fetch_or(1 << 1, Ordering::AcqRel);
+
+        assert_eq!(pcids.assign(10, 0), (1, true));
+        assert_eq!(pcids.assign(10, 0), (1, false));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::acpi_tables::AcpiTables;
+use crate::mm;
+use alloc::string::String;
+use alloc::vec::Vec;
+use boot_info::platform_info::PlatformInfo;
+use boot_info::BootInfoHeader;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use initrd::Initrd;
+use kernel_cmdline::{KernelCommandLine, KernelCommandLineParser};
+use kernel_graphics::FrameBufferInfo;
+use kernel_image::KernelImageInfo;
+use log::warn;
+use memory::MemoryMap;
+use spin::Once;
+
+static BOOT_DATA: Once<BootData> = Once::new();
+
+/// The number of cores that have called `boot_complete()`.
+static CORES_DONE: AtomicUsize = AtomicUsize::new(0);
+
+/// A copy of everything from the `BootInfoHeader` the kernel still needs after the
+/// boot info memory has been given back to the frame allocator.
+pub struct BootData {
+    pub kernel_image_info: KernelImageInfo,
+    pub frame_buffer_info: FrameBufferInfo,
+    pub platform_info: PlatformInfo,
+    /// `None` if there are no ACPI tables or they could not be copied.
+    pub acpi_tables: Option<AcpiTables>,
+    pub memory_map: MemoryMap<'static>,
+    pub cmdline: KernelCommandLine,
+    pub boot_log: String,
+    pub num_cores: usize,
+    initrd: &'static [u8],
+    /// Whether `initrd` lives on the kernel heap or still points into the boot info.
+    initrd_copied: bool,
+}
+
+impl BootData {
+    fn new(boot_info: &BootInfoHeader) -> Self {
+        // Safety: the loader guarantees that the initrd is inside the boot info
+        let initrd = unsafe {
+            core::slice::from_raw_parts(boot_info.initrd_addr.as_ptr::<u8>(), boot_info.initrd_size)
+        };
+
+        let cmdline = parse_cmdline(initrd);
+
+        let acpi_tables = AcpiTables::copy(&boot_info.platform_info);
+        if acpi_tables.is_none() {
+            warn!("unable to copy the acpi tables, keeping the acpi reclaimable memory");
+        }
+
+        let mut copy = Vec::new();
+        let initrd_copied = copy.try_reserve_exact(initrd.len()).is_ok();
+
+        let initrd: &'static [u8] = if initrd_copied {
+            copy.extend_from_slice(initrd);
+            copy.leak()
+        } else {
+            warn!("unable to copy the initrd, keeping the boot info memory");
+            initrd
+        };
+
+        BootData {
+            kernel_image_info: boot_info.kernel_image_info.clone(),
+            frame_buffer_info: boot_info.frame_buffer_info.clone(),
+            platform_info: boot_info.platform_info.clone(),
+            acpi_tables,
+            memory_map: MemoryMap::new(boot_info.memory_map().as_slice().to_vec().leak()),
+            cmdline,
+            boot_log: String::from(boot_info.boot_logger.as_str()),
+            num_cores: boot_info.num_cores,
+            initrd,
+            initrd_copied,
+        }
+    }
+
+    pub fn initrd(&self) -> Initrd<'static> {
+        Initrd::new(self.initrd).expect("unable to parse initrd")
+    }
+}
+
+/// Parses the kernel command line from the `cmdline` file of the initrd.
+fn parse_cmdline(initrd: &[u8]) -> KernelCommandLine {
+    let initrd = Initrd::new(initrd).expect("unable to parse initrd");
+
+    let cmdline_file = initrd
+        .file_by_name("cmdline")
+        .expect("kernel command line file not found");
+
+    let cmdline_data = cmdline_file
+        .data_as_str()
+        .expect("kernel command line not valid utf-8");
+
+    KernelCommandLineParser::new(cmdline_data).parse()
+}
+
+/// Copies the needed parts of `boot_info` to the kernel heap.
+pub fn init(boot_info: &BootInfoHeader) {
+    BOOT_DATA.call_once(|| BootData::new(boot_info));
+}
+
+pub fn get() -> &'static BootData {
+    BOOT_DATA
+        .get()
+        .expect("boot_data::get() called before boot_data::init()")
+}
+
+/// Marks the end of the boot process on the current core.
+///
+/// Once every core has called this function, the memory used by the loader and the boot info
+/// is given back to the frame allocator. The `BootInfoHeader` must not be used afterwards.
+///
+/// The ACPI reclaimable memory is given back as well if the ACPI tables have been copied.
+/// Only `acpi_tables` may be used to access them afterwards.
+///
+/// If `meminfo` is given on the kernel command line, a memory usage report is logged last.
+/// If `dump_mappings` is given, the mappings of the kernel address space are logged as well.
+pub fn boot_complete() {
+    let data = get();
+    let done = CORES_DONE.fetch_add(1, Ordering::AcqRel) + 1;
+
+    if done == data.num_cores {
+        // Safety: every core is done with the boot info and runs on the kernel page tables
+        unsafe { mm::reclaim_boot_memory(&data.memory_map, data.initrd_copied) };
+
+        if data.acpi_tables.is_some() {
+            // Safety: the tables have been copied and the copies are used instead
+            unsafe { mm::reclaim_acpi_memory(&data.memory_map) };
+        }
+
+        if data.cmdline.meminfo() {
+            mm::print_meminfo(&data.memory_map);
+        }
+
+        if data.cmdline.dump_mappings() {
+            mm::dump_address_space();
+        }
+    }
+}
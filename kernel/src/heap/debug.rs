@@ -0,0 +1,255 @@
+//! Debugging aids for the kernel heap, enabled by the `heap-debug` feature.
+//!
+//! Every allocation is surrounded by red zones filled with `RED_ZONE_BYTE`, and freed memory is
+//! filled with `POISON_BYTE`. Both are checked when the allocation is freed. All live allocations
+//! are recorded together with the address they were allocated from, see `dump_allocations()`.
+
+use core::alloc::Layout;
+use log::{info, warn};
+use spin::Mutex;
+
+/// The size of the red zones before and after each allocation.
+const RED_ZONE_SIZE: usize = 16;
+
+const RED_ZONE_BYTE: u8 = 0xfd;
+
+const POISON_BYTE: u8 = 0x6b;
+
+/// The maximum number of allocations which can be tracked at once.
+const TABLE_SIZE: usize = 8192;
+
+static TABLE: Mutex<AllocationTable> = Mutex::new(AllocationTable::new());
+
+#[derive(Debug, Copy, Clone)]
+pub struct AllocationRecord {
+    /// The address returned to the caller.
+    pub addr: usize,
+    /// The size requested by the caller.
+    pub size: usize,
+    /// The return address of the allocation function.
+    pub caller: usize,
+}
+
+#[derive(Copy, Clone)]
+enum Slot {
+    Empty,
+    Deleted,
+    Used(AllocationRecord),
+}
+
+/// A hash table of all live allocations indexed by their address.
+///
+/// Note: the table can not use the heap itself, thus it has a fixed size.
+struct AllocationTable {
+    slots: [Slot; TABLE_SIZE],
+    len: usize,
+    /// Set once an allocation could not be recorded because the table was full.
+    overflowed: bool,
+}
+
+impl AllocationTable {
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot::Empty; TABLE_SIZE],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    pub fn insert(&mut self, record: AllocationRecord) {
+        let start = slot_index(record.addr);
+
+        for i in 0..TABLE_SIZE {
+            let slot = &mut self.slots[(start + i) % TABLE_SIZE];
+
+            if matches!(slot, Slot::Empty | Slot::Deleted) {
+                *slot = Slot::Used(record);
+                self.len += 1;
+                return;
+            }
+        }
+
+        if !self.overflowed {
+            warn!("heap-debug: allocation table is full, further allocations are not tracked");
+            self.overflowed = true;
+        }
+    }
+
+    pub fn remove(&mut self, addr: usize) -> Option<AllocationRecord> {
+        let start = slot_index(addr);
+
+        for i in 0..TABLE_SIZE {
+            let slot = &mut self.slots[(start + i) % TABLE_SIZE];
+
+            match *slot {
+                Slot::Empty => return None,
+                Slot::Used(record) if record.addr == addr => {
+                    *slot = Slot::Deleted;
+                    self.len -= 1;
+                    return Some(record);
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+fn slot_index(addr: usize) -> usize {
+    // the lower bits are mostly zero due to alignment
+    (addr >> 4) % TABLE_SIZE
+}
+
+/// Returns the distance between the start of the underlying allocation and the returned address.
+fn front_size(layout: Layout) -> usize {
+    core::cmp::max(RED_ZONE_SIZE, layout.align())
+}
+
+/// Returns the layout of the underlying allocation including both red zones.
+fn outer_layout(layout: Layout) -> Option<Layout> {
+    let size = front_size(layout)
+        .checked_add(layout.size())?
+        .checked_add(RED_ZONE_SIZE)?;
+
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Allocates memory for `layout` with red zones through `inner` and records the allocation.
+pub fn alloc(layout: Layout, caller: usize, inner: impl FnOnce(Layout) -> *mut u8) -> *mut u8 {
+    let Some(outer) = outer_layout(layout) else {
+        return core::ptr::null_mut();
+    };
+
+    let base = inner(outer);
+
+    if base.is_null() {
+        return base;
+    }
+
+    let front = front_size(layout);
+
+    // Safety: `base` points to `outer.size()` bytes of memory
+    let ptr = unsafe {
+        core::ptr::write_bytes(base, RED_ZONE_BYTE, front);
+        core::ptr::write_bytes(
+            base.add(front + layout.size()),
+            RED_ZONE_BYTE,
+            RED_ZONE_SIZE,
+        );
+        base.add(front)
+    };
+
+    TABLE.lock().insert(AllocationRecord {
+        addr: ptr as usize,
+        size: layout.size(),
+        caller,
+    });
+
+    ptr
+}
+
+/// Checks and poisons the allocation at `ptr` and frees it through `inner`.
+///
+/// # Safety
+/// `ptr` must have been allocated by `alloc()` with the same `layout`.
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout, inner: impl FnOnce(*mut u8, Layout)) {
+    let addr = ptr as usize;
+    let front = front_size(layout);
+    let outer = outer_layout(layout).expect("invalid layout");
+
+    let record = {
+        let mut table = TABLE.lock();
+        let record = table.remove(addr);
+
+        if record.is_none() && !table.overflowed {
+            drop(table);
+
+            // Safety: the memory is either still allocated or has been poisoned
+            let poisoned = unsafe { all_bytes(ptr, layout.size(), POISON_BYTE) };
+
+            if poisoned {
+                panic!("heap-debug: double free of {:#x} ({:?})", addr, layout);
+            } else {
+                panic!(
+                    "heap-debug: free of unknown allocation {:#x} ({:?})",
+                    addr, layout
+                );
+            }
+        }
+
+        record
+    };
+
+    let caller = record.map_or(0, |record| record.caller);
+
+    if let Some(record) = record {
+        if record.size != layout.size() {
+            panic!(
+                "heap-debug: {:#x} allocated with {} bytes from {:#x} but freed with {:?}",
+                addr, record.size, caller, layout
+            );
+        }
+    }
+
+    // Safety: the red zones are part of the underlying allocation
+    let (front_intact, back_intact) = unsafe {
+        let base = ptr.sub(front);
+        (
+            all_bytes(base, front, RED_ZONE_BYTE),
+            all_bytes(ptr.add(layout.size()), RED_ZONE_SIZE, RED_ZONE_BYTE),
+        )
+    };
+
+    if !front_intact || !back_intact {
+        panic!(
+            "heap-debug: red zone {} {:#x} ({} bytes, allocated from {:#x}) has been overwritten",
+            if front_intact { "after" } else { "before" },
+            addr,
+            layout.size(),
+            caller
+        );
+    }
+
+    unsafe {
+        let base = ptr.sub(front);
+        core::ptr::write_bytes(base, POISON_BYTE, outer.size());
+        inner(base, outer);
+    }
+}
+
+/// Logs all live allocations. Allocations which are still listed after their owner has
+/// been dropped are leaked.
+pub fn dump_allocations() {
+    let (len, overflowed) = {
+        let table = TABLE.lock();
+        (table.len, table.overflowed)
+    };
+
+    info!("heap-debug: {} live allocations", len);
+
+    if overflowed {
+        info!("heap-debug: the allocation table overflowed, the list is incomplete");
+    }
+
+    for idx in 0..TABLE_SIZE {
+        // Note: the lock must not be held while logging
+        let slot = TABLE.lock().slots[idx];
+
+        if let Slot::Used(record) = slot {
+            info!(
+                "  {:#x}: {} bytes allocated from {:#x}",
+                record.addr, record.size, record.caller
+            );
+        }
+    }
+}
+
+/// Checks if all `len` bytes at `ptr` are equal to `byte`.
+///
+/// # Safety
+/// `ptr` must point to `len` readable bytes.
+unsafe fn all_bytes(ptr: *const u8, len: usize, byte: u8) -> bool {
+    let slice = unsafe { core::slice::from_raw_parts(ptr, len) };
+    slice.iter().all(|b| *b == byte)
+}
@@ -0,0 +1,546 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+
+use crate::arch::paging::{holds_page_lock, with_mapper};
+use crate::mm::{flush_range, FaultLock, GlobalFrameAllocator, KernelVirtualAllocator};
+use alloc::vec::Vec;
+use boot_info::BootInfoHeader;
+use log::info;
+use memory::phys::PageFrameAllocator;
+use memory::virt::{Page, VirtAddr, VirtualRange, VirtualRangeAllocator};
+use memory::{AccessFlags, PAGE_SIZE};
+use spin::{Mutex, Once};
+
+#[cfg(feature = "heap-debug")]
+mod debug;
+mod slab;
+
+#[cfg(feature = "heap-debug")]
+pub use debug::{dump_allocations, AllocationRecord};
+
+pub use slab::SizeClassStats;
+use slab::{size_class_index, SlabCache, NUM_SIZE_CLASSES, SIZE_CLASSES};
+
+// Note: unit tests use the allocator of the host
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: HeapAllocator = HeapAllocator::empty();
+
+static INIT: Once<()> = Once::new();
+
+static GROWTH_INIT: Once<()> = Once::new();
+
+/// The growth window is not set up if less than this many pages of virtual memory are available.
+const MIN_GROWTH_PAGES: usize = 256;
+
+/// The heap can grow into at most this fraction of the physical memory.
+const MAX_GROWTH_DIVISOR: usize = 4;
+
+/// The kernel heap serves small allocations from slab caches with one cache per size class.
+/// The pages of the caches as well as allocations which are larger than the largest size class
+/// are taken from the initial heap set up by the loader and, once that is exhausted, from the
+/// growth window.
+struct HeapAllocator {
+    caches: [Mutex<SlabCache>; NUM_SIZE_CLASSES],
+    /// The pages of the initial heap, which are mapped by the loader. `None` until `init()`
+    /// has been called.
+    initial: Mutex<Option<PageBitmap>>,
+    /// The virtual memory the heap can grow into. Its pages are mapped to frames of the
+    /// `GlobalFrameAllocator` when they are allocated and unmapped again when they are freed.
+    /// `None` until `init_growth()` has been called.
+    growth: Mutex<Option<PageBitmap>>,
+}
+
+/// Allocates whole pages of a virtual range.
+struct PageBitmap {
+    range: VirtualRange,
+    /// One bit per page of `range`, set if the page is allocated.
+    allocated: &'static mut [u64],
+    /// The number of allocated pages.
+    used: usize,
+}
+
+pub struct HeapStats {
+    /// The number of bytes in use by allocations.
+    pub used: usize,
+    /// The number of bytes available for allocations.
+    pub free: usize,
+    /// The size of the heap in bytes.
+    pub total: usize,
+    /// The usage of each size class.
+    pub classes: [SizeClassStats; NUM_SIZE_CLASSES],
+}
+
+impl HeapAllocator {
+    pub const fn empty() -> Self {
+        Self {
+            caches: [
+                Mutex::new(SlabCache::new(SIZE_CLASSES[0])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[1])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[2])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[3])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[4])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[5])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[6])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[7])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[8])),
+            ],
+            initial: Mutex::new(None),
+            growth: Mutex::new(None),
+        }
+    }
+
+    pub fn init(&self, initial: PageBitmap) {
+        *self.initial.lock() = Some(initial);
+    }
+
+    pub fn init_growth(&self, window: PageBitmap) {
+        *self.growth.lock() = Some(window);
+    }
+
+    fn stats(&self) -> HeapStats {
+        let _fault_lock = FaultLock::acquire();
+        let classes = core::array::from_fn(|idx| self.caches[idx].lock().stats());
+
+        let (initial_size, initial_used) = self
+            .initial
+            .lock()
+            .as_ref()
+            .map_or((0, 0), |initial| (initial.size(), initial.used_size()));
+        let grown = self
+            .growth
+            .lock()
+            .as_ref()
+            .map_or(0, |window| window.used_size());
+        let total = initial_size + grown;
+
+        // slab pages are only partially used by allocations
+        let slab_pages: usize = classes.iter().map(|class| class.pages * PAGE_SIZE).sum();
+        let slab_used: usize = classes
+            .iter()
+            .map(|class| class.used * class.object_size)
+            .sum();
+
+        let used = initial_used + grown - slab_pages + slab_used;
+        let free = total - used;
+
+        HeapStats {
+            used,
+            free,
+            total,
+            classes,
+        }
+    }
+
+    fn alloc_inner(&self, layout: Layout) -> *mut u8 {
+        let ptr = match size_class_index(layout) {
+            Some(idx) => self.caches[idx]
+                .lock()
+                .alloc(|| self.alloc_pages(Layout::new::<[u8; PAGE_SIZE]>())),
+            None => self.alloc_pages(layout),
+        };
+
+        ptr.map_or(core::ptr::null_mut(), |nn| nn.as_ptr())
+    }
+
+    /// # Safety
+    /// `ptr` must have been allocated by `alloc_inner()` with the same `layout`.
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = unsafe { NonNull::new_unchecked(ptr) };
+
+        // Note: the size class only depends on the layout, so it is the same as in `alloc_inner()`
+        match size_class_index(layout) {
+            Some(idx) => unsafe { self.caches[idx].lock().dealloc(ptr) },
+            None => unsafe { self.dealloc_pages(ptr, layout) },
+        }
+    }
+
+    /// Allocates whole pages for `layout`, first from the initial heap and then from the
+    /// growth window.
+    fn alloc_pages(&self, layout: Layout) -> Option<NonNull<u8>> {
+        // mapping pages of the growth window takes the page lock, so this would deadlock
+        debug_assert!(
+            !holds_page_lock(),
+            "the kernel heap must not be used while holding the page lock"
+        );
+
+        let layout = page_layout(layout)?;
+        let num_pages = layout.size() / PAGE_SIZE;
+        let align = layout.align() / PAGE_SIZE;
+
+        let initial = self
+            .initial
+            .lock()
+            .as_mut()
+            .and_then(|initial| initial.alloc(num_pages, align));
+
+        match initial {
+            Some(range) => NonNull::new(range.start_addr().as_ptr_mut()),
+            None => self.alloc_grown(num_pages, align),
+        }
+    }
+
+    /// Allocates pages of the growth window and maps them to new frames.
+    ///
+    /// Note: this must not allocate from the heap itself, since the caller might
+    /// hold the lock of a slab cache.
+    fn alloc_grown(&self, num_pages: usize, align: usize) -> Option<NonNull<u8>> {
+        let range = self.growth.lock().as_mut()?.alloc(num_pages, align)?;
+
+        // Note: the pages are mapped without holding the lock, they are reserved for the caller
+        if map_heap_range(range).is_none() {
+            self.release_grown(range);
+            return None;
+        }
+
+        NonNull::new(range.start_addr().as_ptr_mut())
+    }
+
+    /// # Safety
+    /// `ptr` must have been allocated by `alloc_pages()` with the same `layout`.
+    unsafe fn dealloc_pages(&self, ptr: NonNull<u8>, layout: Layout) {
+        let layout = page_layout(layout).expect("invalid layout");
+
+        let page = Page::new(VirtAddr::new(ptr.as_ptr() as usize));
+        let range = VirtualRange::with_size(page, layout.size() / PAGE_SIZE);
+
+        if let Some(initial) = self.initial.lock().as_mut() {
+            if initial.range.contains_range(range) {
+                initial.release(range);
+                return;
+            }
+        }
+
+        // Safety: the pages belong to the freed allocation
+        unsafe { unmap_heap_range(range) };
+        self.release_grown(range);
+    }
+
+    /// Makes the pages in `range` available to `alloc_grown()` again. They must be unmapped.
+    fn release_grown(&self, range: VirtualRange) {
+        self.growth
+            .lock()
+            .as_mut()
+            .expect("growth window not initialized")
+            .release(range);
+    }
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    // Note: inlined so that the return address points to the caller of the allocation shim
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // the page fault handler allocates as well, so a fault must not be resolved in here
+        let _fault_lock = FaultLock::acquire();
+
+        #[cfg(feature = "heap-debug")]
+        {
+            let caller = core::intrinsics::return_address() as usize;
+            debug::alloc(layout, caller, |layout| self.alloc_inner(layout))
+        }
+
+        #[cfg(not(feature = "heap-debug"))]
+        self.alloc_inner(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _fault_lock = FaultLock::acquire();
+
+        #[cfg(feature = "heap-debug")]
+        unsafe {
+            debug::dealloc(ptr, layout, |ptr, layout| self.dealloc_inner(ptr, layout))
+        }
+
+        #[cfg(not(feature = "heap-debug"))]
+        unsafe {
+            self.dealloc_inner(ptr, layout)
+        }
+    }
+}
+
+impl PageBitmap {
+    /// Creates a bitmap for `range` on the kernel heap.
+    /// Returns `None` if the bitmap could not be allocated.
+    fn new(range: VirtualRange) -> Option<Self> {
+        let mut allocated = Vec::new();
+        let len = bitmap_len(range);
+
+        allocated.try_reserve_exact(len).ok()?;
+        allocated.resize(len, 0);
+
+        Some(Self {
+            range,
+            allocated: Vec::leak(allocated),
+            used: 0,
+        })
+    }
+
+    /// Creates a bitmap for `range` which is stored in the first pages of the range itself.
+    /// These pages are marked as allocated.
+    ///
+    /// # Safety
+    /// `range` must be mapped writable and must only be used through the bitmap.
+    unsafe fn in_place(range: VirtualRange) -> Self {
+        let len = bitmap_len(range);
+        let bitmap_pages = (len * core::mem::size_of::<u64>()).div_ceil(PAGE_SIZE);
+
+        assert!(
+            bitmap_pages < range.num_pages(),
+            "the initial heap is too small"
+        );
+
+        // Safety: the caller guarantees that the memory is ours and every bit pattern is a valid u64
+        let allocated =
+            unsafe { core::slice::from_raw_parts_mut(range.start_addr().as_ptr_mut(), len) };
+        allocated.fill(0);
+
+        let mut bitmap = Self {
+            range,
+            allocated,
+            used: 0,
+        };
+
+        bitmap
+            .alloc(bitmap_pages, 1)
+            .expect("unable to reserve the pages of the bitmap");
+
+        bitmap
+    }
+
+    /// The size of the range in bytes.
+    fn size(&self) -> usize {
+        self.range.num_pages() * PAGE_SIZE
+    }
+
+    /// The size of the allocated pages in bytes.
+    fn used_size(&self) -> usize {
+        self.used * PAGE_SIZE
+    }
+
+    /// Reserves `num_pages` consecutive pages whose first page is aligned to `align` pages.
+    fn alloc(&mut self, num_pages: usize, align: usize) -> Option<VirtualRange> {
+        let base = self.range.start().to_inner();
+        let mut idx = 0;
+
+        loop {
+            let free = self.next_free(idx)?;
+            let start = (base + free).checked_next_multiple_of(align)? - base;
+            let end = start.checked_add(num_pages)?;
+
+            if end > self.range.num_pages() {
+                return None;
+            }
+
+            match (start..end).rev().find(|&idx| self.is_allocated(idx)) {
+                // no run can contain an allocated page, so continue behind it
+                Some(allocated) => idx = allocated + 1,
+                None => {
+                    (start..end).for_each(|idx| self.set_allocated(idx, true));
+                    self.used += num_pages;
+
+                    return Some(VirtualRange::with_size(
+                        self.range.start().add(start),
+                        num_pages,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Releases the pages in `range` which must have been reserved by `alloc()`.
+    fn release(&mut self, range: VirtualRange) {
+        let start = range.start().diff(self.range.start());
+
+        for idx in start..start + range.num_pages() {
+            debug_assert!(self.is_allocated(idx), "heap page freed twice");
+            self.set_allocated(idx, false);
+        }
+
+        self.used -= range.num_pages();
+    }
+
+    /// Returns the index of the first free page at or after `idx`.
+    fn next_free(&self, mut idx: usize) -> Option<usize> {
+        while idx < self.range.num_pages() {
+            let word = idx / u64::BITS as usize;
+            let bit = idx % u64::BITS as usize;
+            let free = !self.allocated[word] >> bit;
+
+            if free != 0 {
+                let idx = idx + free.trailing_zeros() as usize;
+                return (idx < self.range.num_pages()).then_some(idx);
+            }
+
+            // skip the rest of the word, all of its pages are allocated
+            idx += u64::BITS as usize - bit;
+        }
+
+        None
+    }
+
+    fn is_allocated(&self, idx: usize) -> bool {
+        self.allocated[idx / u64::BITS as usize] & (1 << (idx % u64::BITS as usize)) != 0
+    }
+
+    fn set_allocated(&mut self, idx: usize, allocated: bool) {
+        let mask = 1 << (idx % u64::BITS as usize);
+        let word = &mut self.allocated[idx / u64::BITS as usize];
+
+        if allocated {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+    }
+}
+
+/// Maps every page in `range` to a newly allocated frame.
+/// Nothing remains mapped if not all pages could be mapped.
+fn map_heap_range(range: VirtualRange) -> Option<()> {
+    let mapped = with_mapper(|mapper| {
+        for page in range.pages() {
+            let mapped = GlobalFrameAllocator.alloc().and_then(|frame| {
+                match mapper.map(page, frame, AccessFlags::READ_WRITE) {
+                    Ok(()) => Some(()),
+                    Err(_) => {
+                        GlobalFrameAllocator.dealloc(frame);
+                        None
+                    }
+                }
+            });
+
+            if mapped.is_none() {
+                for page in range.start()..page {
+                    let frame = mapper.unmap(page).expect("unable to unmap heap page");
+                    GlobalFrameAllocator.dealloc(frame);
+                }
+
+                return Err(VirtualRange::new(range.start(), page));
+            }
+        }
+
+        Ok(())
+    });
+
+    // the pages have never been used, but other cores might still have cached their mappings
+    mapped.map_err(flush_range).ok()
+}
+
+/// Unmaps every page in `range` and returns its frame to the `GlobalFrameAllocator`.
+///
+/// # Safety
+/// The pages in `range` must have been mapped by `map_heap_range()` and must not be
+/// accessed anymore.
+unsafe fn unmap_heap_range(range: VirtualRange) {
+    with_mapper(|mapper| {
+        for page in range.pages() {
+            let frame = mapper.unmap(page).expect("unable to unmap heap page");
+            GlobalFrameAllocator.dealloc(frame);
+        }
+    });
+
+    flush_range(range);
+}
+
+/// Returns the number of words a `PageBitmap` for `range` needs.
+fn bitmap_len(range: VirtualRange) -> usize {
+    range.num_pages().div_ceil(u64::BITS as usize)
+}
+
+/// Rounds `layout` up to whole pages.
+fn page_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_next_multiple_of(PAGE_SIZE)?;
+    let align = core::cmp::max(layout.align(), PAGE_SIZE);
+    Layout::from_size_align(size, align).ok()
+}
+
+/// Returns the current usage of the kernel heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("failed to allocate: {:?}", layout);
+}
+
+/// Initialize the kernel heap.
+pub fn init(boot_info: &BootInfoHeader) {
+    INIT.call_once(|| {
+        let range = boot_info.kernel_image_info.heap;
+
+        // Safety: range is assumed to be accessible memory with a 'static lifetime.
+        unsafe {
+            init_unchecked(range);
+        }
+
+        let total = ALLOCATOR.stats().total;
+        info!(
+            "the kernel heap has a size of {} MiB",
+            total / (1024 * 1024)
+        );
+    });
+}
+
+/// Reserves virtual memory for the kernel heap to grow into. Before this has been called,
+/// the heap is limited to its initial size.
+///
+/// This must be called after the frame allocator and the paging have been initialized.
+pub fn init_growth() {
+    GROWTH_INIT.call_once(|| {
+        let total_frames: usize = GlobalFrameAllocator
+            .zone_stats()
+            .iter()
+            .map(|stats| stats.total_frames)
+            .sum();
+
+        // the rest of the physical memory and the kernel address space is left to other users
+        let mut num_pages = total_frames / MAX_GROWTH_DIVISOR;
+
+        let window = loop {
+            if num_pages < MIN_GROWTH_PAGES {
+                info!("unable to reserve virtual memory for the kernel heap");
+                return;
+            }
+
+            let Some(range) = KernelVirtualAllocator.alloc(num_pages, 1) else {
+                num_pages /= 2;
+                continue;
+            };
+
+            match PageBitmap::new(range) {
+                Some(window) => break window,
+                None => {
+                    KernelVirtualAllocator
+                        .dealloc(range)
+                        .expect("unable to free virtual range");
+                    num_pages /= 2;
+                }
+            }
+        };
+
+        let window_size = window.size();
+        ALLOCATOR.init_growth(window);
+
+        info!(
+            "the kernel heap can grow up to {} MiB",
+            (ALLOCATOR.stats().total + window_size) / (1024 * 1024)
+        );
+    });
+}
+
+/// Initialize the kernel heap from memory in `heap_memory`.
+///
+/// # Safety
+/// - All memory in the range of `heap_memory` must be safe to access for
+///   the `'static` lifetime.
+/// - This function must only be called once.
+unsafe fn init_unchecked(heap_memory: VirtualRange) {
+    // Safety:
+    // The heap memory is only ever used here and nowhere else.
+    let initial = unsafe { PageBitmap::in_place(heap_memory) };
+
+    ALLOCATOR.init(initial);
+}
@@ -0,0 +1,134 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use memory::PAGE_SIZE;
+
+/// The object sizes of the slab caches. Larger allocations are served by the page allocator.
+pub const SIZE_CLASSES: [usize; 9] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+pub const NUM_SIZE_CLASSES: usize = SIZE_CLASSES.len();
+
+/// Returns the index of the smallest size class that can hold `layout`.
+///
+/// Objects are aligned to their size, since all size classes are powers of two and the
+/// slab pages are page aligned.
+pub fn size_class_index(layout: Layout) -> Option<usize> {
+    let size = core::cmp::max(layout.size(), layout.align());
+    SIZE_CLASSES.iter().position(|class| *class >= size)
+}
+
+/// Usage of a single size class.
+#[derive(Debug, Copy, Clone)]
+pub struct SizeClassStats {
+    /// The size in bytes of each object.
+    pub object_size: usize,
+    /// The number of pages owned by this size class.
+    pub pages: usize,
+    /// The number of allocated objects.
+    pub used: usize,
+    /// The number of free objects.
+    pub free: usize,
+}
+
+/// Free objects are linked together through their own memory.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// A cache of equally sized objects carved out of whole pages.
+///
+/// Pages are never returned by the cache, freed objects are kept for later allocations.
+pub struct SlabCache {
+    object_size: usize,
+    free_list: Option<NonNull<FreeObject>>,
+    pages: usize,
+    used: usize,
+    free: usize,
+}
+
+// Safety: the free objects are only accessed through the cache.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            free_list: None,
+            pages: 0,
+            used: 0,
+            free: 0,
+        }
+    }
+
+    /// Allocates an object. If the cache is empty, a new page is obtained through `alloc_page`.
+    pub fn alloc(
+        &mut self,
+        alloc_page: impl FnOnce() -> Option<NonNull<u8>>,
+    ) -> Option<NonNull<u8>> {
+        if self.free_list.is_none() {
+            let page = alloc_page()?;
+
+            // Safety: the page has just been allocated for this cache
+            unsafe { self.add_page(page) };
+        }
+
+        let obj = self.free_list?;
+
+        // Safety: all objects in the free list are valid `FreeObject`s
+        self.free_list = unsafe { obj.as_ref().next };
+        self.used += 1;
+        self.free -= 1;
+
+        Some(obj.cast())
+    }
+
+    /// Returns an object to the cache.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated from this cache and must not be used anymore.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>) {
+        let obj = ptr.cast::<FreeObject>();
+
+        unsafe {
+            obj.as_ptr().write(FreeObject {
+                next: self.free_list,
+            })
+        };
+
+        self.free_list = Some(obj);
+        self.used -= 1;
+        self.free += 1;
+    }
+
+    pub fn stats(&self) -> SizeClassStats {
+        SizeClassStats {
+            object_size: self.object_size,
+            pages: self.pages,
+            used: self.used,
+            free: self.free,
+        }
+    }
+
+    /// Splits `page` into objects and puts them into the free list.
+    ///
+    /// # Safety
+    /// `page` must point to `PAGE_SIZE` bytes of page aligned memory owned by this cache.
+    unsafe fn add_page(&mut self, page: NonNull<u8>) {
+        let count = PAGE_SIZE / self.object_size;
+
+        // link the objects in ascending order
+        for i in (0..count).rev() {
+            let obj = unsafe { page.as_ptr().add(i * self.object_size) } as *mut FreeObject;
+
+            unsafe {
+                obj.write(FreeObject {
+                    next: self.free_list,
+                })
+            };
+
+            self.free_list = NonNull::new(obj);
+        }
+
+        self.pages += 1;
+        self.free += count;
+    }
+}
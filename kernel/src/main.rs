@@ -1,5 +1,6 @@
-#![no_std]
-#![no_main]
+// unit tests run on the host with the standard library
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![deny(unsafe_op_in_unsafe_fn)]
 #![allow(dead_code)]
 // needed for gs_deref!() macro
@@ -12,6 +13,9 @@
 #![feature(abi_x86_interrupt)]
 // used for fallible allocations
 #![feature(vec_push_within_capacity)]
+// needed for caller addresses in heap debugging mode
+#![cfg_attr(feature = "heap-debug", feature(core_intrinsics))]
+#![cfg_attr(feature = "heap-debug", allow(internal_features))]
 
 extern crate alloc;
 
@@ -24,10 +28,13 @@ use boot_info::BootInfoHeader;
 use memory::phys::{Frame, PageFrameAllocator, PhysicalRange};
 use memory::FRAME_SIZE;
 
+mod acpi_tables;
 mod arch;
+mod boot_data;
 mod heap;
 mod kresult;
 mod mm;
+#[cfg(not(test))]
 mod panic_handler;
 
 #[no_mangle]
@@ -42,10 +49,20 @@ pub extern "C" fn kernel_main(boot_info: &BootInfoHeader, proc_id: usize) -> ! {
 
     mm::init(boot_info);
 
-    let fb = &boot_info.frame_buffer_info;
+    heap::init_growth();
+
+    arch::interrupts::init();
+
+    mm::join_shootdowns();
+
+    boot_data::init(boot_info);
+
+    let fb = &boot_data::get().frame_buffer_info;
     let fixed = FixedFrameAllocator::new(fb.physical_range());
     let pmo = PhysicalMemoryObject::new_shared_in(fixed.num_frames(), fixed).unwrap();
 
+    boot_data::boot_complete();
+
     info!("[CPU {}]: done", proc_id);
     arch::cpu::halt();
 }
@@ -0,0 +1,28 @@
+use crate::arch::paging::{try_with_walker, with_walker};
+use log::info;
+use memory::virt::{Page, VirtAddr};
+
+/// Logs a compact map of all mappings of the current address space.
+pub fn dump_address_space() {
+    info!("mappings of the current address space:");
+
+    // Note: the mappings are logged while holding the page lock
+    with_walker(|walker| walker.for_each_mapping(|mapping| info!("  {}", mapping)));
+}
+
+/// Logs the page table entries of all levels which are used to translate `addr`.
+///
+/// Nothing but a note is logged if the page tables are locked, so that this can be used
+/// from the page fault handler.
+pub fn dump_translation(addr: VirtAddr) {
+    match try_with_walker(|walker| walker.walk(Page::new(addr))) {
+        Some(walk) => {
+            info!("translation of {:?}:", addr);
+
+            for entry in walk.entries() {
+                info!("  {:?}", entry);
+            }
+        }
+        None => info!("translation of {:?}: page tables are locked", addr),
+    }
+}
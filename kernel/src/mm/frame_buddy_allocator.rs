@@ -0,0 +1,360 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use memory::phys::{Frame, Inner, PhysicalRange};
+
+/// The largest block handed out by the buddy allocator consists of `2^MAX_ORDER` frames.
+pub const MAX_ORDER: usize = 10;
+
+const NUM_ORDERS: usize = MAX_ORDER + 1;
+
+/// A binary buddy allocator managing a contiguous range of physical frames.
+///
+/// The free blocks of every order are tracked in a bitmap that lives on the kernel heap.
+/// This way the allocator never has to touch the memory it manages, which is important
+/// because most physical memory is not mapped into the kernel address space.
+pub struct FrameBuddyAllocator {
+    range: PhysicalRange,
+    /// Frame number of `range.start()` aligned down to the size of the largest block.
+    /// All block indices are relative to this frame, so blocks of order `n` are always
+    /// physically aligned to `2^n` frames.
+    base: Inner,
+    orders: Vec<FreeBlocks>,
+}
+
+impl FrameBuddyAllocator {
+    pub fn new(range: PhysicalRange) -> Self {
+        let max_block_frames = 1 << MAX_ORDER;
+        let base = range.start().to_inner() & !(max_block_frames - 1);
+        let span = (range.end().to_inner() - base) as usize;
+
+        let orders = (0..NUM_ORDERS)
+            .map(|order| FreeBlocks::new(span.div_ceil(1 << order)))
+            .collect();
+
+        let mut allocator = Self {
+            range,
+            base,
+            orders,
+        };
+
+        allocator.dealloc_range(range);
+        allocator
+    }
+
+    pub fn range(&self) -> PhysicalRange {
+        self.range
+    }
+
+    pub fn contains(&self, frame: Frame) -> bool {
+        self.range.contains_frame(frame)
+    }
+
+    pub fn alloc(&mut self) -> Option<Frame> {
+        self.alloc_order(0)
+    }
+
+    pub fn alloc_specific(&mut self, frame: Frame) -> Option<()> {
+        if !self.contains(frame) {
+            return None;
+        }
+
+        // find the free block containing `frame` and split it until only `frame` is taken
+        let order = (0..NUM_ORDERS).find(|&order| {
+            let idx = self.block_index(frame, order);
+            self.orders[order].remove(idx)
+        })?;
+
+        for lower in (0..order).rev() {
+            let idx = self.block_index(frame, lower);
+            self.orders[lower].insert(idx ^ 1);
+        }
+
+        Some(())
+    }
+
+    pub fn dealloc(&mut self, frame: Frame) {
+        debug_assert!(self.contains(frame));
+        debug_assert!(
+            !self.is_free(frame),
+            "double free of {:?} in FrameBuddyAllocator",
+            frame
+        );
+
+        self.dealloc_order(frame, 0);
+    }
+
+    /// Allocates `num_frames` physically contiguous frames aligned to `alignment` frames.
+    /// If `limit` is given, the whole range will be below that frame.
+    ///
+    /// The range is carved out of a single block, so at most `2^MAX_ORDER` frames can be
+    /// allocated at once.
+    pub fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<Frame>,
+    ) -> Option<PhysicalRange> {
+        debug_assert!(alignment.is_power_of_two());
+
+        if num_frames == 0 {
+            return None;
+        }
+
+        let size = core::cmp::max(num_frames.checked_next_power_of_two()?, alignment);
+        let order = size.trailing_zeros() as usize;
+
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let start = self.alloc_order_below(order, limit)?;
+        let block = PhysicalRange::with_size(start, size as Inner);
+        let range = PhysicalRange::with_size(start, num_frames as Inner);
+
+        // the part of the block that was not requested goes straight back
+        self.dealloc_range(PhysicalRange::new(range.end(), block.end()));
+
+        Some(range)
+    }
+
+    /// Allocates a naturally aligned block of `2^order` frames.
+    fn alloc_order(&mut self, order: usize) -> Option<Frame> {
+        self.alloc_order_below(order, None)
+    }
+
+    /// Allocates a naturally aligned block of `2^order` frames which ends at or before `limit`.
+    fn alloc_order_below(&mut self, order: usize, limit: Option<Frame>) -> Option<Frame> {
+        let fits = |start: Frame| match limit {
+            Some(limit) => start
+                .checked_add(1 << order)
+                .is_some_and(|end| end <= limit),
+            None => true,
+        };
+
+        // Only the lowest free block of every order needs to be checked, because the
+        // requested block is always taken from the start of a larger one.
+        let (found, mut idx) = (order..NUM_ORDERS).find_map(|found| {
+            let idx = self.orders[found].first()?;
+            fits(self.block_frame(idx, found)).then_some((found, idx))
+        })?;
+
+        self.orders[found].remove(idx);
+
+        // give the upper halves back until the block has the requested size
+        for lower in (order..found).rev() {
+            idx <<= 1;
+            self.orders[lower].insert(idx ^ 1);
+        }
+
+        Some(self.block_frame(idx, order))
+    }
+
+    /// Frees all frames of `range`, using the largest naturally aligned blocks that fit.
+    fn dealloc_range(&mut self, range: PhysicalRange) {
+        let mut frame = range.start();
+
+        while frame < range.end() {
+            let remaining = range.end().diff(frame);
+            let order = (0..NUM_ORDERS)
+                .rev()
+                .find(|&order| {
+                    frame.to_inner().is_multiple_of(1 << order) && remaining >= (1 << order)
+                })
+                .unwrap();
+
+            self.dealloc_order(frame, order);
+            frame = frame.add(1 << order);
+        }
+    }
+
+    /// Frees a block of `2^order` frames and merges it with its buddies as far as possible.
+    fn dealloc_order(&mut self, frame: Frame, order: usize) {
+        let mut order = order;
+        let mut idx = self.block_index(frame, order);
+
+        while order < MAX_ORDER && self.orders[order].remove(idx ^ 1) {
+            idx >>= 1;
+            order += 1;
+        }
+
+        self.orders[order].insert(idx);
+    }
+
+    /// Checks whether `frame` is part of any free block.
+    fn is_free(&self, frame: Frame) -> bool {
+        (0..NUM_ORDERS).any(|order| {
+            let idx = self.block_index(frame, order);
+            self.orders[order].is_free(idx)
+        })
+    }
+
+    fn block_index(&self, frame: Frame, order: usize) -> usize {
+        ((frame.to_inner() - self.base) >> order) as usize
+    }
+
+    fn block_frame(&self, idx: usize, order: usize) -> Frame {
+        Frame::from_inner(self.base + ((idx << order) as Inner))
+    }
+}
+
+/// Bitmap of the free blocks of a single order.
+struct FreeBlocks {
+    bits: Vec<u64>,
+    num_free: usize,
+    /// Index of the first word that might contain a set bit.
+    hint: usize,
+}
+
+impl FreeBlocks {
+    fn new(num_blocks: usize) -> Self {
+        Self {
+            bits: vec![0; num_blocks.div_ceil(64)],
+            num_free: 0,
+            hint: 0,
+        }
+    }
+
+    fn is_free(&self, idx: usize) -> bool {
+        self.bits
+            .get(idx / 64)
+            .is_some_and(|word| word & (1 << (idx % 64)) != 0)
+    }
+
+    fn insert(&mut self, idx: usize) {
+        debug_assert!(!self.is_free(idx));
+
+        self.bits[idx / 64] |= 1 << (idx % 64);
+        self.num_free += 1;
+        self.hint = core::cmp::min(self.hint, idx / 64);
+    }
+
+    fn remove(&mut self, idx: usize) -> bool {
+        if self.is_free(idx) {
+            self.bits[idx / 64] &= !(1 << (idx % 64));
+            self.num_free -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the lowest free block.
+    fn first(&mut self) -> Option<usize> {
+        if self.num_free == 0 {
+            return None;
+        }
+
+        let word_idx = (self.hint..self.bits.len()).find(|&i| self.bits[i] != 0)?;
+        self.hint = word_idx;
+
+        Some(word_idx * 64 + self.bits[word_idx].trailing_zeros() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(inner: Inner) -> Frame {
+        Frame::from_inner(inner)
+    }
+
+    fn allocator(start: Inner, num_frames: Inner) -> FrameBuddyAllocator {
+        FrameBuddyAllocator::new(PhysicalRange::with_size(frame(start), num_frames))
+    }
+
+    /// Allocates single frames until the allocator is empty.
+    fn drain(allocator: &mut FrameBuddyAllocator) -> Vec<Frame> {
+        core::iter::from_fn(|| allocator.alloc()).collect()
+    }
+
+    #[test]
+    fn allocates_every_frame_once() {
+        let mut allocator = allocator(0x103, 0x1234);
+
+        let mut frames = drain(&mut allocator);
+        frames.sort();
+        frames.dedup();
+
+        assert_eq!(frames.len(), 0x1234);
+        assert!(frames.iter().all(|f| allocator.contains(*f)));
+    }
+
+    #[test]
+    fn splits_blocks_lowest_first() {
+        let mut allocator = allocator(0x400, 0x400);
+
+        assert_eq!(allocator.alloc(), Some(frame(0x400)));
+        assert_eq!(allocator.alloc(), Some(frame(0x401)));
+
+        // the split left one free block of every order below MAX_ORDER
+        assert!((1..MAX_ORDER).all(|order| allocator.orders[order].num_free == 1));
+        assert_eq!(allocator.orders[MAX_ORDER].num_free, 0);
+    }
+
+    #[test]
+    fn merges_buddies_on_dealloc() {
+        let mut allocator = allocator(0, 2 << MAX_ORDER);
+
+        let frames = drain(&mut allocator);
+        for frame in frames {
+            allocator.dealloc(frame);
+        }
+
+        assert_eq!(allocator.orders[MAX_ORDER].num_free, 2);
+        assert!((0..MAX_ORDER).all(|order| allocator.orders[order].num_free == 0));
+    }
+
+    #[test]
+    fn contiguous_allocations_are_aligned() {
+        let mut allocator = allocator(0x1, 0x3ff);
+
+        let range = allocator.alloc_contiguous(3, 8, None).unwrap();
+        assert_eq!(range.num_frames(), 3);
+        assert_eq!(range.start().to_inner() % 8, 0);
+
+        // the rest of the block has been given back
+        assert_eq!(drain(&mut allocator).len(), 0x3ff - 3);
+    }
+
+    #[test]
+    fn contiguous_allocations_are_limited_to_max_order() {
+        let mut allocator = allocator(0, 4 << MAX_ORDER);
+
+        assert!(allocator
+            .alloc_contiguous((1 << MAX_ORDER) + 1, 1, None)
+            .is_none());
+        assert!(allocator
+            .alloc_contiguous(1 << MAX_ORDER, 1, None)
+            .is_some());
+        assert!(allocator.alloc_contiguous(0, 1, None).is_none());
+    }
+
+    #[test]
+    fn contiguous_allocations_respect_the_limit() {
+        let mut allocator = allocator(0, 0x40);
+
+        // the lowest blocks are in use, so only the upper half is free
+        allocator.alloc_contiguous(0x20, 1, None).unwrap();
+
+        assert!(allocator
+            .alloc_contiguous(1, 1, Some(frame(0x20)))
+            .is_none());
+
+        let range = allocator.alloc_contiguous(4, 1, Some(frame(0x24))).unwrap();
+        assert_eq!(range, PhysicalRange::with_size(frame(0x20), 4));
+    }
+
+    #[test]
+    fn allocates_specific_frames() {
+        let mut allocator = allocator(0, 0x10);
+
+        assert_eq!(allocator.alloc_specific(frame(0x7)), Some(()));
+        assert_eq!(allocator.alloc_specific(frame(0x7)), None);
+        assert_eq!(allocator.alloc_specific(frame(0x10)), None);
+
+        let frames = drain(&mut allocator);
+        assert_eq!(frames.len(), 0xf);
+        assert!(!frames.contains(&frame(0x7)));
+    }
+}
@@ -1,6 +1,6 @@
 use alloc::boxed::Box;
 use alloc::vec::Vec;
-use memory::phys::{Frame, PageFrameAllocator, PhysicalRange};
+use memory::phys::{AddressLimit, Frame, Inner, PageFrameAllocator, PhysicalRange};
 
 #[derive(Copy, Clone)]
 pub struct FixedFrameAllocator {
@@ -45,6 +45,31 @@ impl PageFrameAllocator for FixedFrameAllocator {
         Some(vec.into_boxed_slice())
     }
 
+    fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange> {
+        if self.is_allocated || num_frames != self.num_frames() {
+            return None;
+        }
+
+        let start = self.range.start().to_inner();
+
+        if !start.is_multiple_of(alignment as Inner) {
+            return None;
+        }
+
+        if limit.is_some_and(|limit| !limit.allows(self.range)) {
+            return None;
+        }
+
+        self.is_allocated = true;
+
+        Some(self.range)
+    }
+
     fn alloc_specific(&mut self, _frame: Frame) -> Option<()> {
         None
     }
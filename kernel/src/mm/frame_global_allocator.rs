@@ -1,58 +1,122 @@
-use crate::mm::frame_bump_allocator::FrameBumpAllocator;
+use crate::arch::cpu::local;
+use crate::mm::frame_magazine::MAGAZINE_BATCH;
+use crate::mm::frame_zone::FrameZone;
+use crate::mm::tlb_shootdown::MAX_CPUS;
+use crate::mm::{FrameMagazine, Zone, ZoneStats};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use boot_info::BootInfoHeader;
-use memory::phys::{Frame, PageFrameAllocator};
+use core::sync::atomic::{AtomicBool, Ordering};
+use memory::phys::{AddressLimit, Frame, PageFrameAllocator, PhysicalRange};
 use memory::MemoryMapEntryKind;
-use spin::Mutex;
+use spin::{Mutex, Once};
 
 static GLOBAL_ALLOC: AllocatorImpl = AllocatorImpl::new();
 
+/// The frame magazines of all CPUs indexed by their processor id, so that the frames cached
+/// in them can be drained from any CPU.
+static MAGAZINES: [Once<&'static Mutex<FrameMagazine>>; MAX_CPUS] =
+    [const { Once::new() }; MAX_CPUS];
+
+/// The number of ranges `ManagedRanges` can hold.
+const MAX_MANAGED_RANGES: usize = 256;
+
 struct AllocatorImpl {
-    allocators: Mutex<Vec<FrameBumpAllocator>>,
+    /// One entry per zone, indexed by `Zone as usize`.
+    zones: Mutex<Vec<FrameZone>>,
+    ranges: ManagedRanges,
+}
+
+/// The physical memory handed to the allocator. Ranges are only ever added, so they can be
+/// checked without taking the lock of the zones.
+struct ManagedRanges {
+    ranges: [Once<PhysicalRange>; MAX_MANAGED_RANGES],
+    /// Set if there were more ranges than fit into `ranges`.
+    overflow: AtomicBool,
+}
+
+impl ManagedRanges {
+    const fn new() -> Self {
+        Self {
+            ranges: [const { Once::new() }; MAX_MANAGED_RANGES],
+            overflow: AtomicBool::new(false),
+        }
+    }
+
+    /// Must only be called while holding the lock of the zones, so that there is a single writer.
+    fn add(&self, range: PhysicalRange) {
+        match self.ranges.iter().find(|slot| !slot.is_completed()) {
+            Some(slot) => {
+                slot.call_once(|| range);
+            }
+            None => self.overflow.store(true, Ordering::Release),
+        }
+    }
+
+    /// Returns whether `frame` is part of any range. After an overflow every frame is.
+    fn contains(&self, frame: Frame) -> bool {
+        self.overflow.load(Ordering::Acquire)
+            || self
+                .ranges
+                .iter()
+                .map_while(Once::get)
+                .any(|range| range.contains_frame(frame))
+    }
 }
 
 impl AllocatorImpl {
     pub const fn new() -> Self {
         AllocatorImpl {
-            allocators: Mutex::new(Vec::new()),
+            zones: Mutex::new(Vec::new()),
+            ranges: ManagedRanges::new(),
         }
     }
 
     pub fn init(&self, boot_info: &BootInfoHeader) {
-        let mut guard = self.allocators.lock();
+        let mut guard = self.zones.lock();
 
         if guard.is_empty() {
-            let vec: Vec<FrameBumpAllocator> = boot_info
-                .memory_map
-                .entries()
-                .filter(|entry| entry.kind() == MemoryMapEntryKind::Usable)
-                .map(|entry| FrameBumpAllocator::new(entry.range_truncate()))
+            let usable = || {
+                boot_info
+                    .memory_map()
+                    .entries()
+                    .filter(|entry| entry.kind() == MemoryMapEntryKind::Usable)
+                    .map(|entry| entry.range_truncate())
+            };
+
+            let vec: Vec<FrameZone> = Zone::ALL
+                .into_iter()
+                .map(|zone| FrameZone::new(zone, usable()))
                 .collect();
 
+            for range in usable() {
+                self.ranges.add(range);
+            }
+
             *guard = vec;
         } else {
             panic!("GLOBAL_ALLOC.init() called more than once");
         }
     }
 
-    pub fn alloc(&self) -> Option<Frame> {
-        let mut guard = self.allocators.lock();
+    /// Allocates a frame from the global pool. The magazines of all CPUs are drained if
+    /// the pool is exhausted.
+    pub fn alloc(&self, zone: Zone) -> Option<Frame> {
+        self.try_alloc(zone).or_else(|| {
+            self.drain_all();
+            self.try_alloc(zone)
+        })
+    }
+
+    fn try_alloc(&self, zone: Zone) -> Option<Frame> {
+        let mut guard = self.zones.lock();
 
         debug_assert!(
             !guard.is_empty(),
             "GlobalFrameAllocator.alloc() called before it was initialized"
         );
 
-        for alloc in guard.iter_mut() {
-            let frame = alloc.alloc();
-
-            if let Some(frame) = frame {
-                return Some(frame);
-            }
-        }
-
-        return None;
+        alloc_from_zones(&mut guard, zone)
     }
 
     pub fn alloc_multiple(&self, num_frames: usize) -> Option<Box<[Frame]>> {
@@ -60,7 +124,7 @@ impl AllocatorImpl {
         vec.try_reserve(num_frames).ok()?;
 
         for _ in 0..num_frames {
-            let frame = self.alloc();
+            let frame = self.alloc(Zone::Normal);
 
             match frame {
                 Some(frame) => vec.push(frame),
@@ -80,11 +144,66 @@ impl AllocatorImpl {
         Some(boxed)
     }
 
+    pub fn alloc_contiguous(
+        &self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange> {
+        self.try_alloc_contiguous(num_frames, alignment, limit)
+            .or_else(|| {
+                self.drain_all();
+                self.try_alloc_contiguous(num_frames, alignment, limit)
+            })
+    }
+
+    fn try_alloc_contiguous(
+        &self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange> {
+        let mut guard = self.zones.lock();
+
+        debug_assert!(
+            !guard.is_empty(),
+            "GlobalFrameAllocator.alloc_contiguous() called before it was initialized"
+        );
+
+        // The zone only selects where to look first, the limit itself is still
+        // enforced by the buddy allocators.
+        let zone = Zone::for_limit(limit);
+        let limit = limit.and_then(AddressLimit::end);
+
+        for fallback in zone.fallbacks() {
+            let range = guard[fallback as usize].alloc_contiguous(
+                num_frames,
+                alignment,
+                limit,
+                fallback != zone,
+            );
+
+            if let Some(range) = range {
+                return Some(range);
+            }
+        }
+
+        None
+    }
+
     pub fn alloc_specific(&self, frame: Frame) -> Option<()> {
-        let mut guard = self.allocators.lock();
-        for alloc in guard.iter_mut() {
-            if alloc.contains(frame) {
-                return alloc.alloc_specific(frame);
+        // the frame might be cached in a magazine
+        self.try_alloc_specific(frame).or_else(|| {
+            self.drain_all();
+            self.try_alloc_specific(frame)
+        })
+    }
+
+    fn try_alloc_specific(&self, frame: Frame) -> Option<()> {
+        let mut guard = self.zones.lock();
+        for zone in guard.iter_mut() {
+            if zone.contains(frame) {
+                return zone.alloc_specific(frame);
             }
         }
 
@@ -92,51 +211,229 @@ impl AllocatorImpl {
     }
 
     pub fn dealloc(&self, frame: Frame) {
-        let mut guard = self.allocators.lock();
+        let mut guard = self.zones.lock();
 
         debug_assert!(
             !guard.is_empty(),
             "GlobalFrameAllocator.dealloc() called before it was initialized"
         );
 
-        for alloc in guard.iter_mut() {
-            if alloc.contains(frame) {
-                alloc.dealloc(frame);
-                return;
+        dealloc_to_zones(&mut guard, frame);
+    }
+
+    /// Moves up to `MAGAZINE_BATCH` frames from the global pool into `magazine`.
+    ///
+    /// Only frames of the Normal zone are cached, so that the DMA zones never run out
+    /// while their frames sit in a magazine.
+    pub fn refill(&self, magazine: &mut FrameMagazine) {
+        let mut guard = self.zones.lock();
+
+        for _ in 0..MAGAZINE_BATCH {
+            match guard[Zone::Normal as usize].alloc(false) {
+                Some(frame) => magazine.push(frame).unwrap(),
+                None => break,
             }
         }
     }
 
-    pub fn contains(&self, frame: Frame) -> bool {
-        let guard = self.allocators.lock();
+    /// Moves `MAGAZINE_BATCH` frames from `magazine` back into the global pool.
+    pub fn drain(&self, magazine: &mut FrameMagazine) {
+        let mut guard = self.zones.lock();
 
-        for alloc in guard.iter() {
-            if alloc.contains(frame) {
-                return true;
+        for _ in 0..MAGAZINE_BATCH {
+            match magazine.pop() {
+                Some(frame) => dealloc_to_zones(&mut guard, frame),
+                None => break,
             }
         }
+    }
+
+    /// Moves all frames of every magazine back into the global pool.
+    ///
+    /// Note: this must not be called while holding the lock of the zones, since the owner
+    /// of a magazine takes it while holding the magazine.
+    fn drain_all(&self) {
+        for_each_magazine(|magazine| {
+            let mut guard = self.zones.lock();
+
+            while let Some(frame) = magazine.pop() {
+                dealloc_to_zones(&mut guard, frame);
+            }
+        });
+    }
 
-        return false;
+    pub fn contains(&self, frame: Frame) -> bool {
+        self.ranges.contains(frame)
+    }
+
+    pub fn add_range(&self, range: PhysicalRange) {
+        let mut guard = self.zones.lock();
+
+        debug_assert!(
+            !guard.is_empty(),
+            "GlobalFrameAllocator.add_range() called before it was initialized"
+        );
+
+        for zone in guard.iter_mut() {
+            zone.add_range(range);
+        }
+
+        self.ranges.add(range);
+    }
+
+    pub fn zone_stats(&self) -> Vec<ZoneStats> {
+        let mut cached = 0;
+        for_each_magazine(|magazine| cached += magazine.len());
+
+        let guard = self.zones.lock();
+        let mut stats: Vec<ZoneStats> = guard.iter().map(|zone| zone.stats()).collect();
+
+        // the magazines only cache frames of the Normal zone, see `refill()`
+        if let Some(normal) = stats.get_mut(Zone::Normal as usize) {
+            normal.free_frames += cached;
+        }
+
+        stats
+    }
+}
+
+fn alloc_from_zones(zones: &mut [FrameZone], zone: Zone) -> Option<Frame> {
+    for fallback in zone.fallbacks() {
+        let frame = zones[fallback as usize].alloc(fallback != zone);
+
+        if let Some(frame) = frame {
+            return Some(frame);
+        }
+    }
+
+    None
+}
+
+fn dealloc_to_zones(zones: &mut [FrameZone], frame: Frame) {
+    for zone in zones.iter_mut() {
+        if zone.contains(frame) {
+            zone.dealloc(frame);
+            return;
+        }
     }
 }
 
+/// Runs `f` with the frame magazine of the current CPU.
+/// Returns `None` if the magazine is already in use further up the call stack.
+fn with_magazine<R>(f: impl FnOnce(&mut FrameMagazine) -> R) -> Option<R> {
+    let id = local::is_initialized().then(local::proc_id)?;
+    let mut magazine = MAGAZINES[id].get()?.try_lock()?;
+    Some(f(&mut magazine))
+}
+
+/// Runs `f` with the frame magazine of every CPU.
+///
+/// The magazine of the current CPU is skipped if it is in use further up the call stack,
+/// waiting for it would never end. The magazines of other CPUs are only held briefly.
+fn for_each_magazine(mut f: impl FnMut(&mut FrameMagazine)) {
+    let current = local::is_initialized().then(local::proc_id);
+
+    for (id, magazine) in MAGAZINES.iter().enumerate() {
+        let Some(magazine) = magazine.get() else {
+            continue;
+        };
+
+        let guard = if Some(id) == current {
+            magazine.try_lock()
+        } else {
+            Some(magazine.lock())
+        };
+
+        if let Some(mut guard) = guard {
+            f(&mut guard);
+        }
+    }
+}
+
+/// Makes the frame magazine of the CPU with the given processor id known to the allocator.
+/// Must be called once per CPU before it allocates frames.
+pub fn register_frame_magazine(proc_id: usize, magazine: &'static Mutex<FrameMagazine>) {
+    MAGAZINES[proc_id].call_once(|| magazine);
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct GlobalFrameAllocator;
 
+impl GlobalFrameAllocator {
+    /// Allocates a frame from `zone` or one of the zones below it.
+    /// Use this for memory which has to be reachable by devices with limited addressing.
+    pub fn alloc_in_zone(&mut self, zone: Zone) -> Option<Frame> {
+        GLOBAL_ALLOC.alloc(zone)
+    }
+
+    /// Hands a range of physical memory that was in use during boot over to the allocator.
+    ///
+    /// # Safety
+    /// The memory in `range` must not be used anymore and must not overlap with memory
+    /// already managed by the allocator.
+    pub unsafe fn add_range(&mut self, range: PhysicalRange) {
+        GLOBAL_ALLOC.add_range(range);
+    }
+
+    /// Returns the frame accounting of every zone.
+    pub fn zone_stats(&self) -> Vec<ZoneStats> {
+        GLOBAL_ALLOC.zone_stats()
+    }
+}
+
 impl PageFrameAllocator for GlobalFrameAllocator {
     fn alloc(&mut self) -> Option<Frame> {
-        GLOBAL_ALLOC.alloc()
+        let cached = with_magazine(|magazine| {
+            if magazine.is_empty() {
+                GLOBAL_ALLOC.refill(magazine);
+            }
+
+            magazine.pop()
+        });
+
+        cached
+            .flatten()
+            .or_else(|| GLOBAL_ALLOC.alloc(Zone::Normal))
     }
 
     fn alloc_multiple(&mut self, num_frames: usize) -> Option<Box<[Frame]>> {
         GLOBAL_ALLOC.alloc_multiple(num_frames)
     }
 
+    fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange> {
+        GLOBAL_ALLOC.alloc_contiguous(num_frames, alignment, limit)
+    }
+
     fn alloc_specific(&mut self, frame: Frame) -> Option<()> {
         GLOBAL_ALLOC.alloc_specific(frame)
     }
 
     fn dealloc(&mut self, frame: Frame) {
+        debug_assert!(
+            self.contains(frame),
+            "GlobalFrameAllocator.dealloc() called with a foreign frame"
+        );
+
+        // frames of the DMA zones always go straight back, see `AllocatorImpl::refill()`
+        if Zone::of(frame) == Zone::Normal {
+            let cached = with_magazine(|magazine| {
+                if magazine.is_full() {
+                    GLOBAL_ALLOC.drain(magazine);
+                }
+
+                magazine.push(frame)
+            });
+
+            if cached.flatten().is_some() {
+                return;
+            }
+        }
+
         GLOBAL_ALLOC.dealloc(frame);
     }
 
@@ -0,0 +1,56 @@
+use memory::phys::Frame;
+
+/// The maximum number of frames a magazine can hold.
+pub const MAGAZINE_CAPACITY: usize = 64;
+
+/// The number of frames moved between a magazine and the global pool at once.
+pub const MAGAZINE_BATCH: usize = MAGAZINE_CAPACITY / 2;
+
+/// A small per-CPU cache of free frames.
+///
+/// Single frame allocations are served from the magazine of the current CPU, so the lock of the
+/// global frame allocator is only taken once per `MAGAZINE_BATCH` allocations or deallocations.
+pub struct FrameMagazine {
+    frames: [Frame; MAGAZINE_CAPACITY],
+    len: usize,
+}
+
+impl FrameMagazine {
+    pub const fn new() -> Self {
+        Self {
+            frames: [Frame::zero(); MAGAZINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == MAGAZINE_CAPACITY
+    }
+
+    pub fn push(&mut self, frame: Frame) -> Option<()> {
+        if self.is_full() {
+            return None;
+        }
+
+        self.frames[self.len] = frame;
+        self.len += 1;
+        Some(())
+    }
+
+    pub fn pop(&mut self) -> Option<Frame> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        Some(self.frames[self.len])
+    }
+}
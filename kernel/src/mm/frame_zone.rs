@@ -0,0 +1,301 @@
+use crate::mm::frame_buddy_allocator::FrameBuddyAllocator;
+use alloc::vec::Vec;
+use memory::phys::{AddressLimit, Frame, Inner, PhysicalRange};
+
+/// A zone of physical memory. Zones are ordered by address, so every zone
+/// satisfies the address constraints of all zones above it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Memory below 16 MiB, reachable by legacy ISA DMA.
+    Dma,
+    /// Memory below 4 GiB, reachable by devices with 32-bit addressing.
+    Dma32,
+    /// All remaining memory.
+    Normal,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    /// Returns the highest zone that satisfies `limit`.
+    /// A limit beyond the physical address space, i.e. 4 GiB on i686, is satisfied by all zones.
+    pub fn for_limit(limit: Option<AddressLimit>) -> Zone {
+        match limit.filter(|limit| limit.end().is_some()) {
+            Some(AddressLimit::Below16M) => Zone::Dma,
+            Some(AddressLimit::Below4G) => Zone::Dma32,
+            None => Zone::Normal,
+        }
+    }
+
+    /// Returns the zone containing `frame`.
+    pub fn of(frame: Frame) -> Zone {
+        Zone::ALL
+            .into_iter()
+            .find(|zone| match zone.end() {
+                Some(end) => frame < end,
+                None => true,
+            })
+            .unwrap()
+    }
+
+    /// Returns the first frame of this zone.
+    pub fn start(self) -> Frame {
+        match self {
+            Zone::Dma => Frame::zero(),
+            Zone::Dma32 => Zone::Dma.end().unwrap(),
+            Zone::Normal => Zone::Dma32.end().unwrap(),
+        }
+    }
+
+    /// Returns the end (exclusive) of this zone or `None` if it extends to the
+    /// end of the physical address space.
+    ///
+    /// Note: without physical addresses above 4 GiB, i.e. on i686, the DMA32 zone is empty
+    /// and the Normal zone covers all memory above 16 MiB.
+    pub fn end(self) -> Option<Frame> {
+        match self {
+            Zone::Dma => AddressLimit::Below16M.end(),
+            Zone::Dma32 => AddressLimit::Below4G.end().or(Zone::Dma.end()),
+            Zone::Normal => None,
+        }
+    }
+
+    /// Returns the part of `range` that lies inside this zone.
+    pub fn clamp(self, range: PhysicalRange) -> Option<PhysicalRange> {
+        let start = core::cmp::max(range.start(), self.start());
+        let end = match self.end() {
+            Some(end) => core::cmp::min(range.end(), end),
+            None => range.end(),
+        };
+
+        (start < end).then(|| PhysicalRange::new(start, end))
+    }
+
+    /// Returns the zones an allocation preferring this zone may use, in order of preference.
+    pub fn fallbacks(self) -> impl Iterator<Item = Zone> {
+        Zone::ALL.into_iter().rev().filter(move |zone| *zone <= self)
+    }
+
+    /// Returns how many frames of a zone with `total_frames` frames are held back from
+    /// allocations which only fell back to this zone.
+    fn reserve(self, total_frames: Inner) -> Inner {
+        match self {
+            // ISA DMA memory is scarce, only hand it out when it is explicitly asked for
+            Zone::Dma => total_frames,
+            Zone::Dma32 => total_frames / 16,
+            Zone::Normal => 0,
+        }
+    }
+}
+
+/// Per-zone frame accounting.
+#[derive(Debug, Copy, Clone)]
+pub struct ZoneStats {
+    pub zone: Zone,
+    pub total_frames: usize,
+    /// Frames cached in the per-CPU magazines are counted as free.
+    pub free_frames: usize,
+    pub reserved_frames: usize,
+}
+
+pub(super) struct FrameZone {
+    zone: Zone,
+    allocators: Vec<FrameBuddyAllocator>,
+    total_frames: Inner,
+    free_frames: Inner,
+    reserved_frames: Inner,
+}
+
+impl FrameZone {
+    pub fn new(zone: Zone, ranges: impl Iterator<Item = PhysicalRange>) -> Self {
+        let allocators: Vec<FrameBuddyAllocator> = ranges
+            .filter_map(|range| zone.clamp(range))
+            .map(FrameBuddyAllocator::new)
+            .collect();
+
+        let total_frames = allocators.iter().map(|a| a.range().num_frames()).sum();
+
+        Self {
+            zone,
+            allocators,
+            total_frames,
+            free_frames: total_frames,
+            reserved_frames: zone.reserve(total_frames),
+        }
+    }
+
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// Hands the part of `range` that lies inside this zone over to the zone.
+    /// The frames must not be managed by any allocator yet.
+    pub fn add_range(&mut self, range: PhysicalRange) {
+        if let Some(range) = self.zone.clamp(range) {
+            self.allocators.push(FrameBuddyAllocator::new(range));
+
+            self.total_frames += range.num_frames();
+            self.free_frames += range.num_frames();
+            self.reserved_frames = self.zone.reserve(self.total_frames);
+        }
+    }
+
+    /// Allocates a frame. If `fallback` is true the allocation was meant for a higher zone
+    /// and must not eat into the reserve of this zone.
+    pub fn alloc(&mut self, fallback: bool) -> Option<Frame> {
+        if !self.can_alloc(1, fallback) {
+            return None;
+        }
+
+        let frame = self.allocators.iter_mut().find_map(|alloc| alloc.alloc())?;
+        self.free_frames -= 1;
+        Some(frame)
+    }
+
+    /// Allocates physically contiguous frames which end at or before `limit`.
+    /// See `alloc()` for `fallback`.
+    pub fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<Frame>,
+        fallback: bool,
+    ) -> Option<PhysicalRange> {
+        if !self.can_alloc(num_frames as Inner, fallback) {
+            return None;
+        }
+
+        let range = self
+            .allocators
+            .iter_mut()
+            .find_map(|alloc| alloc.alloc_contiguous(num_frames, alignment, limit))?;
+
+        self.free_frames -= range.num_frames();
+        Some(range)
+    }
+
+    pub fn alloc_specific(&mut self, frame: Frame) -> Option<()> {
+        let alloc = self.allocators.iter_mut().find(|a| a.contains(frame))?;
+        alloc.alloc_specific(frame)?;
+        self.free_frames -= 1;
+        Some(())
+    }
+
+    pub fn dealloc(&mut self, frame: Frame) {
+        if let Some(alloc) = self.allocators.iter_mut().find(|a| a.contains(frame)) {
+            alloc.dealloc(frame);
+            self.free_frames += 1;
+        }
+    }
+
+    pub fn contains(&self, frame: Frame) -> bool {
+        self.allocators.iter().any(|alloc| alloc.contains(frame))
+    }
+
+    pub fn stats(&self) -> ZoneStats {
+        ZoneStats {
+            zone: self.zone,
+            total_frames: self.total_frames as usize,
+            free_frames: self.free_frames as usize,
+            reserved_frames: self.reserved_frames as usize,
+        }
+    }
+
+    fn can_alloc(&self, num_frames: Inner, fallback: bool) -> bool {
+        let reserve = if fallback { self.reserved_frames } else { 0 };
+        self.free_frames >= num_frames + reserve
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// The first frame above 16 MiB.
+    const FRAME_16M: Inner = 0x1000;
+
+    fn range(start: Inner, end: Inner) -> PhysicalRange {
+        PhysicalRange::new(Frame::from_inner(start), Frame::from_inner(end))
+    }
+
+    #[test]
+    fn fallbacks_go_down_to_lower_zones() {
+        let fallbacks: Vec<Zone> = Zone::Normal.fallbacks().collect();
+        assert_eq!(fallbacks, vec![Zone::Normal, Zone::Dma32, Zone::Dma]);
+
+        let fallbacks: Vec<Zone> = Zone::Dma.fallbacks().collect();
+        assert_eq!(fallbacks, vec![Zone::Dma]);
+    }
+
+    #[test]
+    fn zones_split_ranges_at_their_bounds() {
+        let range = range(FRAME_16M - 0x10, FRAME_16M + 0x10);
+
+        assert_eq!(
+            Zone::Dma.clamp(range),
+            Some(PhysicalRange::new(
+                range.start(),
+                Frame::from_inner(FRAME_16M)
+            ))
+        );
+        assert_eq!(Zone::of(Frame::from_inner(FRAME_16M - 1)), Zone::Dma);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn zones_on_x86_64() {
+        let frame_4g: Inner = 0x10_0000;
+
+        assert_eq!(Zone::for_limit(Some(AddressLimit::Below4G)), Zone::Dma32);
+        assert_eq!(Zone::of(Frame::from_inner(FRAME_16M)), Zone::Dma32);
+        assert_eq!(Zone::of(Frame::from_inner(frame_4g)), Zone::Normal);
+        assert_eq!(Zone::Normal.start(), Frame::from_inner(frame_4g));
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn zones_on_i686() {
+        // all memory is below 4 GiB, so the DMA32 zone is empty
+        assert_eq!(Zone::for_limit(Some(AddressLimit::Below4G)), Zone::Normal);
+        assert_eq!(Zone::of(Frame::from_inner(FRAME_16M)), Zone::Normal);
+        assert_eq!(Zone::Dma32.clamp(range(0, Inner::MAX)), None);
+    }
+
+    #[test]
+    fn fallback_allocations_keep_the_reserve() {
+        let mut zone = FrameZone::new(Zone::Dma32, [range(FRAME_16M, FRAME_16M + 160)].into_iter());
+        assert_eq!(zone.stats().reserved_frames, 10);
+
+        let fallbacks = core::iter::from_fn(|| zone.alloc(true)).count();
+        assert_eq!(fallbacks, 150);
+
+        // explicit allocations may use the reserve
+        let explicit = core::iter::from_fn(|| zone.alloc(false)).count();
+        assert_eq!(explicit, 10);
+        assert_eq!(zone.stats().free_frames, 0);
+    }
+
+    #[test]
+    fn dma_memory_is_not_used_as_fallback() {
+        let mut zone = FrameZone::new(Zone::Dma, [range(0x100, 0x200)].into_iter());
+
+        assert_eq!(zone.alloc(true), None);
+        assert!(zone.alloc_contiguous(4, 1, None, true).is_none());
+        assert!(zone.alloc(false).is_some());
+    }
+
+    #[test]
+    fn zones_only_take_their_part_of_a_range() {
+        let mut zone = FrameZone::new(Zone::Dma, core::iter::empty());
+        zone.add_range(range(FRAME_16M - 0x10, FRAME_16M + 0x10));
+
+        assert_eq!(zone.stats().total_frames, 0x10);
+        assert!(zone.contains(Frame::from_inner(FRAME_16M - 1)));
+        assert!(!zone.contains(Frame::from_inner(FRAME_16M)));
+
+        let frame = zone.alloc(false).unwrap();
+        zone.dealloc(frame);
+        assert_eq!(zone.stats().free_frames, 0x10);
+    }
+}
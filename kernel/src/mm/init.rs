@@ -1,5 +1,5 @@
 use crate::arch;
-use crate::mm::{frame_global_allocator, virtual_global_allocator};
+use crate::mm::{frame_global_allocator, stack_guard, virtual_global_allocator};
 use alloc::vec::Vec;
 use boot_info::BootInfoHeader;
 use core::iter::once;
@@ -28,10 +28,13 @@ pub enum InitPagingError {
     UnableToReadPageTable,
     UnableToMapKernelImage,
     UnableToMapEntry(MemoryMapEntryKind),
+    WritableAndExecutable(VirtualRange),
 }
 
 pub fn init(boot_info: &BootInfoHeader) {
     INIT.call_once(|| {
+        stack_guard::init(&boot_info.kernel_image_info);
+
         frame_global_allocator::init(boot_info);
 
         virtual_global_allocator::init(boot_info);
@@ -51,9 +54,24 @@ pub fn get_initial_kernel_regions(
         regions.push(region);
     }
 
+    verify_write_xor_execute(&regions)?;
+
     Ok(regions)
 }
 
+/// Ensures that no region would be mapped both writable and executable.
+fn verify_write_xor_execute(regions: &[InitialKernelRegion]) -> Result<(), InitPagingError> {
+    let wx = AccessFlags::WRITE | AccessFlags::EXEC;
+
+    match regions
+        .iter()
+        .find(|region| region.access_flags.contains(wx))
+    {
+        Some(region) => Err(InitPagingError::WritableAndExecutable(region.virt_range)),
+        None => Ok(()),
+    }
+}
+
 /// Translates a `MemoryMapEntry` to an `InitialKernelRegion`
 fn translate_memory_map_entry(
     entry: &MemoryMapEntry,
@@ -81,7 +99,7 @@ fn translate_memory_map_entry(
 ///
 /// Note that this function does not report an entry of kind `MemoryMapEntryKind::KernelImage` to
 /// need a mapping as the kernel image is handled as a special case.
-fn get_entries_to_map<'a>(map: &'a MemoryMap) -> impl Iterator<Item = &'a MemoryMapEntry> + 'a {
+fn get_entries_to_map<'a>(map: &MemoryMap<'a>) -> impl Iterator<Item = &'a MemoryMapEntry> + 'a {
     map.entries().filter(|entry| match entry.kind() {
         MemoryMapEntryKind::BootInfo => true,
         MemoryMapEntryKind::RuntimeServiceCode => true,
@@ -91,20 +109,27 @@ fn get_entries_to_map<'a>(map: &'a MemoryMap) -> impl Iterator<Item = &'a Memory
         MemoryMapEntryKind::Defective => false,
         MemoryMapEntryKind::Loader => false,
         MemoryMapEntryKind::KernelImage => false,
+        MemoryMapEntryKind::Hole => false,
+        MemoryMapEntryKind::AcpiReclaimable => false,
+        MemoryMapEntryKind::AcpiNvs => false,
+        MemoryMapEntryKind::Mmio => false,
+        MemoryMapEntryKind::PersistentMemory => false,
+        MemoryMapEntryKind::FrameBuffer => false,
     })
 }
 
 fn get_kernel_image_regions(
     kernel_image: &KernelImageInfo,
 ) -> Result<Vec<InitialKernelRegion>, InitPagingError> {
-    let stack = translate_kernel_image_region(kernel_image.stack, AccessFlags::READ_WRITE)?;
+    let stacks = get_stack_regions(kernel_image)?;
     let rodata = translate_optional_image_region(kernel_image.rodata, AccessFlags::READ)?;
     let code = translate_kernel_image_region(kernel_image.code, AccessFlags::READ_EXEC)?;
     let relro = translate_optional_image_region(kernel_image.relro, AccessFlags::READ)?;
     let data = translate_optional_image_region(kernel_image.data, AccessFlags::READ_WRITE)?;
     let heap = translate_kernel_image_region(kernel_image.heap, AccessFlags::READ_WRITE)?;
 
-    let res = once(stack)
+    let res = stacks
+        .into_iter()
         .chain(rodata)
         .chain(once(code))
         .chain(relro)
@@ -115,6 +140,17 @@ fn get_kernel_image_regions(
     Ok(res)
 }
 
+/// Returns the regions of the per-core stacks. The guard page below each stack is not mapped,
+/// so that a stack overflow faults instead of corrupting the stack of another core.
+fn get_stack_regions(
+    kernel_image: &KernelImageInfo,
+) -> Result<Vec<InitialKernelRegion>, InitPagingError> {
+    (0..kernel_image.num_stacks())
+        .filter_map(|proc_id| kernel_image.core_stack(proc_id))
+        .map(|stack| translate_kernel_image_region(stack, AccessFlags::READ_WRITE))
+        .collect()
+}
+
 fn translate_kernel_image_region(
     region: VirtualRange,
     access_flags: AccessFlags,
@@ -148,7 +184,7 @@ fn translate_access_flags(kind: MemoryMapEntryKind) -> AccessFlags {
     }
 }
 
-fn translate_phys_range(physical_range: PhysicalRange) -> Option<VirtualRange> {
+pub(super) fn translate_phys_range(physical_range: PhysicalRange) -> Option<VirtualRange> {
     let start = physical_range.start_addr().to_higher_half_checked()?;
     let end = physical_range.end_addr().to_higher_half_checked()?;
     Some(VirtualRange::new(Page::new(start), Page::new(end)))
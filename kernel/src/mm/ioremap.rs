@@ -0,0 +1,49 @@
+use crate::arch::cpu::features;
+use crate::arch::paging::{unmap_range, with_mapper};
+use crate::mm::KernelVirtualAllocator;
+use memory::paging::MemoryType;
+use memory::phys::PhysicalRange;
+use memory::virt::{VirtualRange, VirtualRangeAllocator};
+use memory::AccessFlags;
+
+/// Maps the device memory in `range` into the kernel address space with the given memory type.
+///
+/// Write-combining and write-protected fall back to uncached if the cpu has no PAT.
+/// Returns `None` if there is not enough virtual memory or the page tables could not be allocated.
+pub fn ioremap(range: PhysicalRange, memory_type: MemoryType) -> Option<VirtualRange> {
+    let memory_type = if memory_type.requires_pat() && !features::has_pat() {
+        MemoryType::Uncached
+    } else {
+        memory_type
+    };
+
+    let num_pages = usize::try_from(range.num_frames()).ok()?;
+    let virt_range = KernelVirtualAllocator.alloc(num_pages, 1)?;
+
+    let res = with_mapper(|mapper| {
+        mapper.map_range_with_type(virt_range, range, AccessFlags::READ_WRITE, memory_type)
+    });
+
+    match res {
+        Ok(()) => Some(virt_range),
+        Err(_) => {
+            KernelVirtualAllocator
+                .dealloc(virt_range)
+                .expect("unable to free virtual range");
+
+            None
+        }
+    }
+}
+
+/// Removes a mapping created by `ioremap()`.
+///
+/// # Safety
+/// `range` must have been returned by `ioremap()` and must not be accessed anymore.
+pub unsafe fn iounmap(range: VirtualRange) {
+    unsafe { unmap_range(range) };
+
+    KernelVirtualAllocator
+        .dealloc(range)
+        .expect("unable to free virtual range");
+}
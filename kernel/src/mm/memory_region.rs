@@ -0,0 +1,283 @@
+use crate::arch::paging::{unmap_range, with_mapper};
+use crate::mm::{flush_range, FaultLock, PageFaultError, PhysicalMemoryObject};
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use memory::paging::MapError;
+use memory::virt::{Page, VirtualRange};
+use memory::AccessFlags;
+use spin::{Mutex, MutexGuard};
+
+/// The memory regions of the kernel half. These are shared by all address spaces.
+static KERNEL_REGIONS: RegionSetLock = RegionSetLock::new();
+
+/// A `MemoryRegion` binds a range of virtual memory to a `PhysicalMemoryObject`.
+///
+/// Pages of the region are mapped on demand when they are first accessed.
+/// The n-th page of the range is backed by the n-th frame of the physical memory object.
+pub struct MemoryRegion {
+    range: VirtualRange,
+    access: AccessFlags,
+    pmo: PhysicalMemoryObject,
+}
+
+impl MemoryRegion {
+    /// Creates a new memory region.
+    /// Returns `None` if the range is empty or a shared `pmo` has not enough frames to back it.
+    pub fn new(
+        range: VirtualRange,
+        access: AccessFlags,
+        pmo: PhysicalMemoryObject,
+    ) -> Option<Self> {
+        if range.is_empty() {
+            return None;
+        }
+
+        if let PhysicalMemoryObject::Shared(ref shared) = pmo {
+            if shared.num_frames() < range.num_pages() {
+                return None;
+            }
+        }
+
+        Some(Self { range, access, pmo })
+    }
+
+    pub fn range(&self) -> VirtualRange {
+        self.range
+    }
+
+    pub fn access(&self) -> AccessFlags {
+        self.access
+    }
+
+    pub fn pmo(&self) -> &PhysicalMemoryObject {
+        &self.pmo
+    }
+
+    /// Resolves a page fault caused by an `access` to `page` by mapping the corresponding frame
+    /// of the physical memory object into the current address space.
+    ///
+    /// Frames which are shared copy-on-write are mapped read-only. A write to such a frame
+    /// replaces it with a private copy.
+    pub fn handle_fault(&mut self, page: Page, access: AccessFlags) -> Result<(), PageFaultError> {
+        debug_assert!(self.range.contains_page(page));
+
+        if !self.access.contains(access) {
+            return Err(PageFaultError::AccessViolation);
+        }
+
+        let index = page.diff(self.range.start());
+        let write = access.contains(AccessFlags::WRITE);
+
+        let frame = if write {
+            if self.pmo.is_cow(index) {
+                // The shared frame must not stay mapped after the copy, since the other
+                // references might free it at any time.
+                if with_mapper(|mapper| mapper.unmap(page)).is_ok() {
+                    flush_range(VirtualRange::with_size(page, 1));
+                }
+            }
+
+            self.pmo.get_or_copy_frame(index)
+        } else {
+            self.pmo.get_or_alloc_frame(index)
+        }
+        .ok_or(PageFaultError::OutOfMemory)?;
+
+        let mut map_access = self.access;
+
+        if self.pmo.is_cow(index) {
+            map_access.remove(AccessFlags::WRITE);
+        }
+
+        let res = with_mapper(|mapper| match mapper.translate(page) {
+            None => mapper.map(page, frame, map_access).map(|_| false),
+            // another core resolved the fault in the meantime or the mapping was read-only
+            Some(mapped) if mapped == frame => mapper.protect(page, map_access).map(|_| false),
+            Some(_) => {
+                mapper.unmap(page)?;
+                mapper.map(page, frame, map_access).map(|_| true)
+            }
+        });
+
+        match res {
+            Ok(replaced) => {
+                // other cores might still use the previous frame
+                if replaced {
+                    flush_range(VirtualRange::with_size(page, 1));
+                }
+
+                Ok(())
+            }
+            Err(MapError::OutOfMemory) => Err(PageFaultError::OutOfMemory),
+            Err(err) => panic!("unable to map page {:?} of memory region: {:?}", page, err),
+        }
+    }
+
+    /// Creates a copy-on-write clone of this region.
+    ///
+    /// Note: the pages of the region which are already mapped writable must be write-protected
+    /// afterwards, so that writes to shared frames fault.
+    pub fn cow_clone(&self) -> Option<Self> {
+        Some(Self {
+            range: self.range,
+            access: self.access,
+            pmo: self.pmo.cow_clone()?,
+        })
+    }
+
+    /// Checks if the mappings of this region must be write-protected after a copy-on-write clone.
+    pub fn needs_write_protect(&self) -> bool {
+        self.access.contains(AccessFlags::WRITE)
+            && matches!(self.pmo, PhysicalMemoryObject::Anon(_))
+    }
+}
+
+/// A set of non-overlapping memory regions sorted by their start address.
+pub struct MemoryRegionSet {
+    regions: Vec<MemoryRegion>,
+}
+
+impl MemoryRegionSet {
+    pub const fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+        }
+    }
+
+    /// Inserts `region` into the set.
+    /// Returns `None` if it overlaps with another region or the allocation failed.
+    pub fn insert(&mut self, region: MemoryRegion) -> Option<()> {
+        let pos = self
+            .regions
+            .partition_point(|r| r.range().start() < region.range().start());
+
+        let overlaps_prev = pos > 0 && self.regions[pos - 1].range().overlaps_with(region.range());
+        let overlaps_next =
+            pos < self.regions.len() && self.regions[pos].range().overlaps_with(region.range());
+
+        if overlaps_prev || overlaps_next {
+            return None;
+        }
+
+        self.regions.try_reserve(1).ok()?;
+        self.regions.insert(pos, region);
+        Some(())
+    }
+
+    /// Removes the region with exactly the given `range` from the set.
+    pub fn remove(&mut self, range: VirtualRange) -> Option<MemoryRegion> {
+        let pos = self
+            .regions
+            .binary_search_by_key(&range.start(), |r| r.range().start())
+            .ok()?;
+
+        if self.regions[pos].range().end() != range.end() {
+            return None;
+        }
+
+        Some(self.regions.remove(pos))
+    }
+
+    /// Returns the region containing `page`.
+    pub fn find(&self, page: Page) -> Option<&MemoryRegion> {
+        let pos = self.regions.partition_point(|r| r.range().end() <= page);
+        self.regions
+            .get(pos)
+            .filter(|r| r.range().contains_page(page))
+    }
+
+    /// Returns the region containing `page`.
+    pub fn find_mut(&mut self, page: Page) -> Option<&mut MemoryRegion> {
+        let pos = self.regions.partition_point(|r| r.range().end() <= page);
+        self.regions
+            .get_mut(pos)
+            .filter(|r| r.range().contains_page(page))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.regions.iter()
+    }
+
+    /// Creates a copy-on-write clone of all regions. See `MemoryRegion::cow_clone()`.
+    pub fn cow_clone(&self) -> Option<Self> {
+        let mut regions = Vec::new();
+        regions.try_reserve_exact(self.regions.len()).ok()?;
+
+        for region in self.regions.iter() {
+            regions.push(region.cow_clone()?);
+        }
+
+        Some(Self { regions })
+    }
+}
+
+/// The lock of a `MemoryRegionSet`. The page fault handler takes it as well, so it is a
+/// `FaultLock` while it is held.
+pub struct RegionSetLock {
+    set: Mutex<MemoryRegionSet>,
+}
+
+pub struct RegionSetGuard<'a> {
+    // Note: the fields are dropped in order, so the lock is released before the fault lock
+    guard: MutexGuard<'a, MemoryRegionSet>,
+    _fault_lock: FaultLock,
+}
+
+impl RegionSetLock {
+    pub const fn new() -> Self {
+        Self {
+            set: Mutex::new(MemoryRegionSet::new()),
+        }
+    }
+
+    pub fn lock(&self) -> RegionSetGuard<'_> {
+        let fault_lock = FaultLock::acquire();
+
+        RegionSetGuard {
+            guard: self.set.lock(),
+            _fault_lock: fault_lock,
+        }
+    }
+}
+
+impl Deref for RegionSetGuard<'_> {
+    type Target = MemoryRegionSet;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for RegionSetGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Adds `region` to the kernel half. The range of the region must have been allocated
+/// from the `KernelVirtualAllocator`. Its pages are mapped when they are first accessed.
+pub fn map_kernel_region(region: MemoryRegion) -> Option<()> {
+    KERNEL_REGIONS.lock().insert(region)
+}
+
+/// Removes the kernel region with the given `range` and unmaps all its pages.
+/// The range is not returned to the `KernelVirtualAllocator`.
+pub fn unmap_kernel_region(range: VirtualRange) -> Option<()> {
+    let region = KERNEL_REGIONS.lock().remove(range)?;
+
+    // Safety: the pages of the region are only accessed through the region
+    unsafe { unmap_range(range) };
+
+    // the frames of the region are freed after they have been unmapped
+    drop(region);
+    Some(())
+}
+
+/// Resolves a page fault in the kernel half.
+pub(super) fn handle_kernel_fault(page: Page, access: AccessFlags) -> Result<(), PageFaultError> {
+    KERNEL_REGIONS
+        .lock()
+        .find_mut(page)
+        .ok_or(PageFaultError::NoRegion)?
+        .handle_fault(page, access)
+}
@@ -1,13 +1,37 @@
-mod frame_bump_allocator;
+mod dump;
+mod frame_buddy_allocator;
 mod frame_fixed_allocator;
 mod frame_global_allocator;
+mod frame_magazine;
+mod frame_zone;
 mod init;
+mod ioremap;
+mod memory_region;
+mod page_fault;
+mod page_table_allocator;
 mod physical_memory_object;
-mod virtual_bump_allocator;
+mod reclaim;
+mod stack_guard;
+mod stats;
+mod temporary_mapping;
+mod tlb_shootdown;
 mod virtual_global_allocator;
+mod virtual_interval_allocator;
 
+pub use dump::{dump_address_space, dump_translation};
 pub use frame_fixed_allocator::FixedFrameAllocator;
-pub use frame_global_allocator::GlobalFrameAllocator;
+pub use frame_global_allocator::{register_frame_magazine, GlobalFrameAllocator};
+pub use frame_magazine::FrameMagazine;
+pub use frame_zone::{Zone, ZoneStats};
 pub use init::{get_initial_kernel_regions, init, InitPagingError, InitialKernelRegion};
+pub use ioremap::ioremap;
+pub use memory_region::{MemoryRegion, RegionSetLock};
+pub use page_fault::{handle_page_fault, FaultLock, PageFaultError};
+pub use page_table_allocator::{page_table_frames, PageTableAllocator};
 pub use physical_memory_object::*;
+pub use reclaim::{reclaim_acpi_memory, reclaim_boot_memory};
+pub use stack_guard::stack_overflow_cpu;
+pub use stats::print_meminfo;
+pub use temporary_mapping::with_temporary_mapping;
+pub use tlb_shootdown::{flush_range, handle_tlb_shootdown, join_shootdowns, CpuSet, TlbBatch};
 pub use virtual_global_allocator::KernelVirtualAllocator;
@@ -0,0 +1,84 @@
+use crate::arch::cpu::local;
+use crate::arch::paging::AddressSpace;
+use crate::mm::memory_region::handle_kernel_fault;
+use crate::mm::tlb_shootdown::MAX_CPUS;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory::virt::{Page, VirtAddr};
+use memory::{AccessFlags, KERNEL_BASE};
+
+/// The number of fault locks held by each core, see `FaultLock`.
+static FAULT_LOCKS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// The faulting address does not belong to any memory region.
+    NoRegion,
+    /// The memory region does not permit the attempted access.
+    AccessViolation,
+    /// There was not enough memory to back the faulting page.
+    OutOfMemory,
+    /// The fault occurred while the core held a lock which is needed to resolve it.
+    LockHeld,
+}
+
+/// Marks the current core as holding a lock which is also taken while resolving a page fault,
+/// i.e. the lock of a `MemoryRegionSet`, the page lock or a lock of the kernel heap.
+///
+/// Waiting for such a lock in the page fault handler would never end if the faulting core holds
+/// it, so faults are not resolved while the core holds any fault lock.
+pub struct FaultLock {
+    core: Option<usize>,
+}
+
+impl FaultLock {
+    /// Must be created before the lock is taken and dropped after it has been released.
+    pub fn acquire() -> Self {
+        let core = local::is_initialized().then(local::proc_id);
+        if let Some(id) = core {
+            FAULT_LOCKS[id].fetch_add(1, Ordering::AcqRel);
+        }
+
+        Self { core }
+    }
+}
+
+impl Drop for FaultLock {
+    fn drop(&mut self) {
+        if let Some(id) = self.core {
+            FAULT_LOCKS[id].fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Returns whether the current core holds a fault lock.
+fn holds_fault_lock() -> bool {
+    local::is_initialized() && FAULT_LOCKS[local::proc_id()].load(Ordering::Acquire) != 0
+}
+
+/// Tries to resolve a page fault caused by an `access` to `addr`.
+///
+/// Returns `Ok(())` if the faulting page has been mapped and the faulting instruction can be
+/// restarted. Otherwise the fault is a real access violation.
+pub fn handle_page_fault(addr: VirtAddr, access: AccessFlags) -> Result<(), PageFaultError> {
+    // resolving the fault takes the lock of the regions, the page lock and allocates from the
+    // kernel heap, so this core must not hold any of them
+    if holds_fault_lock() {
+        return Err(PageFaultError::LockHeld);
+    }
+
+    let page = Page::new(addr);
+
+    if addr.to_inner() >= KERNEL_BASE {
+        handle_kernel_fault(page, access)
+    } else {
+        let space = AddressSpace::current().ok_or(PageFaultError::NoRegion)?;
+
+        // Note: the lock can only be held by other cores at this point, which will release it
+        let mut regions = space.regions().lock();
+
+        regions
+            .find_mut(page)
+            .ok_or(PageFaultError::NoRegion)?
+            .handle_fault(page, access)
+    }
+}
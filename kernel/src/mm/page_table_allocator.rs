@@ -0,0 +1,59 @@
+use crate::mm::GlobalFrameAllocator;
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory::phys::{AddressLimit, Frame, PageFrameAllocator, PhysicalRange};
+
+/// The number of frames currently used for page tables.
+static PAGE_TABLE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// A frame allocator for page tables. The frames come from the `GlobalFrameAllocator`
+/// and are counted, see `page_table_frames()`.
+#[derive(Debug, Copy, Clone)]
+pub struct PageTableAllocator;
+
+impl PageFrameAllocator for PageTableAllocator {
+    fn alloc(&mut self) -> Option<Frame> {
+        let frame = GlobalFrameAllocator.alloc()?;
+        PAGE_TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
+        Some(frame)
+    }
+
+    fn alloc_multiple(&mut self, num_frames: usize) -> Option<Box<[Frame]>> {
+        let frames = GlobalFrameAllocator.alloc_multiple(num_frames)?;
+        PAGE_TABLE_FRAMES.fetch_add(frames.len(), Ordering::Relaxed);
+        Some(frames)
+    }
+
+    fn alloc_contiguous(
+        &mut self,
+        num_frames: usize,
+        alignment: usize,
+        limit: Option<AddressLimit>,
+    ) -> Option<PhysicalRange> {
+        let range = GlobalFrameAllocator.alloc_contiguous(num_frames, alignment, limit)?;
+        PAGE_TABLE_FRAMES.fetch_add(range.num_frames() as usize, Ordering::Relaxed);
+        Some(range)
+    }
+
+    fn alloc_specific(&mut self, frame: Frame) -> Option<()> {
+        GlobalFrameAllocator.alloc_specific(frame)?;
+        PAGE_TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
+        Some(())
+    }
+
+    fn dealloc(&mut self, frame: Frame) {
+        GlobalFrameAllocator.dealloc(frame);
+        PAGE_TABLE_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn contains(&self, frame: Frame) -> bool {
+        GlobalFrameAllocator.contains(frame)
+    }
+}
+
+/// Returns the number of frames which are currently used for page tables.
+///
+/// Note: the page tables set up by the loader are not included.
+pub fn page_table_frames() -> usize {
+    PAGE_TABLE_FRAMES.load(Ordering::Relaxed)
+}
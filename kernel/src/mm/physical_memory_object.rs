@@ -1,7 +1,20 @@
-use crate::mm::GlobalFrameAllocator;
+use crate::mm::{with_temporary_mapping, GlobalFrameAllocator};
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use memory::phys::{Frame, PageFrameAllocator};
+use memory::virt::VirtAddr;
+use memory::PAGE_SIZE;
+
+/// The number of frames currently held by physical memory objects.
+static PINNED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of frames which are currently held by physical memory objects.
+/// Frames shared copy-on-write are counted once.
+pub fn pinned_frames() -> usize {
+    PINNED_FRAMES.load(Ordering::Relaxed)
+}
 
 pub enum PhysicalMemoryObject<A: PageFrameAllocator + Clone = GlobalFrameAllocator> {
     Shared(Arc<SharedPhysicalMemoryObject<A>>),
@@ -28,6 +41,41 @@ impl<A: PageFrameAllocator + Clone> PhysicalMemoryObject<A> {
     pub fn new_anon_in(alloc: A) -> Self {
         Self::Anon(AnonymousPhysicalMemoryObject::new_in(alloc))
     }
+
+    /// Returns the frame backing the page at `index` of this object.
+    /// Anonymous objects allocate a zeroed frame if the page has never been accessed.
+    pub fn get_or_alloc_frame(&mut self, index: usize) -> Option<Frame> {
+        match self {
+            Self::Shared(pmo) => pmo.frames().get(index).copied(),
+            Self::Anon(pmo) => pmo.get_or_alloc_frame(index),
+        }
+    }
+
+    /// Returns the frame backing the page at `index` for a write access.
+    /// Frames of anonymous objects which are shared copy-on-write are copied first.
+    pub fn get_or_copy_frame(&mut self, index: usize) -> Option<Frame> {
+        match self {
+            Self::Shared(pmo) => pmo.frames().get(index).copied(),
+            Self::Anon(pmo) => pmo.get_or_copy_frame(index),
+        }
+    }
+
+    /// Checks if the frame backing the page at `index` is shared copy-on-write.
+    pub fn is_cow(&self, index: usize) -> bool {
+        match self {
+            Self::Shared(_) => false,
+            Self::Anon(pmo) => pmo.is_cow(index),
+        }
+    }
+
+    /// Creates a clone of this object. Shared objects are referenced by both clones,
+    /// while anonymous objects are cloned copy-on-write.
+    pub fn cow_clone(&self) -> Option<Self> {
+        match self {
+            Self::Shared(pmo) => Some(Self::Shared(pmo.clone())),
+            Self::Anon(pmo) => Some(Self::Anon(pmo.cow_clone()?)),
+        }
+    }
 }
 
 pub struct SharedPhysicalMemoryObject<A: PageFrameAllocator + Clone = GlobalFrameAllocator> {
@@ -44,6 +92,9 @@ impl SharedPhysicalMemoryObject<GlobalFrameAllocator> {
 impl<A: PageFrameAllocator + Clone> SharedPhysicalMemoryObject<A> {
     pub fn new_in(num_frames: usize, mut alloc: A) -> Option<Arc<SharedPhysicalMemoryObject<A>>> {
         let frames = alloc.alloc_multiple(num_frames)?;
+        PINNED_FRAMES.fetch_add(frames.len(), Ordering::Relaxed);
+
+        // Note: dropping `pmo` on failure releases the frames again
         let pmo = SharedPhysicalMemoryObject { frames, alloc };
         Arc::try_new(pmo).ok()
     }
@@ -63,16 +114,36 @@ impl<A: PageFrameAllocator + Clone> Drop for SharedPhysicalMemoryObject<A> {
         for frame in self.frames() {
             alloc.dealloc(*frame);
         }
+
+        PINNED_FRAMES.fetch_sub(self.frames.len(), Ordering::Relaxed);
+    }
+}
+
+/// A frame of an anonymous memory object. The frame can be shared copy-on-write between multiple
+/// anonymous memory objects and is freed when the last reference to it is dropped.
+struct AnonymousFrame<A: PageFrameAllocator + Clone> {
+    frame: Frame,
+    alloc: A,
+}
+
+impl<A: PageFrameAllocator + Clone> Drop for AnonymousFrame<A> {
+    fn drop(&mut self) {
+        self.alloc.dealloc(self.frame);
+        PINNED_FRAMES.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
+/// A physical memory object which allocates its frames lazily when they are first accessed.
 pub struct AnonymousPhysicalMemoryObject<A: PageFrameAllocator + Clone = GlobalFrameAllocator> {
+    /// The allocated frames together with their page index, sorted by the index.
+    frames: Vec<(usize, Arc<AnonymousFrame<A>>)>,
     alloc: A,
 }
 
 impl AnonymousPhysicalMemoryObject<GlobalFrameAllocator> {
     pub fn new() -> Self {
         Self {
+            frames: Vec::new(),
             alloc: GlobalFrameAllocator,
         }
     }
@@ -80,10 +151,118 @@ impl AnonymousPhysicalMemoryObject<GlobalFrameAllocator> {
 
 impl<A: PageFrameAllocator + Clone> AnonymousPhysicalMemoryObject<A> {
     pub fn new_in(alloc: A) -> Self {
-        Self { alloc }
+        Self {
+            frames: Vec::new(),
+            alloc,
+        }
     }
 
     pub fn allocator(&self) -> A {
         self.alloc.clone()
     }
+
+    /// Returns the number of frames which have been allocated so far.
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the frame backing the page at `index` if it has been allocated.
+    pub fn frame(&self, index: usize) -> Option<Frame> {
+        self.position(index)
+            .ok()
+            .map(|pos| self.frames[pos].1.frame)
+    }
+
+    /// Checks if the frame backing the page at `index` is shared with another object.
+    /// Such a frame must be mapped read-only and copied before it is written.
+    pub fn is_cow(&self, index: usize) -> bool {
+        match self.position(index) {
+            Ok(pos) => Arc::strong_count(&self.frames[pos].1) > 1,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the frame backing the page at `index`. If there is no such frame yet,
+    /// a zeroed frame is allocated.
+    pub fn get_or_alloc_frame(&mut self, index: usize) -> Option<Frame> {
+        let pos = match self.position(index) {
+            Ok(pos) => return Some(self.frames[pos].1.frame),
+            Err(pos) => pos,
+        };
+
+        self.frames.try_reserve(1).ok()?;
+
+        let frame = self.alloc_frame(|addr| {
+            unsafe { core::ptr::write_bytes(addr.as_ptr_mut::<u8>(), 0, PAGE_SIZE) };
+            Some(())
+        })?;
+
+        self.frames.insert(pos, (index, frame));
+        Some(self.frames[pos].1.frame)
+    }
+
+    /// Returns a frame backing the page at `index` which is not shared with any other object.
+    /// A shared frame is replaced by a copy, a missing frame is allocated like in `get_or_alloc_frame()`.
+    pub fn get_or_copy_frame(&mut self, index: usize) -> Option<Frame> {
+        let pos = match self.position(index) {
+            Ok(pos) => pos,
+            Err(_) => return self.get_or_alloc_frame(index),
+        };
+
+        // Note: `get_mut()` only succeeds if there are no other references,
+        // thus other objects can no longer access the frame.
+        if let Some(frame) = Arc::get_mut(&mut self.frames[pos].1) {
+            return Some(frame.frame);
+        }
+
+        let src = self.frames[pos].1.frame;
+
+        let copy = self.alloc_frame(|dst| {
+            with_temporary_mapping(src, |src| unsafe {
+                core::ptr::copy_nonoverlapping(
+                    src.as_ptr::<u8>(),
+                    dst.as_ptr_mut::<u8>(),
+                    PAGE_SIZE,
+                );
+            })
+        })?;
+
+        // this drops our reference to the shared frame
+        self.frames[pos].1 = copy;
+        Some(self.frames[pos].1.frame)
+    }
+
+    /// Creates a copy-on-write clone of this object. All frames which have been allocated so far
+    /// are shared between both objects until one of them writes to a frame.
+    pub fn cow_clone(&self) -> Option<Self> {
+        let mut frames = Vec::new();
+        frames.try_reserve_exact(self.frames.len()).ok()?;
+        frames.extend(self.frames.iter().map(|(idx, frame)| (*idx, frame.clone())));
+
+        Some(Self {
+            frames,
+            alloc: self.alloc.clone(),
+        })
+    }
+
+    fn position(&self, index: usize) -> Result<usize, usize> {
+        self.frames.binary_search_by_key(&index, |(idx, _)| *idx)
+    }
+
+    /// Allocates a new frame and initializes its content with `init`.
+    fn alloc_frame(
+        &mut self,
+        init: impl FnOnce(VirtAddr) -> Option<()>,
+    ) -> Option<Arc<AnonymousFrame<A>>> {
+        let frame = AnonymousFrame {
+            frame: self.alloc.alloc()?,
+            alloc: self.alloc.clone(),
+        };
+
+        PINNED_FRAMES.fetch_add(1, Ordering::Relaxed);
+
+        // Note: dropping `frame` on failure frees it again
+        with_temporary_mapping(frame.frame, init).flatten()?;
+        Arc::try_new(frame).ok()
+    }
 }
@@ -0,0 +1,48 @@
+use crate::arch;
+use crate::mm::init::translate_phys_range;
+use crate::mm::{GlobalFrameAllocator, KernelVirtualAllocator};
+use memory::virt::VirtualRangeAllocator;
+use memory::{MemoryMap, MemoryMapEntryKind};
+
+/// Gives the memory of all `Loader` entries back to the frame allocator. If `include_boot_info`
+/// is true, the `BootInfo` entries are unmapped and released as well.
+///
+/// # Safety
+/// - all cores must have switched to the kernel page tables
+/// - nothing may reference loader memory anymore
+/// - if `include_boot_info` is true, nothing may reference the `BootInfoHeader` or its body
+pub unsafe fn reclaim_boot_memory(map: &MemoryMap, include_boot_info: bool) {
+    for entry in map.entries() {
+        match entry.kind() {
+            MemoryMapEntryKind::Loader => {}
+            MemoryMapEntryKind::BootInfo if include_boot_info => {
+                // the boot info has been mapped into the kernel address space by mm::init()
+                let virt_range = translate_phys_range(entry.range_enclose())
+                    .expect("boot info is not mapped to the higher half");
+
+                unsafe { arch::paging::unmap_range(virt_range) };
+
+                KernelVirtualAllocator
+                    .dealloc(virt_range)
+                    .expect("unable to deallocate the boot info address space");
+            }
+            _ => continue,
+        }
+
+        unsafe { GlobalFrameAllocator.add_range(entry.range_truncate()) };
+    }
+}
+
+/// Gives the memory of all `AcpiReclaimable` entries back to the frame allocator.
+///
+/// # Safety
+/// - the ACPI tables must have been copied or parsed and nothing may reference them anymore,
+///   including the RSDP in `PlatformInfo`
+pub unsafe fn reclaim_acpi_memory(map: &MemoryMap) {
+    // AcpiReclaimable entries are not mapped by mm::init(), so there is nothing to unmap
+    for entry in map.entries() {
+        if entry.kind() == MemoryMapEntryKind::AcpiReclaimable {
+            unsafe { GlobalFrameAllocator.add_range(entry.range_truncate()) };
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use kernel_image::KernelImageInfo;
+use memory::virt::VirtAddr;
+use spin::Once;
+
+/// The layout of the per-core kernel stacks and their guard pages.
+static KERNEL_IMAGE: Once<KernelImageInfo> = Once::new();
+
+pub(super) fn init(kernel_image: &KernelImageInfo) {
+    KERNEL_IMAGE.call_once(|| kernel_image.clone());
+}
+
+/// Returns the processor id of the core whose kernel stack overflowed if `addr` lies in the
+/// guard page of one of the kernel stacks.
+pub fn stack_overflow_cpu(addr: VirtAddr) -> Option<usize> {
+    KERNEL_IMAGE.get()?.stack_guard_owner(addr)
+}
@@ -0,0 +1,94 @@
+use crate::heap::{self, HeapStats};
+use crate::mm::{page_table_frames, pinned_frames, GlobalFrameAllocator, ZoneStats};
+use alloc::format;
+use alloc::vec::Vec;
+use log::info;
+use memory::{MemoryMap, MemoryMapEntryKind, PAGE_SIZE};
+
+/// A snapshot of the physical memory usage of the kernel.
+pub struct MemoryStats {
+    /// The number of bytes of each kind in the memory map, in the order of first appearance.
+    pub kinds: Vec<(MemoryMapEntryKind, u64)>,
+    /// The frame accounting of every zone.
+    pub zones: Vec<ZoneStats>,
+    /// The number of frames used for page tables.
+    pub page_table_frames: usize,
+    /// The number of frames held by physical memory objects.
+    pub pinned_frames: usize,
+    /// The usage of the kernel heap.
+    pub heap: HeapStats,
+}
+
+impl MemoryStats {
+    pub fn total_frames(&self) -> usize {
+        self.zones.iter().map(|zone| zone.total_frames).sum()
+    }
+
+    /// Frames cached in the per-CPU magazines are counted as free.
+    pub fn free_frames(&self) -> usize {
+        self.zones.iter().map(|zone| zone.free_frames).sum()
+    }
+}
+
+/// Collects the current memory statistics. The totals per kind are taken from `map`.
+pub fn memory_stats(map: &MemoryMap) -> MemoryStats {
+    let mut kinds: Vec<(MemoryMapEntryKind, u64)> = Vec::new();
+
+    for entry in map.entries() {
+        // the sizes are only 32 bits wide on i686, but their sum may not fit
+        #[cfg(target_arch = "x86")]
+        let size = u64::from(entry.size());
+        #[cfg(target_arch = "x86_64")]
+        let size = entry.size();
+
+        match kinds.iter_mut().find(|(kind, _)| *kind == entry.kind()) {
+            Some((_, total)) => *total += size,
+            None => kinds.push((entry.kind(), size)),
+        }
+    }
+
+    MemoryStats {
+        kinds,
+        zones: GlobalFrameAllocator.zone_stats(),
+        page_table_frames: page_table_frames(),
+        pinned_frames: pinned_frames(),
+        heap: heap::stats(),
+    }
+}
+
+/// Logs a report of the current memory usage in the format of `/proc/meminfo`.
+pub fn print_meminfo(map: &MemoryMap) {
+    let stats = memory_stats(map);
+
+    let frames_kb = |frames: usize| (frames * (PAGE_SIZE / 1024)) as u64;
+    let line = |key: &str, kb: u64| info!("  {:<24}{:>12} kB", key, kb);
+
+    info!("meminfo:");
+
+    line("MemTotal:", frames_kb(stats.total_frames()));
+    line("MemFree:", frames_kb(stats.free_frames()));
+    line("PageTables:", frames_kb(stats.page_table_frames));
+    line("Pinned:", frames_kb(stats.pinned_frames));
+    line("HeapTotal:", (stats.heap.total / 1024) as u64);
+    line("HeapUsed:", (stats.heap.used / 1024) as u64);
+    line("HeapFree:", (stats.heap.free / 1024) as u64);
+
+    for zone in &stats.zones {
+        line(
+            &format!("{:?}Total:", zone.zone),
+            frames_kb(zone.total_frames),
+        );
+        line(
+            &format!("{:?}Free:", zone.zone),
+            frames_kb(zone.free_frames),
+        );
+        line(
+            &format!("{:?}Reserved:", zone.zone),
+            frames_kb(zone.reserved_frames),
+        );
+    }
+
+    for (kind, bytes) in &stats.kinds {
+        line(&format!("Map{:?}:", kind), bytes / 1024);
+    }
+}
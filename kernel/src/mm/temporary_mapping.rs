@@ -0,0 +1,33 @@
+use crate::arch::paging::{unmap_range, with_mapper};
+use crate::mm::KernelVirtualAllocator;
+use memory::phys::Frame;
+use memory::virt::{VirtAddr, VirtualRangeAllocator};
+use memory::AccessFlags;
+
+/// Maps `frame` writable into the kernel address space for the duration of `f`.
+///
+/// This is the only way to access frames which are not part of any mapping, i.e. page tables of
+/// inactive address spaces. Returns `None` if the mapping could not be created.
+pub fn with_temporary_mapping<R>(frame: Frame, f: impl FnOnce(VirtAddr) -> R) -> Option<R> {
+    let range = KernelVirtualAllocator.alloc(1, 1)?;
+    let page = range.start();
+
+    let res = match with_mapper(|mapper| mapper.map(page, frame, AccessFlags::READ_WRITE)) {
+        Ok(()) => {
+            let res = f(page.to_addr());
+
+            // the mapping might be cached by other cores as well, so it must be shot down before
+            // the page can be reused
+            // Safety: the page is not accessed after `f` has returned
+            unsafe { unmap_range(range) };
+            Some(res)
+        }
+        Err(_) => None,
+    };
+
+    KernelVirtualAllocator
+        .dealloc(range)
+        .expect("unable to free temporary mapping");
+
+    res
+}
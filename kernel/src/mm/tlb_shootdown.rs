@@ -0,0 +1,326 @@
+//! TLB shootdowns keep the TLBs of all cores consistent with the page tables.
+//!
+//! The `Mapper` only invalidates the TLB of the current core. After a mapping has been removed or
+//! restricted, the other cores which might have cached it are sent an IPI with the affected ranges
+//! and the initiating core waits until every one of them has flushed its TLB.
+//!
+//! A core which waits to initiate a shootdown with interrupts disabled handles the shootdowns
+//! sent to it while waiting, since their initiator would wait for it forever otherwise.
+
+use crate::arch::cpu::local;
+use crate::arch::interrupts::{self, apic};
+use crate::arch::paging::{flush_all_contexts, invalidate_other_contexts, AddressSpace};
+use core::sync::atomic::{AtomicU64, Ordering};
+use memory::paging::flush_tlb;
+use memory::virt::VirtualRange;
+use memory::KERNEL_BASE;
+use spin::{Mutex, RwLock};
+
+/// The largest apic id supported by `CpuSet`.
+pub const MAX_CPUS: usize = 256;
+
+/// The maximum number of ranges in a `TlbBatch`.
+const MAX_BATCH_RANGES: usize = 8;
+
+/// Batches with more pages than this flush the whole TLB instead of single pages.
+const FULL_FLUSH_THRESHOLD: usize = 64;
+
+/// The cores which receive shootdowns for the kernel half.
+static ONLINE_CORES: CpuSet = CpuSet::new();
+
+/// Only one core can initiate a shootdown at a time.
+static INITIATOR: Mutex<()> = Mutex::new(());
+
+/// The batch of the shootdown in progress. It is only written while no shootdown is in progress.
+static REQUEST: RwLock<TlbBatch> = RwLock::new(TlbBatch::new());
+
+/// The cores which have not yet acknowledged the shootdown in progress.
+static PENDING: CpuSet = CpuSet::new();
+
+/// A set of cores identified by their apic id.
+pub struct CpuSet {
+    bits: [AtomicU64; MAX_CPUS / 64],
+}
+
+impl CpuSet {
+    pub const fn new() -> Self {
+        Self {
+            bits: [const { AtomicU64::new(0) }; MAX_CPUS / 64],
+        }
+    }
+
+    pub fn insert(&self, id: usize) {
+        assert!(id < MAX_CPUS, "apic id {} is not supported", id);
+        self.bits[id / 64].fetch_or(1 << (id % 64), Ordering::AcqRel);
+    }
+
+    pub fn remove(&self, id: usize) {
+        self.bits[id / 64].fetch_and(!(1 << (id % 64)), Ordering::AcqRel);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits
+            .iter()
+            .all(|bits| bits.load(Ordering::Acquire) == 0)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        id < MAX_CPUS && self.bits[id / 64].load(Ordering::Acquire) & (1 << (id % 64)) != 0
+    }
+
+    /// Returns the ids of all cores in the set.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CPUS).filter(|id| self.contains(*id))
+    }
+}
+
+/// A list of virtual ranges whose TLB entries must be flushed together.
+///
+/// If the batch grows too large, it degrades to a flush of the whole TLB.
+#[derive(Clone)]
+pub struct TlbBatch {
+    ranges: [VirtualRange; MAX_BATCH_RANGES],
+    len: usize,
+    num_pages: usize,
+    full: bool,
+}
+
+impl TlbBatch {
+    pub const fn new() -> Self {
+        Self {
+            ranges: [VirtualRange::zero(); MAX_BATCH_RANGES],
+            len: 0,
+            num_pages: 0,
+            full: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.len == 0
+    }
+
+    pub fn add(&mut self, range: VirtualRange) {
+        if range.is_empty() || self.full {
+            return;
+        }
+
+        self.num_pages += range.num_pages();
+
+        if self.len == MAX_BATCH_RANGES || self.num_pages > FULL_FLUSH_THRESHOLD {
+            self.full = true;
+            self.len = 0;
+        } else {
+            self.ranges[self.len] = range;
+            self.len += 1;
+        }
+    }
+
+    /// Checks if this batch might contain pages of the kernel half.
+    pub fn touches_kernel(&self) -> bool {
+        self.full
+            || self.ranges[..self.len]
+                .iter()
+                .any(|range| range.end_addr().to_inner() > KERNEL_BASE)
+    }
+
+    /// Flushes the ranges of this batch from the TLB of the current core.
+    pub fn flush_local(&self) {
+        if self.full {
+            flush_all_contexts();
+        } else {
+            for range in self.ranges[..self.len].iter() {
+                for page in range.pages() {
+                    flush_tlb(page.to_addr());
+                }
+            }
+
+            if self.touches_kernel() {
+                invalidate_other_contexts();
+            }
+        }
+    }
+
+    /// Flushes the ranges of this batch from the TLBs of all cores in `targets` except the current
+    /// one and waits until they are done. The current core must have been flushed by the caller.
+    pub fn flush(&self, targets: &CpuSet) {
+        if self.is_empty() {
+            return;
+        }
+
+        // the caller has only flushed the address space which is active on the current core
+        if self.touches_kernel() {
+            invalidate_other_contexts();
+        }
+
+        let current = current_id();
+        let mut remote = targets.iter().filter(|id| *id != current).peekable();
+
+        // nothing to do on single-core systems or if no other core uses the address space
+        if remote.peek().is_none() {
+            return;
+        }
+
+        let _guard = loop {
+            if let Some(guard) = INITIATOR.try_lock() {
+                break guard;
+            }
+
+            // the shootdown IPI cannot be delivered while interrupts are disabled
+            if !interrupts::are_enabled() {
+                service_shootdown(current);
+            }
+
+            core::hint::spin_loop();
+        };
+
+        *REQUEST.write() = self.clone();
+
+        for id in remote {
+            PENDING.insert(id);
+            apic::send_ipi(id, apic::TLB_SHOOTDOWN_VECTOR);
+        }
+
+        // no other core can initiate a shootdown in the meantime, so none is sent to this one
+        while !PENDING.is_empty() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Registers the current core as a target of shootdowns for the kernel half.
+/// The local apic must have been initialized.
+pub fn join_shootdowns() {
+    ONLINE_CORES.insert(current_id());
+
+    // a shootdown might have missed this core while it was joining
+    flush_all_contexts();
+}
+
+/// Returns the cores which receive shootdowns for the kernel half.
+pub fn online_cores() -> &'static CpuSet {
+    &ONLINE_CORES
+}
+
+/// Flushes `range` from the TLBs of all cores which might have cached it, after its mappings in
+/// the current address space have been changed. Kernel ranges are flushed on all cores, user
+/// ranges only on those with the current address space active.
+pub fn flush_range(range: VirtualRange) {
+    let mut batch = TlbBatch::new();
+    batch.add(range);
+
+    if range.start_addr().to_inner() >= KERNEL_BASE {
+        batch.flush(online_cores());
+    } else if let Some(space) = AddressSpace::current() {
+        space.flush_tlb(&batch);
+    }
+}
+
+/// Returns the apic id of the current core.
+fn current_id() -> usize {
+    local::proc_id()
+}
+
+/// Handles the shootdown IPI on a target core.
+pub fn handle_tlb_shootdown() {
+    service_shootdown(current_id());
+}
+
+/// Flushes the TLB of the current core if the shootdown in progress has not been handled by it yet.
+///
+/// Note: the IPI of a shootdown which was handled while waiting arrives later and does nothing.
+fn service_shootdown(current: usize) {
+    if PENDING.contains(current) {
+        REQUEST.read().flush_local();
+        PENDING.remove(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::virt::{Page, VirtAddr};
+    use memory::PAGE_SIZE;
+
+    fn range(addr: usize, num_pages: usize) -> VirtualRange {
+        VirtualRange::with_size(Page::new(VirtAddr::new(addr)), num_pages)
+    }
+
+    #[test]
+    fn empty_ranges_are_ignored() {
+        let mut batch = TlbBatch::new();
+        batch.add(range(0x1000, 0));
+
+        assert!(batch.is_empty());
+        assert!(!batch.touches_kernel());
+    }
+
+    #[test]
+    fn small_batches_keep_their_ranges() {
+        let mut batch = TlbBatch::new();
+
+        for idx in 0..MAX_BATCH_RANGES {
+            batch.add(range(idx * 0x10 * PAGE_SIZE, 1));
+        }
+
+        assert!(!batch.is_empty());
+        assert!(!batch.full);
+        assert_eq!(batch.len, MAX_BATCH_RANGES);
+        assert!(!batch.touches_kernel());
+    }
+
+    #[test]
+    fn too_many_ranges_degrade_to_a_full_flush() {
+        let mut batch = TlbBatch::new();
+
+        for idx in 0..=MAX_BATCH_RANGES {
+            batch.add(range(idx * 0x10 * PAGE_SIZE, 1));
+        }
+
+        assert!(batch.full);
+        assert!(!batch.is_empty());
+        assert!(batch.touches_kernel());
+    }
+
+    #[test]
+    fn too_many_pages_degrade_to_a_full_flush() {
+        let mut batch = TlbBatch::new();
+
+        batch.add(range(0, FULL_FLUSH_THRESHOLD));
+        assert!(!batch.full);
+
+        batch.add(range(FULL_FLUSH_THRESHOLD * PAGE_SIZE, 1));
+        assert!(batch.full);
+
+        // a full batch stays full
+        batch.add(range(0, 1));
+        assert!(batch.full);
+        assert_eq!(batch.len, 0);
+    }
+
+    #[test]
+    fn kernel_ranges_are_detected() {
+        let mut batch = TlbBatch::new();
+
+        batch.add(range(0x1000, 1));
+        assert!(!batch.touches_kernel());
+
+        batch.add(range(KERNEL_BASE, 1));
+        assert!(batch.touches_kernel());
+    }
+
+    #[test]
+    fn cpu_sets_track_their_members() {
+        let set = CpuSet::new();
+        assert!(set.is_empty());
+
+        set.insert(3);
+        set.insert(130);
+        assert!(set.contains(130));
+        assert!(!set.contains(4));
+        assert_eq!(set.iter().collect::<alloc::vec::Vec<_>>(), [3, 130]);
+
+        set.remove(3);
+        set.remove(130);
+        assert!(set.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::mm::virtual_bump_allocator::VirtualBumpAllocator;
-use crate::mm::{get_initial_kernel_regions, InitialKernelRegion};
+use crate::arch;
+use crate::mm::get_initial_kernel_regions;
+use crate::mm::virtual_interval_allocator::VirtualIntervalAllocator;
 use alloc::vec::Vec;
 use boot_info::BootInfoHeader;
 use memory::virt::{Page, VirtAddr, VirtualRange, VirtualRangeAllocator};
@@ -9,18 +10,21 @@ use spin::Mutex;
 static GLOBAL_ALLOC: AllocatorImpl = AllocatorImpl::new();
 
 struct AllocatorImpl {
-    inner: Mutex<VirtualBumpAllocator>,
+    inner: Mutex<VirtualIntervalAllocator>,
 }
 
 impl AllocatorImpl {
     pub const fn new() -> Self {
         Self {
-            inner: Mutex::new(VirtualBumpAllocator::new(kernel_virtual_range())),
+            inner: Mutex::new(VirtualIntervalAllocator::new(kernel_virtual_range())),
         }
     }
 
     pub fn init(&self, _boot_info: &BootInfoHeader) {
-        // nothing to do for now
+        let scratch = VirtualRange::with_size(arch::paging::SCRATCH_PAGE, 1);
+
+        self.alloc_specific(scratch)
+            .expect("unable to reserve the scratch page");
     }
 
     pub fn alloc(&self, num_pages: usize, alignment: usize) -> Option<VirtualRange> {
@@ -34,11 +38,22 @@ impl AllocatorImpl {
     pub fn dealloc(&self, range: VirtualRange) -> Option<()> {
         self.inner.lock().dealloc(range)
     }
+
+    pub fn allocated_ranges(&self) -> Vec<VirtualRange> {
+        self.inner.lock().allocated_ranges().collect()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct KernelVirtualAllocator;
 
+impl KernelVirtualAllocator {
+    /// Returns all allocated ranges of the kernel address space in ascending order.
+    pub fn allocated_ranges(&self) -> Vec<VirtualRange> {
+        GLOBAL_ALLOC.allocated_ranges()
+    }
+}
+
 impl VirtualRangeAllocator for KernelVirtualAllocator {
     fn range(&self) -> VirtualRange {
         kernel_virtual_range()
@@ -66,46 +81,13 @@ const fn kernel_virtual_range() -> VirtualRange {
 pub(super) fn init(boot_info: &BootInfoHeader) {
     GLOBAL_ALLOC.init(boot_info);
 
-    let regions = get_initial_kernel_regions(&boot_info.memory_map, &boot_info.kernel_image_info)
+    let regions = get_initial_kernel_regions(&boot_info.memory_map(), &boot_info.kernel_image_info)
         .expect("unable to obtain initial kernel regions");
 
-    let merged = merge_initial_kernel_regions(regions);
-
-    for region in merged {
+    // every region is allocated on its own, so that e.g. the boot info can be released later
+    for region in regions {
         KernelVirtualAllocator
-            .alloc_specific(region)
+            .alloc_specific(region.virt_range)
             .expect("unable to allocate virtual address space for initial kernel regions")
     }
 }
-
-fn merge_initial_kernel_regions(mut regions: Vec<InitialKernelRegion>) -> Vec<VirtualRange> {
-    assert!(!regions.is_empty());
-    regions.sort_unstable_by_key(|region| region.virt_range.start_addr().to_inner());
-
-    let mut res = Vec::new();
-    res.push(regions[0].virt_range);
-
-    for region in regions.iter().skip(1) {
-        let last_idx = res.len() - 1;
-
-        let last = res[last_idx];
-        let current = region.virt_range;
-
-        if can_merge(last, current) {
-            let merged = merge(last, current);
-            res[last_idx] = merged;
-        } else {
-            res.push(region.virt_range);
-        }
-    }
-
-    res
-}
-
-fn merge(left: VirtualRange, right: VirtualRange) -> VirtualRange {
-    left.union_with(right)
-}
-
-fn can_merge(left: VirtualRange, right: VirtualRange) -> bool {
-    left.end() == right.start()
-}
@@ -0,0 +1,195 @@
+use alloc::vec::Vec;
+use memory::virt::{Page, VirtualRange};
+
+/// Manages a range of virtual memory by keeping a sorted list of the allocated intervals.
+///
+/// Every allocation is kept as its own interval and must be released as a whole. The free space
+/// is derived from the gaps between the intervals, so free ranges are always coalesced.
+pub struct VirtualIntervalAllocator {
+    range: VirtualRange,
+    /// Sorted and non-overlapping allocated ranges, one per allocation.
+    allocated: Vec<VirtualRange>,
+}
+
+impl VirtualIntervalAllocator {
+    pub const fn new(range: VirtualRange) -> Self {
+        Self {
+            range,
+            allocated: Vec::new(),
+        }
+    }
+
+    pub fn range(&self) -> VirtualRange {
+        self.range
+    }
+
+    /// Returns an iterator over the allocated ranges in ascending order.
+    pub fn allocated_ranges(&self) -> impl Iterator<Item = VirtualRange> + '_ {
+        self.allocated.iter().copied()
+    }
+
+    /// Returns an iterator over the free ranges in ascending order.
+    pub fn free_ranges(&self) -> impl Iterator<Item = VirtualRange> + '_ {
+        let starts =
+            core::iter::once(self.range.start()).chain(self.allocated.iter().map(|r| r.end()));
+        let ends = self
+            .allocated
+            .iter()
+            .map(|r| r.start())
+            .chain(core::iter::once(self.range.end()));
+
+        starts
+            .zip(ends)
+            .map(|(start, end)| VirtualRange::new(start, end))
+            .filter(|range| !range.is_empty())
+    }
+
+    pub fn alloc(&mut self, num_pages: usize, align: usize) -> Option<VirtualRange> {
+        debug_assert!(align.is_power_of_two());
+
+        let rng = self.free_ranges().find_map(|free| {
+            let start = free.start().to_inner().checked_next_multiple_of(align)?;
+            let end = start.checked_add(num_pages)?;
+            let rng = VirtualRange::new(Page::from_inner(start), Page::from_inner(end));
+
+            free.contains_range(rng).then_some(rng)
+        })?;
+
+        self.insert(rng)?;
+        Some(rng)
+    }
+
+    pub fn alloc_specific(&mut self, range_to_allocate: VirtualRange) -> Option<()> {
+        if !self.range.contains_range(range_to_allocate) || self.is_allocated(range_to_allocate) {
+            return None;
+        }
+
+        self.insert(range_to_allocate)
+    }
+
+    /// Releases `range` which must have been returned by a single allocation.
+    /// Returns `None` if `range` does not match an allocation exactly.
+    pub fn dealloc(&mut self, range: VirtualRange) -> Option<()> {
+        if range.is_empty() {
+            return Some(());
+        }
+
+        let idx = self
+            .allocated
+            .binary_search_by_key(&range.start(), |allocated| allocated.start())
+            .ok()?;
+
+        if self.allocated[idx] != range {
+            return None;
+        }
+
+        self.allocated.remove(idx);
+        Some(())
+    }
+
+    /// Checks if any part of `range` is allocated.
+    fn is_allocated(&self, range: VirtualRange) -> bool {
+        !range.is_empty()
+            && self
+                .allocated
+                .iter()
+                .any(|allocated| allocated.start() < range.end() && range.start() < allocated.end())
+    }
+
+    /// Marks the free `range` as allocated.
+    fn insert(&mut self, range: VirtualRange) -> Option<()> {
+        if range.is_empty() {
+            return Some(());
+        }
+
+        let idx = self
+            .allocated
+            .partition_point(|allocated| allocated.start() < range.start());
+
+        self.allocated.try_reserve(1).ok()?;
+        self.allocated.insert(idx, range);
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: usize, end: usize) -> VirtualRange {
+        VirtualRange::new(Page::from_inner(start), Page::from_inner(end))
+    }
+
+    fn allocator() -> VirtualIntervalAllocator {
+        VirtualIntervalAllocator::new(range(0x100, 0x200))
+    }
+
+    #[test]
+    fn allocates_from_the_lowest_free_range() {
+        let mut allocator = allocator();
+
+        assert_eq!(allocator.alloc(0x10, 1), Some(range(0x100, 0x110)));
+        assert_eq!(allocator.alloc(0x10, 1), Some(range(0x110, 0x120)));
+        assert_eq!(allocator.alloc(0x8, 0x40), Some(range(0x140, 0x148)));
+        assert_eq!(allocator.alloc(0x100, 1), None);
+
+        let free: Vec<_> = allocator.free_ranges().collect();
+        assert_eq!(free, [range(0x120, 0x140), range(0x148, 0x200)]);
+    }
+
+    #[test]
+    fn adjacent_allocations_are_not_merged() {
+        let mut allocator = allocator();
+
+        let first = allocator.alloc(0x10, 1).unwrap();
+        let second = allocator.alloc(0x10, 1).unwrap();
+
+        let allocated: Vec<_> = allocator.allocated_ranges().collect();
+        assert_eq!(allocated, [first, second]);
+    }
+
+    #[test]
+    fn dealloc_requires_an_exact_match() {
+        let mut allocator = allocator();
+
+        let first = allocator.alloc(0x10, 1).unwrap();
+        let second = allocator.alloc(0x10, 1).unwrap();
+
+        assert_eq!(allocator.dealloc(first.union_with(second)), None);
+        assert_eq!(allocator.dealloc(range(0x100, 0x108)), None);
+        assert_eq!(allocator.dealloc(range(0x108, 0x110)), None);
+
+        assert_eq!(allocator.dealloc(first), Some(()));
+        assert_eq!(allocator.dealloc(first), None);
+        assert_eq!(allocator.dealloc(second), Some(()));
+
+        assert_eq!(allocator.allocated_ranges().count(), 0);
+        let free: Vec<_> = allocator.free_ranges().collect();
+        assert_eq!(free, [range(0x100, 0x200)]);
+    }
+
+    #[test]
+    fn freed_ranges_are_reused() {
+        let mut allocator = allocator();
+
+        let first = allocator.alloc(0x10, 1).unwrap();
+        allocator.alloc(0x10, 1).unwrap();
+
+        allocator.dealloc(first).unwrap();
+        assert_eq!(allocator.alloc(0x8, 1), Some(range(0x100, 0x108)));
+        assert_eq!(allocator.alloc(0x10, 1), Some(range(0x120, 0x130)));
+    }
+
+    #[test]
+    fn alloc_specific_rejects_overlaps() {
+        let mut allocator = allocator();
+
+        assert_eq!(allocator.alloc_specific(range(0x180, 0x190)), Some(()));
+        assert_eq!(allocator.alloc_specific(range(0x18f, 0x1a0)), None);
+        assert_eq!(allocator.alloc_specific(range(0x1f0, 0x210)), None);
+        assert_eq!(allocator.alloc_specific(range(0x190, 0x1a0)), Some(()));
+
+        let allocated: Vec<_> = allocator.allocated_ranges().collect();
+        assert_eq!(allocated, [range(0x180, 0x190), range(0x190, 0x1a0)]);
+    }
+}
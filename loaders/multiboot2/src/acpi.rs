@@ -6,15 +6,13 @@ use crate::multiboot2::{RSDPDescriptor, RSDPDescriptorV1, RSDPDescriptorV2};
 use acpi::AcpiTables;
 use kernel_image::KernelImage;
 use log::info;
-use memory::phys::PhysAddr;
 use multi_core::handler::IdentityMappedAcpiHandler;
 
 pub fn startup_all_application_processors(
     acpi_tables: &AcpiTables<IdentityMappedAcpiHandler>,
     kernel_image: &KernelImage,
 ) {
-    // Defined in boot.s
-    let page_table_addr = PhysAddr::new(0x1000);
+    let page_table_addr = paging::root_table_addr();
 
     multi_core::ap_startup::startup_all_application_processors(
         acpi_tables,
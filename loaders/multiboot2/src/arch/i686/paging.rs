@@ -1,6 +1,7 @@
 use core::ops::{Deref, DerefMut};
 
 use memory::paging::{Level2, Table};
+use memory::phys::PhysAddr;
 use spin::{Mutex, MutexGuard};
 
 /// This is a pointer to the recursive mapped page directory.
@@ -40,6 +41,12 @@ fn get_page_directory() -> PageDirectoryGuard<'static> {
     PageDirectoryGuard { table, guard }
 }
 
+/// Returns the physical address of the page directory which is set up in boot.s.
+pub fn root_table_addr() -> PhysAddr {
+    // keep in sync with boot.s
+    PhysAddr::new(0x1000)
+}
+
 pub fn init_ap() {
     unsafe {
         enable_paging();
@@ -4,6 +4,10 @@ PML4T_ADDR = 0x1000
 PDPT_ADDR = 0x2000
 PDT_START_ADDR = 0x3000
 PDT_END_ADDR = 0x7000
+PML5T_ADDR = 0x7000
+// Note: the trampoline of the application processors is installed at 0x8000 later on
+LOW_PML4T_ADDR = 0x9000
+PAGE_TABLES_END_ADDR = 0xA000
 
 // various flag bits for the paging entries
 // Note: ENTRY_USAGE_*_BITS are not used by the hardware, but by EntryUsage enum in rust
@@ -129,24 +133,44 @@ start:
     test $0x20000000, %edx              // check bit 29 (LM-bit)
     jz long_mode_error                  // if it is not set we don't have long mode
 
+    // check for five-level paging, %esi is non-zero if it is supported
+    xorl %esi, %esi
+    xorl %eax, %eax                     // eax = 0 to request the highest basic function
+    cpuid
+    cmpl $7, %eax                       // function 7 contains the structured extended features
+    jb 1f
+
+    movl $7, %eax
+    xorl %ecx, %ecx                     // sub-function 0
+    cpuid
+    andl $0x10000, %ecx                 // check bit 16 (LA57-bit)
+    movl %ecx, %esi
+1:
+
     /* ------------------------------------------------------------------------ *
      *  Identity map the memory range and enable paging.                        *
      *                                                                          *
      *  The first 4GiB of the ram will be identity mapped using 2MiB pages.     *
      * ------------------------------------------------------------------------ */ 
 
-    // clear the memory from 0 to PDT_END_ADDR
+    // clear the memory from 0 to PAGE_TABLES_END_ADDR
 
     xorl %eax, %eax                                 // zero out eax
     xorl %edi, %edi                                 // start at address 0
 
-    movl $PDT_END_ADDR, %ecx                        // get the number of bytes to clear
+    movl $PAGE_TABLES_END_ADDR, %ecx                // get the number of bytes to clear
     shr $2, %ecx                                    // divide by 4 because we do 4 bytes at a time
 
     rep stosl
  
-    // the first PML4T entry points to the PDPT
-    movl $(PDPT_ADDR | TABLE_ENTRY_BITS), PML4T_ADDR
+    // the first PML4T entry of the lower half points to the PDPT
+    // Note: with five-level paging the lower half has a PML4T of its own
+    movl $PML4T_ADDR, %edi
+    testl %esi, %esi
+    jz 1f
+    movl $LOW_PML4T_ADDR, %edi
+1:
+    movl $(PDPT_ADDR | TABLE_ENTRY_BITS), (%edi)
 
     // the last PML4T entry points to itself, this enables "recursive mapping"
     movl $(PML4T_ADDR | TABLE_ENTRY_BITS), (PML4T_ADDR + 8 * 511)
@@ -176,13 +200,30 @@ start:
     cmpl %ecx, %edi
     jb 1b
 
-    // tell the cpu where to find our PML4T by setting the cr3 register
-    movl $PML4T_ADDR, %eax
-    movl %eax, %cr3
-
     // enable PAE which is required for long mode
     movl %cr4, %eax
     or $(1 << 5), %eax                  // set bit 5 which is the PAE-bit
+
+    testl %esi, %esi
+    jnz 1f
+
+    // tell the cpu where to find our PML4T by setting the cr3 register
+    movl $PML4T_ADDR, %ecx
+    movl %ecx, %cr3
+    jmp 2f
+1:
+    // with five-level paging the first PML5T entry points to the PML4T of the lower half and
+    // the last one to the PML4T of the higher half, so that the address space looks the same
+    // as with four-level paging
+    movl $(LOW_PML4T_ADDR | TABLE_ENTRY_BITS), PML5T_ADDR
+    movl $(PML4T_ADDR | TABLE_ENTRY_BITS), (PML5T_ADDR + 8 * 511)
+
+    // tell the cpu where to find our PML5T by setting the cr3 register
+    movl $PML5T_ADDR, %ecx
+    movl %ecx, %cr3
+
+    or $(1 << 12), %eax                 // set bit 12 which is the LA57-bit
+2:
     movl %eax, %cr4
 
     // set the LM-bit in the EFER MSR
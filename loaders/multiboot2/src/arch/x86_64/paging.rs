@@ -1,7 +1,8 @@
 use core::ops::{Deref, DerefMut};
 
 use memory::{
-    paging::{Level4, Table},
+    paging::{five_level_paging, Entry, Level4, Table},
+    phys::PhysAddr,
     KERNEL_BASE,
 };
 use spin::{Mutex, MutexGuard};
@@ -42,6 +43,16 @@ pub fn get_page_map_level_four() -> PageMapLevelFourGuard<'static> {
     PageMapLevelFourGuard { table, guard }
 }
 
+/// Returns the physical address of the root page table which is set up in boot.s.
+pub fn root_table_addr() -> PhysAddr {
+    // keep in sync with boot.s
+    if five_level_paging() {
+        PhysAddr::new(0x7000)
+    } else {
+        PhysAddr::new(0x1000)
+    }
+}
+
 pub fn init_ap() {
     // Note: nothing to do here, all initialization is done during ap startup
 }
@@ -50,5 +61,8 @@ pub fn init_ap() {
 pub fn init() {
     let mut p4 = get_page_map_level_four();
     let pml4t_high_index = (KERNEL_BASE >> 39) & 0x1FF;
-    p4[pml4t_high_index] = p4[0];
+
+    // the higher half uses the PDPT of the identity mapping, which is set up in boot.s
+    // Note: with five-level paging the identity mapping is not part of this PML4T
+    p4[pml4t_high_index] = Entry::table_entry(PhysAddr::new(0x2000));
 }
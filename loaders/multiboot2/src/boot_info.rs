@@ -1,4 +1,3 @@
-use alloc::vec::Vec;
 use core::ptr::addr_of_mut;
 
 use boot_info::{
@@ -10,7 +9,7 @@ use boot_info::{
 };
 use initrd::Initrd;
 use kernel_image::KernelImageInfo;
-use memory::{virt::VirtAddr, MemoryMap, MemoryMapEntry};
+use memory::{virt::VirtAddr, MemoryMapEntry};
 
 use crate::multiboot2::{self, Multiboot2Info};
 
@@ -23,16 +22,38 @@ pub fn get_boot_info_addr() -> VirtAddr {
     VirtAddr::new(boot_info_ptr as usize).to_higher_half()
 }
 
+/// Copies `map` into the boot info body at `addr`, where room for `capacity` entries has been reserved.
+///
+/// Note: this must be called before the identity mapping is removed.
+pub fn store_memory_map(
+    addr: VirtAddr,
+    capacity: usize,
+    map: &[MemoryMapEntry],
+) -> &'static [MemoryMapEntry] {
+    assert!(
+        map.len() <= capacity,
+        "memory map has more entries than reserved in the boot info"
+    );
+
+    // Safety: the memory after the initrd is reserved for the memory map
+    unsafe {
+        let ptr = addr.as_ptr_mut::<MemoryMapEntry>();
+        core::ptr::copy_nonoverlapping(map.as_ptr(), ptr, map.len());
+        core::slice::from_raw_parts(ptr, map.len())
+    }
+}
+
 pub fn init_boot_info<'a>(
     mboot: &Multiboot2Info,
-    map: &Vec<MemoryMapEntry>,
+    memory_map: &[MemoryMapEntry],
     initrd: &Initrd<'a>,
     kernel_image_info: &KernelImageInfo,
+    num_cores: usize,
 ) {
     let mut boot_info = BootInfoHeader::empty();
 
     let boot_info_start = get_boot_info_addr();
-    let boot_info_end = initrd.end_addr().to_higher_half();
+    let boot_info_end = VirtAddr::new(memory_map.as_ptr_range().end as usize).to_higher_half();
 
     boot_info.boot_info_addr = boot_info_start;
     boot_info.boot_info_size = boot_info_end - boot_info_start;
@@ -43,7 +64,8 @@ pub fn init_boot_info<'a>(
     boot_info.frame_buffer_info = mboot.frame_buffer_info.clone().unwrap_or_default();
 
     boot_info.platform_info = get_platform_info(mboot);
-    boot_info.memory_map = MemoryMap::from_slice(map);
+    boot_info.memory_map_addr = VirtAddr::new(memory_map.as_ptr() as usize).to_higher_half();
+    boot_info.memory_map_len = memory_map.len();
 
     boot_logger::get(|log| {
         boot_info.boot_logger = *log;
@@ -52,6 +74,8 @@ pub fn init_boot_info<'a>(
     boot_info.initrd_addr = initrd.start_addr().to_higher_half();
     boot_info.initrd_size = initrd.size();
 
+    boot_info.num_cores = num_cores;
+
     // Safety: this function is only called in the BSP
     unsafe {
         BOOT_INFO_HEADER = boot_info;
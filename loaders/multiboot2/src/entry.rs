@@ -1,4 +1,5 @@
 use memory::virt::VirtAddr;
+use memory::PAGE_SIZE;
 use spin::Once;
 
 pub static KERNEL_ENTRY: Once<KernelEntryInfo> = Once::new();
@@ -26,7 +27,9 @@ pub fn make_jump_to_kernel(processor_id: usize, entry: KernelEntryInfo) -> ! {
     // calculate the new stack pointer to use
     // Note: don't forget that the stack grows downwards, so we need to use the end address
     // of our stack area to load into rsp and not the start
-    let stack_start_addr = entry.stacks_start + processor_id * entry.stack_size;
+    // Note: every stack is preceded by a guard page which the kernel leaves unmapped
+    let stack_start_addr =
+        entry.stacks_start + processor_id * (entry.stack_size + PAGE_SIZE) + PAGE_SIZE;
     let stack_ptr = stack_start_addr + entry.stack_size;
 
     unsafe {
@@ -16,6 +16,7 @@ use initrd::Initrd;
 use kernel_image::KernelImage;
 use log::info;
 use memory::virt::VirtAddr;
+use memory::MemoryMapEntry;
 use multiboot2::Multiboot2Info;
 
 use crate::entry::{make_jump_to_kernel, KernelEntryInfo, KERNEL_ENTRY};
@@ -91,8 +92,15 @@ pub extern "C" fn rust_entry(mboot_ptr: usize) -> ! {
     // Relocation can be disabled in order to facilitate debugging with gdb.
     // Note: this does not yet load the kernel.
 
+    // The memory map is stored in the boot info body right after the initrd.
+    let memory_map_capacity = mmap::memory_map_capacity(&mboot_info);
+    let memory_map_addr = initrd.end_addr().page_align_up();
+    let boot_info_end_addr = (memory_map_addr
+        + memory_map_capacity * core::mem::size_of::<MemoryMapEntry>())
+    .page_align_up();
+
     let kernel_image_base_addr = if kernel_cmdline.use_reloc() {
-        Some(initrd.end_addr().page_align_up())
+        Some(boot_info_end_addr)
     } else {
         None
     };
@@ -111,7 +119,7 @@ pub extern "C" fn rust_entry(mboot_ptr: usize) -> ! {
     // Create the physical memory map
     let memory_map = mmap::create_memory_map(
         &mboot_info,
-        initrd.end_addr().page_align_up().to_phys(),
+        boot_info_end_addr.to_phys(),
         kernel_image_info.end().to_phys(),
     );
 
@@ -122,6 +130,8 @@ pub extern "C" fn rust_entry(mboot_ptr: usize) -> ! {
         );
     }
 
+    let memory_map = boot_info::store_memory_map(memory_map_addr, memory_map_capacity, &memory_map);
+
     // Startup the Application Processors
     acpi::startup_all_application_processors(&acpi_tables, &kernel_image);
 
@@ -138,7 +148,13 @@ pub extern "C" fn rust_entry(mboot_ptr: usize) -> ! {
     arch::paging::init();
 
     // Initialize the boot_info header
-    boot_info::init_boot_info(&mboot_info, &memory_map, &initrd, &kernel_image_info);
+    boot_info::init_boot_info(
+        &mboot_info,
+        memory_map,
+        &initrd,
+        &kernel_image_info,
+        num_cores,
+    );
 
     // Get the entry point address from the kernel image and translate it into
     // a higher-half address.
@@ -1,12 +1,15 @@
 use alloc::vec::Vec;
-use memory::{phys::PhysAddr, MemoryMapEntry, MemoryMapEntryKind};
+use memory::{
+    max_sanitized_entries, phys::PhysAddr, sanitize_memory_map, MemoryMap, MemoryMapEntry,
+    MemoryMapEntryKind,
+};
 
 use crate::multiboot2::{MemoryRegion, Multiboot2Info};
 
 #[cfg(target_arch = "x86")]
 mod arch {
     use alloc::vec::Vec;
-    use memory::{MemoryMapEntry, MemoryMapEntryKind};
+    use memory::MemoryMapEntry;
 
     use crate::multiboot2;
 
@@ -31,13 +34,7 @@ mod arch {
     }
 
     fn convert_region_to_entry(region: multiboot2::MemoryRegion) -> MemoryMapEntry {
-        let kind = match region.region_type {
-            1 => MemoryMapEntryKind::Usable,
-            3 => MemoryMapEntryKind::Reserved, // Usable ACPI Information
-            4 => MemoryMapEntryKind::Reserved, // Reserved but preserve on hibernation
-            5 => MemoryMapEntryKind::Defective,
-            _ => MemoryMapEntryKind::Reserved,
-        };
+        let kind = super::translate_region_type(region.region_type);
 
         if region.base_addr + region.length <= core::u32::MAX as u64 {
             let start = (region.base_addr as u32).into();
@@ -61,7 +58,7 @@ mod arch {
     use alloc::vec::Vec;
 
     use crate::multiboot2;
-    use memory::{MemoryMapEntry, MemoryMapEntryKind};
+    use memory::MemoryMapEntry;
 
     pub type AddrType = u64;
 
@@ -77,13 +74,7 @@ mod arch {
     }
 
     fn convert_region_to_entry(region: multiboot2::MemoryRegion) -> MemoryMapEntry {
-        let kind = match region.region_type {
-            1 => MemoryMapEntryKind::Usable,
-            3 => MemoryMapEntryKind::Reserved, // Usable ACPI Information
-            4 => MemoryMapEntryKind::Reserved, // Reserved but preserve on hibernation
-            5 => MemoryMapEntryKind::Defective,
-            _ => MemoryMapEntryKind::Reserved,
-        };
+        let kind = super::translate_region_type(region.region_type);
 
         let start = region.base_addr.into();
         let end = start + region.length;
@@ -92,90 +83,69 @@ mod arch {
     }
 }
 
+/// Translates the type of a multiboot2 memory region.
+/// Types other than those defined by multiboot2 are passed through from the e820 memory map.
+fn translate_region_type(region_type: u32) -> MemoryMapEntryKind {
+    match region_type {
+        1 => MemoryMapEntryKind::Usable,
+        3 => MemoryMapEntryKind::AcpiReclaimable, // Usable ACPI Information
+        4 => MemoryMapEntryKind::AcpiNvs,         // Reserved but preserve on hibernation
+        5 => MemoryMapEntryKind::Defective,
+        7 => MemoryMapEntryKind::PersistentMemory, // e820 persistent memory
+        12 => MemoryMapEntryKind::PersistentMemory, // legacy e820 persistent memory
+        _ => MemoryMapEntryKind::Reserved,
+    }
+}
+
 pub struct MemoryMapAddresses {
     initrd_end_addr: PhysAddr,
     kernel_end_addr: PhysAddr,
 }
 
+/// The number of entries the loader adds to the memory map reported by multiboot2
+/// (the hardcoded entries and the frame buffer).
+const NUM_HARDCODED_ENTRIES: usize = 5;
+
+/// Returns the number of entries the boot info needs to make room for.
+pub fn memory_map_capacity(mboot: &Multiboot2Info) -> usize {
+    max_sanitized_entries(mboot.memory_regions.len() + NUM_HARDCODED_ENTRIES)
+}
+
+/// Creates the memory map.
+///
+/// - `boot_info_end_addr` is the end of the boot info body (including initrd and memory map)
+/// - `kernel_end_addr` is the end of the kernel image, which directly follows the boot info
 pub fn create_memory_map(
     mboot: &Multiboot2Info,
-    initrd_end_addr: PhysAddr,
+    boot_info_end_addr: PhysAddr,
     kernel_end_addr: PhysAddr,
 ) -> Vec<MemoryMapEntry> {
     let page_tables = get_page_tables_entry();
     let loader = get_loader_entry();
-    let boot_info = get_boot_info_entry(initrd_end_addr);
-    let kernel_image = get_kernel_image_entry(initrd_end_addr, kernel_end_addr);
+    let boot_info = get_boot_info_entry(boot_info_end_addr);
+    let kernel_image = get_kernel_image_entry(boot_info_end_addr, kernel_end_addr);
 
     verify_hardcoded_mmap_entries(page_tables, loader, boot_info, kernel_image);
 
     let hardcoded_entries = [page_tables, loader, boot_info, kernel_image];
 
     verify_memory_regions(&mboot.memory_regions);
-    let mut memory_map = arch::translate_memory_regions(&mboot.memory_regions);
+    let regions = sanitize_memory_map(&arch::translate_memory_regions(&mboot.memory_regions));
 
     for entry in hardcoded_entries {
-        let start_idx = find_mmap_entry_containing(entry.start(), &memory_map)
-            .expect("hardcoded memory map entry is not covered by any memory region");
-
-        let end_idx = find_mmap_entry_containing(entry.end(), &memory_map)
-            .expect("hardcoded memory map entry is not covered by any memory region");
-
-        if start_idx != end_idx {
-            panic!("hardcoded memory map entry spans over multiple memory regions");
-        }
-
-        match memory_map[start_idx].kind() {
-            MemoryMapEntryKind::Usable => {}
-            _ => panic!("hardcoded memory map entry is not in a usable memory region"),
-        }
-
-        let (pre, entry, post) = split_mmap_entry(memory_map[start_idx], entry);
-
-        memory_map.remove(start_idx);
-
-        if post.size() != 0 {
-            memory_map.insert(start_idx, post);
-        }
-
-        if entry.size() != 0 {
-            memory_map.insert(start_idx, entry);
-        }
-
-        if pre.size() != 0 {
-            memory_map.insert(start_idx, pre);
+        if !MemoryMap::new(&regions).is_usable(entry.range_enclose()) {
+            panic!("hardcoded memory map entry is not in a usable memory region");
         }
     }
 
-    memory_map
-}
-
-fn split_mmap_entry(
-    big: MemoryMapEntry,
-    small: MemoryMapEntry,
-) -> (MemoryMapEntry, MemoryMapEntry, MemoryMapEntry) {
-    debug_assert!(big.start() <= small.start() && big.end() >= small.end());
-
-    // Split region into three parts:
-    // 1. big.start...small.start
-    // 2. small.start...small.end
-    // 3. small.end...big.end
-
-    let pre = MemoryMapEntry::new(big.start(), small.start(), big.kind());
-    let post = MemoryMapEntry::new(small.end(), big.end(), big.kind());
+    let mut memory_map = regions;
+    memory_map.extend_from_slice(&hardcoded_entries);
 
-    (pre, small, post)
-}
-
-fn find_mmap_entry_containing(addr: PhysAddr, entries: &[MemoryMapEntry]) -> Option<usize> {
-    // for now just a linear search since the mem_regions vector is probably quite small
-    for (i, entry) in entries.iter().enumerate() {
-        if addr >= entry.start() && addr < entry.end() {
-            return Some(i);
-        }
+    if let Some(frame_buffer) = get_frame_buffer_entry(mboot) {
+        memory_map.push(frame_buffer);
     }
 
-    None
+    sanitize_memory_map(&memory_map)
 }
 
 /// This function checks that implicitly assumed properties
@@ -218,35 +188,19 @@ fn verify_hardcoded_mmap_entries(
 /// This function performs a few sanity checks on the memory regions
 /// provided by the multiboot2 loader.
 ///
-/// This includes:
-/// - There is at least one memory region
-/// - Memory regions are in ascending order
+/// Note: unordered or overlapping regions are handled by `sanitize_memory_map()`.
 fn verify_memory_regions(mem_regions: &[MemoryRegion]) {
     // TODO: maybe propagate errors to rust_entry() instead of panicking directly here?
 
     if mem_regions.is_empty() {
         panic!("no memory regions from multiboot2");
     }
-
-    let mut prev_addr = 0;
-    let mut prev_size = 0;
-
-    for region in mem_regions {
-        let base_addr = region.base_addr;
-
-        if prev_addr + prev_size > base_addr {
-            panic!("memory regions from multiboot2 are not in ascending order or overlapping");
-        }
-
-        prev_addr = base_addr;
-        prev_size = region.length;
-    }
 }
 
 fn get_page_tables_entry() -> MemoryMapEntry {
     // defined in boot.s
     let start_addr = 0x0000;
-    let end_addr = 0x7000;
+    let end_addr = 0xA000;
 
     MemoryMapEntry::new(
         PhysAddr::new(start_addr),
@@ -274,7 +228,7 @@ fn get_loader_entry() -> MemoryMapEntry {
     )
 }
 
-fn get_boot_info_entry(initrd_end_addr: PhysAddr) -> MemoryMapEntry {
+fn get_boot_info_entry(boot_info_end_addr: PhysAddr) -> MemoryMapEntry {
     // symbols defined in linkers/x86_64.ld
     extern "C" {
         pub fn __boot_info_start();
@@ -286,14 +240,27 @@ fn get_boot_info_entry(initrd_end_addr: PhysAddr) -> MemoryMapEntry {
 
     MemoryMapEntry::new(
         PhysAddr::new(boot_info_start),
-        initrd_end_addr,
+        boot_info_end_addr,
         MemoryMapEntryKind::BootInfo,
     )
 }
 
-fn get_kernel_image_entry(initrd_end_addr: PhysAddr, kernel_end_addr: PhysAddr) -> MemoryMapEntry {
+fn get_frame_buffer_entry(mboot: &Multiboot2Info) -> Option<MemoryMapEntry> {
+    let range = mboot.frame_buffer_info.as_ref()?.physical_range();
+
+    Some(MemoryMapEntry::new(
+        range.start_addr(),
+        range.end_addr(),
+        MemoryMapEntryKind::FrameBuffer,
+    ))
+}
+
+fn get_kernel_image_entry(
+    boot_info_end_addr: PhysAddr,
+    kernel_end_addr: PhysAddr,
+) -> MemoryMapEntry {
     MemoryMapEntry::new(
-        initrd_end_addr,
+        boot_info_end_addr,
         kernel_end_addr,
         MemoryMapEntryKind::KernelImage,
     )
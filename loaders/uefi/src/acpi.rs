@@ -15,26 +15,33 @@ pub fn startup_all_application_processors(
     kernel_image: &KernelImage,
 ) {
     // Safety: we have CPL=0
+    // Note: this is a PML5T if the firmware uses five-level paging
     let cr3 = unsafe { cr3() };
-    let pml4t_addr = PhysAddr::new(cr3);
+    let page_table_addr = PhysAddr::new(cr3);
 
     multi_core::ap_startup::startup_all_application_processors(
         acpi_tables,
         kernel_image,
-        pml4t_addr,
+        page_table_addr,
         busy_sleep_us,
     )
     .expect("failed to bring up the application processors")
 }
 
-pub fn get_acpi_tables(system_table: &SystemTable<Boot>) -> AcpiTables<IdentityMappedAcpiHandler> {
+/// Returns the physical address of the RSDP from the configuration table.
+pub fn find_rsdp(system_table: &SystemTable<Boot>) -> PhysAddr {
     let acpi_entry = system_table
         .config_table()
         .iter()
         .find(|entry| entry.guid == ACPI_GUID || entry.guid == ACPI2_GUID)
         .expect("unable to find acpi tables");
 
-    let rsdp_addr = VirtAddr::new(acpi_entry.address as usize);
+    PhysAddr::new(acpi_entry.address as u64)
+}
+
+pub fn get_acpi_tables(system_table: &SystemTable<Boot>) -> AcpiTables<IdentityMappedAcpiHandler> {
+    // UEFI identity maps all physical memory
+    let rsdp_addr = find_rsdp(system_table).to_virt();
 
     // Safety:
     // UEFI must provide correct pointers to the RSDP
@@ -1,5 +1,7 @@
 use memory::{
-    paging::{Entry, EntryFlags, Level2, Level3, Level4, Table, TableLevel},
+    paging::{
+        five_level_paging, Entry, EntryFlags, Level2, Level3, Level4, Level5, Table, TableLevel,
+    },
     phys::{Frame, PhysAddr, PhysicalRange},
     virt::{Page, VirtAddr},
     AccessFlags, KERNEL_BASE,
@@ -11,13 +13,21 @@ use zeroize::Zeroize;
 
 static PAGE_TABLES_MEMORY: Once<PhysicalRange> = Once::new();
 
+/// The physical address of the table which is loaded into CR3.
+static ROOT_TABLE_ADDR: Once<PhysAddr> = Once::new();
+
 pub fn prepare(boot_services: &BootServices) {
     // this function should only be called once and never concurrently
     assert!(PAGE_TABLES_MEMORY.get().is_none());
 
+    // Note: the paging mode cannot be changed while in long mode,
+    // thus we use five-level paging only if the firmware already enabled it.
+    let five_level = five_level_paging();
+
     // We need to allocate memory for six page-tables:
-    // The PLM4T, one PDPT and 4 PD's
-    let num_frames = 6;
+    // The PLM4T, one PDPT and 4 PD's plus a PML5T and a PML4T for the lower half with
+    // five-level paging
+    let num_frames = if five_level { 8 } else { 6 };
 
     let frames = boot_services.allocate_pages(
         AllocateType::AnyPages,
@@ -40,12 +50,12 @@ pub fn prepare(boot_services: &BootServices) {
     pds[2].zeroize();
     pds[3].zeroize();
 
-    // the first PML4T entry points to the PDPT
-    pml4t[0] = Entry::table_entry(table_addr(pdpt));
+    // the identity mapping and the higher half mapping both use the PDPT
+    let pdpt_entry = Entry::table_entry(table_addr(pdpt));
 
     // enable higher half mapping
     let pml4t_high_index = (KERNEL_BASE >> 39) & 0x1FF;
-    pml4t[pml4t_high_index] = pml4t[0];
+    pml4t[pml4t_high_index] = pdpt_entry;
 
     // the last PML4T entry points to itself, this enables "recursive mapping"
     let pml4t_addr = table_addr(pml4t);
@@ -77,7 +87,27 @@ pub fn prepare(boot_services: &BootServices) {
         addr += 0x200000; // 2 MiB
     }
 
+    let root_addr = if five_level {
+        // The PML5T and the PML4T of the lower half are stored after the PD's. The PML4T above
+        // only covers the higher half, so that it is not visible in the lower half as well.
+        let pml5t = unsafe { &mut *start_addr.as_ptr_mut::<Table<Level5>>().add(6) };
+        let low_pml4t = unsafe { &mut *start_addr.as_ptr_mut::<Table<Level4>>().add(7) };
+        pml5t.zeroize();
+        low_pml4t.zeroize();
+
+        // the first PML4T entry of the lower half points to the PDPT
+        low_pml4t[0] = pdpt_entry;
+
+        pml5t.set_p4s(table_addr(low_pml4t), pml4t_addr);
+        table_addr(pml5t)
+    } else {
+        // the first PML4T entry points to the PDPT
+        pml4t[0] = pdpt_entry;
+        pml4t_addr
+    };
+
     PAGE_TABLES_MEMORY.call_once(|| memory);
+    ROOT_TABLE_ADDR.call_once(|| root_addr);
 }
 
 pub fn get_kernel_page_tables_range() -> PhysicalRange {
@@ -87,8 +117,9 @@ pub fn get_kernel_page_tables_range() -> PhysicalRange {
 }
 
 pub fn activate() {
-    let range = get_kernel_page_tables_range();
-    let addr = range.start_addr();
+    let addr = ROOT_TABLE_ADDR
+        .get()
+        .expect("paging::activate() called before paging::prepare()");
     unsafe {
         cr3_write(addr.to_inner());
     }
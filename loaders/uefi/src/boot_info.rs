@@ -1,4 +1,3 @@
-use alloc::vec::Vec;
 use boot_info::platform_info::uefi::UefiInfo;
 use boot_info::platform_info::PlatformInfo;
 use boot_info::{BootInfoHeader, BOOT_INFO_STRUCT_V1};
@@ -6,19 +5,39 @@ use core::mem::MaybeUninit;
 use initrd::Initrd;
 use kernel_graphics::FrameBufferInfo;
 use kernel_image::KernelImageInfo;
+use memory::phys::PhysAddr;
 use memory::virt::VirtAddr;
-use memory::{MemoryMap, MemoryMapEntry, PAGE_SIZE};
+use memory::{max_sanitized_entries, MemoryMapEntry, PAGE_SIZE};
 use uefi::table::boot::{AllocateType, BootServices, MemoryType};
 use uefi::table::{Runtime, SystemTable};
 
 use crate::mmap::MEMORY_TYPE_BOOT_INFO;
 
+/// The number of descriptors the firmware memory map might grow by until boot services are exited.
+const EXTRA_MEMORY_DESCRIPTORS: usize = 32;
+
+/// The memory map is only known after exiting boot services, but the boot info has to be
+/// allocated before. This returns the number of entries the boot info needs to make room for.
+pub fn memory_map_capacity(boot_services: &BootServices) -> usize {
+    let size = boot_services.memory_map_size();
+    let num_descriptors = size.map_size / size.entry_size;
+
+    max_sanitized_entries(num_descriptors + EXTRA_MEMORY_DESCRIPTORS)
+}
+
 pub fn allocate_boot_info(
     boot_services: &BootServices,
     initrd_num_pages: usize,
-) -> (&'static mut MaybeUninit<BootInfoHeader>, &'static mut [u8]) {
+    memory_map_capacity: usize,
+) -> (
+    &'static mut MaybeUninit<BootInfoHeader>,
+    &'static mut [u8],
+    &'static mut [MaybeUninit<MemoryMapEntry>],
+) {
     let header_size = core::mem::size_of::<BootInfoHeader>().next_multiple_of(PAGE_SIZE);
-    let num_pages = (header_size / PAGE_SIZE) + initrd_num_pages;
+    let memory_map_size =
+        (memory_map_capacity * core::mem::size_of::<MemoryMapEntry>()).next_multiple_of(PAGE_SIZE);
+    let num_pages = (header_size / PAGE_SIZE) + initrd_num_pages + (memory_map_size / PAGE_SIZE);
 
     let base_addr: usize = boot_services
         .allocate_pages(
@@ -32,28 +51,53 @@ pub fn allocate_boot_info(
 
     let header_ptr = base_addr as *mut MaybeUninit<BootInfoHeader>;
     let buffer_ptr = (base_addr + header_size) as *mut u8;
+    let memory_map_ptr = (base_addr + header_size + initrd_num_pages * PAGE_SIZE)
+        as *mut MaybeUninit<MemoryMapEntry>;
 
     let buffer =
         unsafe { core::slice::from_raw_parts_mut(buffer_ptr, initrd_num_pages * PAGE_SIZE) };
 
+    let memory_map =
+        unsafe { core::slice::from_raw_parts_mut(memory_map_ptr, memory_map_capacity) };
+
     let header = unsafe { &mut *header_ptr };
 
-    (header, buffer)
+    (header, buffer, memory_map)
+}
+
+/// Copies `map` into the space reserved for it in the boot info.
+pub fn store_memory_map(
+    buffer: &'static mut [MaybeUninit<MemoryMapEntry>],
+    map: &[MemoryMapEntry],
+) -> &'static [MemoryMapEntry] {
+    assert!(
+        map.len() <= buffer.len(),
+        "memory map has more entries than reserved in the boot info"
+    );
+
+    for (slot, entry) in buffer.iter_mut().zip(map) {
+        slot.write(*entry);
+    }
+
+    // Safety: the first `map.len()` entries have just been initialized
+    unsafe { core::slice::from_raw_parts(buffer.as_ptr() as *const MemoryMapEntry, map.len()) }
 }
 
 pub fn init_boot_info(
     system_table: &SystemTable<Runtime>,
+    rsdp_addr: PhysAddr,
     uninit_boot_info: &mut MaybeUninit<BootInfoHeader>,
-    map: &Vec<MemoryMapEntry>,
+    memory_map: &[MemoryMapEntry],
     initrd: &Initrd,
     kernel_image_info: &KernelImageInfo,
+    num_cores: usize,
 ) {
     let mut boot_info = BootInfoHeader::empty();
 
     let boot_info_start =
         VirtAddr::new(uninit_boot_info as *const MaybeUninit<BootInfoHeader> as usize);
 
-    let boot_info_end = initrd.end_addr();
+    let boot_info_end = VirtAddr::new(memory_map.as_ptr_range().end as usize);
 
     boot_info.boot_info_addr = boot_info_start;
     boot_info.boot_info_size = boot_info_end - boot_info_start;
@@ -62,9 +106,10 @@ pub fn init_boot_info(
     boot_info.kernel_image_info = kernel_image_info.to_higher_half();
     boot_info.frame_buffer_info = FrameBufferInfo::empty();
     // panic!("here");
-    boot_info.platform_info = get_platform_info(system_table);
+    boot_info.platform_info = get_platform_info(system_table, rsdp_addr);
 
-    boot_info.memory_map = MemoryMap::from_slice(map);
+    boot_info.memory_map_addr = VirtAddr::new(memory_map.as_ptr() as usize).to_higher_half();
+    boot_info.memory_map_len = memory_map.len();
 
     boot_logger::get(|log| {
         boot_info.boot_logger = *log;
@@ -73,10 +118,12 @@ pub fn init_boot_info(
     boot_info.initrd_addr = initrd.start_addr().to_higher_half();
     boot_info.initrd_size = initrd.size();
 
+    boot_info.num_cores = num_cores;
+
     uninit_boot_info.write(boot_info);
 }
 
-fn get_platform_info(system_table: &SystemTable<Runtime>) -> PlatformInfo {
+fn get_platform_info(system_table: &SystemTable<Runtime>, rsdp_addr: PhysAddr) -> PlatformInfo {
     let addr: usize = system_table
         .get_current_system_table_addr()
         .try_into()
@@ -84,6 +131,7 @@ fn get_platform_info(system_table: &SystemTable<Runtime>) -> PlatformInfo {
 
     let info = UefiInfo {
         system_table_address: VirtAddr::new(addr),
+        rsdp_address: rsdp_addr,
     };
 
     PlatformInfo::UEFI(info)
@@ -47,6 +47,7 @@ fn main(handle: Handle, system_table: SystemTable<Boot>) -> Status {
     arch::time::init(system_table.boot_services());
 
     // Parse the ACPI tables
+    let rsdp_addr = acpi::find_rsdp(&system_table);
     let acpi_tables = acpi::get_acpi_tables(&system_table);
     let num_cores =
         multi_core::number_of_cores(&acpi_tables).expect("acpi processor info not available");
@@ -60,9 +61,13 @@ fn main(handle: Handle, system_table: SystemTable<Boot>) -> Status {
     // Find and open the initrd file
     let (mut initrd_file, initrd_pages) = bootfs.open_initrd().expect("unable to open initrd file");
 
-    // Allocate memory for the boot info and INITRD
-    let (boot_info_header, initrd_buffer) =
-        boot_info::allocate_boot_info(system_table.boot_services(), initrd_pages);
+    // Allocate memory for the boot info, INITRD and memory map
+    let memory_map_capacity = boot_info::memory_map_capacity(system_table.boot_services());
+    let (boot_info_header, initrd_buffer, memory_map_buffer) = boot_info::allocate_boot_info(
+        system_table.boot_services(),
+        initrd_pages,
+        memory_map_capacity,
+    );
 
     // Load the INITRD into memory
     bootfs
@@ -135,6 +140,7 @@ fn main(handle: Handle, system_table: SystemTable<Boot>) -> Status {
     // This function also validates the memory map to ensure that everything is still accessible
     // after enabling the higher half paging
     let memory_map = mmap::create_memory_map(&mmap).expect("failed to create memory map");
+    let memory_map = boot_info::store_memory_map(memory_map_buffer, &memory_map);
 
     // for entry in &memory_map {
     //     info!(
@@ -160,10 +166,12 @@ fn main(handle: Handle, system_table: SystemTable<Boot>) -> Status {
     // Initialize boot info
     boot_info::init_boot_info(
         &system_table,
+        rsdp_addr,
         boot_info_header,
-        &memory_map,
+        memory_map,
         &initrd,
         kernel_image_info,
+        num_cores,
     );
 
     // BootInfoHeader is now initialized
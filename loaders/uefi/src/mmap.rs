@@ -1,6 +1,6 @@
 use alloc::vec::Vec;
 use memory::phys::PhysAddr;
-use memory::{MemoryMapEntry, MemoryMapEntryKind, FRAME_SIZE};
+use memory::{sanitize_memory_map, MemoryMapEntry, MemoryMapEntryKind, FRAME_SIZE};
 use uefi::table::boot::{MemoryDescriptor, MemoryType};
 
 pub const MEMORY_TYPE_BOOT_INFO: u32 = 0x80000005;
@@ -16,29 +16,8 @@ pub enum MemoryMapError {
 pub fn create_memory_map(
     uefi_map: &uefi::table::boot::MemoryMap,
 ) -> Result<Vec<MemoryMapEntry>, MemoryMapError> {
-    let mut mmap = Vec::new();
-
-    for entry in uefi_map.entries() {
-        let translated = translate(entry);
-
-        let merged = if let Some(last) = mmap.last() {
-            if can_merge(last, &translated) {
-                Some(merge(last, &translated))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
-
-        match merged {
-            Some(merged) => {
-                let last_idx = mmap.len() - 1;
-                mmap[last_idx] = merged;
-            }
-            None => mmap.push(translated),
-        }
-    }
+    let translated: Vec<MemoryMapEntry> = uefi_map.entries().map(translate).collect();
+    let mmap = sanitize_memory_map(&translated);
 
     verify_memory_map(&mmap).map(|_| mmap)
 }
@@ -85,16 +64,6 @@ fn translate(uefi_entry: &MemoryDescriptor) -> MemoryMapEntry {
     MemoryMapEntry::new(start, start + size, kind)
 }
 
-fn can_merge(entry1: &MemoryMapEntry, entry2: &MemoryMapEntry) -> bool {
-    entry1.end() == entry2.start() && entry1.kind() == entry2.kind()
-}
-
-fn merge(entry1: &MemoryMapEntry, entry2: &MemoryMapEntry) -> MemoryMapEntry {
-    assert!(can_merge(entry1, entry2));
-
-    MemoryMapEntry::new(entry1.start(), entry2.end(), entry1.kind())
-}
-
 fn map_memory_type(uefi_type: MemoryType) -> MemoryMapEntryKind {
     match uefi_type {
         MemoryType::LOADER_CODE => MemoryMapEntryKind::Loader,
@@ -107,12 +76,12 @@ fn map_memory_type(uefi_type: MemoryType) -> MemoryMapEntryKind {
 
         MemoryType::UNUSABLE => MemoryMapEntryKind::Defective,
         MemoryType::RESERVED => MemoryMapEntryKind::Reserved,
-        MemoryType::ACPI_RECLAIM => MemoryMapEntryKind::Reserved,
-        MemoryType::ACPI_NON_VOLATILE => MemoryMapEntryKind::Reserved,
-        MemoryType::MMIO => MemoryMapEntryKind::Reserved,
-        MemoryType::MMIO_PORT_SPACE => MemoryMapEntryKind::Reserved,
+        MemoryType::ACPI_RECLAIM => MemoryMapEntryKind::AcpiReclaimable,
+        MemoryType::ACPI_NON_VOLATILE => MemoryMapEntryKind::AcpiNvs,
+        MemoryType::MMIO => MemoryMapEntryKind::Mmio,
+        MemoryType::MMIO_PORT_SPACE => MemoryMapEntryKind::Mmio,
         MemoryType::PAL_CODE => MemoryMapEntryKind::Reserved,
-        MemoryType::PERSISTENT_MEMORY => MemoryMapEntryKind::Reserved,
+        MemoryType::PERSISTENT_MEMORY => MemoryMapEntryKind::PersistentMemory,
 
         MemoryType(custom) => match custom {
             MEMORY_TYPE_BOOT_INFO => MemoryMapEntryKind::BootInfo,